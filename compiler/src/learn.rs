@@ -1,5 +1,5 @@
 use lliw::{Style::Bold, Fg::{Green, Blue, Red}, Reset};
-use psi_lang::{Compiler, Lexer, VM, Error};
+use psi_lang::{Compiler, Lexer, VM};
 use std::time::{Instant, Duration};
 use std::io::{self, Write};
 use std::thread::sleep;
@@ -26,23 +26,29 @@ macro_rules! sprintln {
     };
 }
 
-fn run(check: &str) -> Result<(), Error> {
+fn run(check: &str) -> bool {
     let valid = check.to_ascii_lowercase().replace(" ", "").replace("\n", "");
     let src = scanln!("> ");
     if src.to_ascii_lowercase().replace(" ", "").replace("\n", "") != valid {
         sprintln!("Please try typing in {}", check);
-        return Err(Error::UnexpectedEOI("Check failed".to_string()));
+        return true
     }
     let start = Instant::now();
-    let mut lexer = Lexer::new(&src);
-    lexer.run()?;
-    let mut compiler = Compiler::new(lexer.tokens);
-    compiler.compile()?;
+    let mut compiler = Compiler::new(Lexer::new(&src));
+    if let Err(error) = compiler.compile() {
+        error.display_line(&src, true);
+        println!("{}{}{}{}", Red, Bold, error, Reset);
+        return true
+    }
     let mut vm = VM::new(false);
-    vm.run(compiler.chunk)?;
+    if let Err(error) = vm.run(compiler.chunk) {
+        error.display_line(&src, true);
+        println!("{}{}{}{}", Red, Bold, error, Reset);
+        return true
+    }
     let end = Instant::now();
     println!("{}{}Success!{} Done in {}{:?}{}", Green, Bold, Reset, Blue, end - start, Reset);
-    Ok(())
+    false
 }
 
 fn run_error(check: &str) -> bool {
@@ -52,13 +58,7 @@ fn run_error(check: &str) -> bool {
         sprintln!("Please try typing in {}", check);
         return true
     }
-    let mut lexer = Lexer::new(&src);
-    if let Err(error) = lexer.run() {
-        error.display_line(&src, true);
-        println!("{}{}{}{}", Red, Bold, error, Reset);
-        return false
-    }
-    let mut compiler = Compiler::new(lexer.tokens);
+    let mut compiler = Compiler::new(Lexer::new(&src));
     if let Err(error) = compiler.compile() {
         error.display_line(&src, true);
         println!("{}{}{}{}", Red, Bold, error, Reset);
@@ -87,38 +87,38 @@ pub fn learn() {
     // Arithmetic
     sprintln!("\nLet's start off with performing some arithmetic!");
     sprintln!("Start out with a simple `1 + 1`");
-    while run("1 + 1").is_err() {  }
+    while run("1 + 1") {  }
     sprintln!("Great work! Notice the result comes back as 2, pretty simple");
     sprintln!("There are also other operators, try out the subtraction operator `4 - 2`");
-    while run("4 - 2").is_err() {  }
+    while run("4 - 2") {  }
     sprintln!("Nice! Once again, we can see a 2");
     sprintln!("There are also divide and multiply operators");
     sprintln!("We can create more complicated arithmetic by combining operations `4 * 2 / 6`");
-    while run("4 * 2 / 6").is_err() {  }
+    while run("4 * 2 / 6") {  }
     sprintln!("Brilliant! 1.333333333...");
     sprintln!("PSI adheres to the BIDMAS precedence rules.");
     sprintln!("( PEMDAS for you 'muricans :D )");
     sprintln!("You can use brackets to raise the precedence, try `1 + 2 / 3`");
-    while run("1 + 2 / 3").is_err() {  }
+    while run("1 + 2 / 3") {  }
     sprintln!("now try `(1 + 2) / 3`");
-    while run("(1 + 2) / 3").is_err() {  }
+    while run("(1 + 2) / 3") {  }
     sprintln!("See how they evaluate to different things?");
     sprintln!("\nWhat about negative numbers?");
     sprintln!("You can use negative numbers too, try out `-3 + 4`");
-    while run("-3 + 4").is_err() {  }
+    while run("-3 + 4") {  }
     sprintln!("Excellent! this gives back 1");
     sprintln!("Negation has a high precedence, try out `-(3 + 4)`");
-    while run("-(3 + 4)").is_err() {  }
+    while run("-(3 + 4)") {  }
     sprintln!("Excellent! this gives back -7 due to the different order of operations");
     sprintln!("\nThere are two more arithmetic operators in PSI. The power operator `3 ^ 2`");
-    while run("3 ^ 2").is_err() {  }
+    while run("3 ^ 2") {  }
     sprintln!("Three squared is nine, handy");
     sprintln!("Finally there's the modulo operator, for the remainder `1 % 3`");
-    while run("1 % 3").is_err() {  }
+    while run("1 % 3") {  }
     sprintln!("1 / 3 gives a remainder of 1");
     // Equality
     sprintln!("This is quite useful for finding if a number is even `4 % 2 == 0`");
-    while run("4 % 2 == 0").is_err() {  }
+    while run("4 % 2 == 0") {  }
     sprintln!("Wait! What do these `==` mean?");
     sprintln!("They mean 'is equal to'");
     sprintln!("\nFirst PSI evaluates 4 % 2 (which evalutes to `0` if 4 is even)");
@@ -129,42 +129,42 @@ pub fn learn() {
     sprintln!("therefore `4 % 2` gives 0 and `4 % 2 == 0` gives us true");
     sprintln!("Here's another example: `5 % 2 == 0` (is 5 even?) this would return false");
     sprintln!("\n`==` can be used for anything really, try `3 == 2`");
-    while run("3 == 2").is_err() {  }
+    while run("3 == 2") {  }
     sprintln!("Awesome! You see this evaluates to false, because 3 isn't equal to 2");
     sprintln!("\n`==` can be used regardless of datatypes `true == 3`");
-    while run("true == 3").is_err() {  }
+    while run("true == 3") {  }
     sprintln!("Let's move onto our next operator: `!=`");
     sprintln!("`!=` means `is not equal to`");
     sprintln!("\ngive `5.7 != 3.2` a whirl");
-    while run("5.7 != 3.2").is_err() {  }
+    while run("5.7 != 3.2") {  }
     sprintln!("This returns true, because 5.7 isn't equal to 3.2");
     sprintln!("Notice the usage of floats here, floats are just decimal numbers");
     sprintln!("\nLet's learn about another useful operation: `not true`");
     sprintln!("not turns true into false and false into true, it just flips them");
-    while run("not true").is_err() {  }
+    while run("not true") {  }
     sprintln!("This returns false, because what isn't true, is false");
     sprintln!("You can also use `!` to serve the same purpose, it does exactly the same thing");
     sprintln!("`!false`");
-    while run("!false").is_err() {  }
+    while run("!false") {  }
     sprintln!("This returns true, because what isn't false, is true");
     // Comparison
     sprintln!("\nSo you've covered arithmetic, basic datatypes and equality");
     sprintln!("Great work! Next up is comparison");
     sprintln!("\n`>` means `is greater than`");
     sprintln!("Try out `5 > 2`");
-    while run("5 > 2").is_err() {  }
+    while run("5 > 2") {  }
     sprintln!("5 is greater than 2, therefore it returns true");
     sprintln!("\n`<` means `is less than`");
     sprintln!("Try out `5.4 < 5.3`");
-    while run("5.4 < 5.3").is_err() {  }
+    while run("5.4 < 5.3") {  }
     sprintln!("This gives back false because 5.4 is greater than 5.3, not less than");
     sprintln!("\n`>=` means `is greater than or equal to`");
     sprintln!("Try out `137 >= 137`");
-    while run("137 >= 137").is_err() {  }
+    while run("137 >= 137") {  }
     sprintln!("137 is equal to 137, therefore it returns true");
     sprintln!("\n`<=` means `is less than`");
     sprintln!("Try out `3.141 <= 3`");
-    while run("3.141 <= 3").is_err() {  }
+    while run("3.141 <= 3") {  }
     sprintln!("This gives back false because 3.141 is not equal to 3");
     sprintln!("3.141 is also greater than 3, not less than.");
     sprintln!("\nThat's comparison done, congratulations!");
@@ -173,11 +173,11 @@ pub fn learn() {
     sprintln!("\nLet's move onto another datatype, strings.");
     sprintln!("Strings are a very important datatype, they allow us to store text");
     sprintln!("Strings are always surrounded by quotes: `\"Hello World!\"`");
-    while run("\"Hello World!\"").is_err() {  }
+    while run("\"Hello World!\"") {  }
     sprintln!("What about joining strings together?");
     sprintln!("We can use a process called `concatenation`");
     sprintln!("It's just like adding two numbers together: `\"Me\" + \"lon\"`");
-    while run("\"Me\" + \"lon\"").is_err() {  }
+    while run("\"Me\" + \"lon\"") {  }
     sprintln!("Great!");
     // Comments
     sprintln!("\nSometimes all this code can be a bit confusing as to what it's doing");
@@ -186,16 +186,16 @@ pub fn learn() {
     sprintln!("Comments are completely ignored by the language, you can put anything in them");
     sprintln!("There are both single-line and multi-line comments");
     sprintln!("Here's a single line comment: `// This is a comment`");
-    while run("// This is a comment").is_err() {  }
+    while run("// This is a comment") {  }
     sprintln!("You can use it in the same line as code: `5 % 2 == 0 // Is 5 even?`");
-    while run("5 % 2 == 0 // Is 5 even?").is_err() {  }
+    while run("5 % 2 == 0 // Is 5 even?") {  }
     sprintln!("Aha! The comment is completely ignored and only the code is run");
     sprintln!("Single line comments run to the end of the line and then stop");
     sprintln!("Next up is the multiline comment:");
     sprintln!("```\n/*\nHello \nWorld!\n*/\n```");
     sprintln!("These start with `/*` and end with `*/`");
     sprintln!("These comments can be embedded inbetween code: `5 /* comment */ + 2`");
-    while run("5 /* comment */ + 2").is_err() {  }
+    while run("5 /* comment */ + 2") {  }
     sprintln!("This will just evaluate to 7, ignoring the comment");
     sprintln!("These comments can span multiple lines too, this is useful if you want to");
     sprintln!("insert a lot of text into your code");
@@ -1,12 +1,15 @@
 mod learn;
 
-use psi_lang::{Lexer, Compiler, VM, VERSION};
+use psi_lang::{Lexer, Compiler, Loader, Error, VM, VERSION};
 use lliw::Fg::{Red, Yellow, Green, Blue};
 use lliw::{Style::{Bold, NoBold}, Reset};
 use std::time::Instant;
 use clap::{App, Arg};
 use scanln::scanln;
-use std::fs;
+use std::path::{Path, PathBuf};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::{env, fs};
 
 macro_rules! vprintln {
     ($v:expr, $fmt:literal, $( $arg:expr ),*) => { if $v { println!($fmt, $( $arg ),*) } };
@@ -61,7 +64,7 @@ fn file(path: &str, verbose: bool) {
     if let Ok(contents) = fs::read_to_string(path) {
         // Execute file contents
         let mut vm = VM::new(verbose);
-        run(&contents, &mut vm, verbose, false)
+        run(&contents, Path::new(path), &mut vm, verbose, false)
     } else {
         println!("{}{}Error: Failed to find file '{}'{}", Red, Bold, path, Reset);
     }
@@ -78,35 +81,44 @@ fn repl(verbose: bool) {
     loop {
         // Prompt user for input
         let input: String = scanln!("> ");
-        run(&input, &mut vm, verbose, true)
+        run(&input, Path::new("repl"), &mut vm, verbose, true)
     }
 }
 
-fn run(src: &str, vm: &mut VM, verbose: bool, repl: bool) {
+fn source_line(error: &Error, src: &str) -> String {
+    // Find the line an error occured on, this may be in an imported module
+    let src = match error.file().map(fs::read_to_string) {
+        Some(Ok(module)) => module,
+        _ => src.to_string(),
+    };
+    src.lines().nth(error.line().saturating_sub(1)).unwrap_or("").to_string()
+}
+
+fn run(src: &str, path: &Path, vm: &mut VM, verbose: bool, repl: bool) {
     // Start timer
     let start = Instant::now();
-    // Initiate a lexer
-    vprintln!(verbose, "{}{}Lexing from char stream to token stream{}", Yellow, Bold, Reset);
-    let mut lexer = Lexer::new(&src);
-    // Run the lexer and handle any errors
-    if let Err(error) = lexer.run() {
-        error.display_line(src, repl);
-        println!("{}{}{}{}", Red, Bold, error, Reset);
-        return
-    }
-    // Show result
-    let lex = Instant::now();
-    vprintln!(verbose, "\n{}{}Success!{} Token stream:", Green, Bold, Reset); 
+    // Initiate a lexer, tokens are pulled in lazily by the compiler
+    let lexer = Lexer::new(src);
+    vprintln!(verbose, "{}{}Token stream:{}", Yellow, Bold, Reset);
     if verbose { lexer.display(); }
     // Initiate compiler
     vprintln!(verbose, "\n{}{}Compiling from token stream to bytecode{}", Yellow, Bold, Reset); 
-    let mut compiler = Compiler::new(lexer.tokens);
+    let mut compiler = Compiler::new(lexer);
+    // Modules are looked for next to the file, and then in the directories in PSI_PATH
+    let search: Vec<PathBuf> = env::var_os("PSI_PATH").map_or(vec![], |p| env::split_paths(&p).collect());
+    compiler.file = path.to_path_buf();
+    compiler.loader = Rc::new(RefCell::new(Loader::new(search)));
     // Run the compiler and handle any errors
     if let Err(error) = compiler.compile() {
-        error.display_line(src, repl);
+        error.display_line(&source_line(&error, src), repl);
         println!("{}{}{}{}", Red, Bold, error, Reset);
         return
     }
+    // Display any warnings
+    for warning in &compiler.warnings {
+        warning.display_line(&source_line(warning, src), repl);
+        println!("{}{}Warning: {}{}", Yellow, Bold, warning, Reset);
+    }
     // Show result
     let com = Instant::now();
     vprintln!(verbose, "\n{}{}Success!{} Disassembled bytecode:", Green, Bold, Reset);
@@ -114,7 +126,7 @@ fn run(src: &str, vm: &mut VM, verbose: bool, repl: bool) {
     // Run virtual machine
     vprintln!(verbose, "{}{}\nExecuting bytecode chunk in VM:{}", Yellow, Bold, Reset);
     if let Err(error) = vm.run(compiler.chunk) {
-        error.display_line(src, repl);
+        error.display_line(&source_line(&error, src), repl);
         println!("{}{}{}{}", Red, Bold, error, Reset);
         vm.reset();
         return
@@ -126,7 +138,6 @@ fn run(src: &str, vm: &mut VM, verbose: bool, repl: bool) {
     // Display success
     let end = Instant::now();
     println!("{}{}Success!{} Done in {}{:?}{}", Green, Bold, Reset, Blue, end - start, Reset);
-    vprintln!(verbose, "Lexer:    {:?}", lex - start);
-    vprintln!(verbose, "Compiler: {:?}", com - lex);
+    vprintln!(verbose, "Compiler: {:?}", com - start);
     vprintln!(verbose, "VM:       {:?}", end - com);
 }
@@ -0,0 +1,42 @@
+// examples.rs - Runs every example program and compares what it prints with the recorded output
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+fn output(example: &Path) -> String {
+    // Run an example, leaving out the closing success line as it contains a timing
+    let run = Command::new(env!("CARGO_BIN_EXE_psi-compiler"))
+        .arg(example)
+        .output()
+        .expect("Failed to run the interpreter");
+    let stdout = String::from_utf8_lossy(&run.stdout);
+    let lines: Vec<&str> = stdout.lines().filter(|line| !line.contains("Success!")).collect();
+    lines.join("\n") + "\n"
+}
+
+#[test]
+fn examples() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let expected = root.join("tests/examples");
+    let mut examples: Vec<_> = fs::read_dir(root.join("../examples"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "psi"))
+        .collect();
+    examples.sort();
+    // Setting BLESS records the current output instead of checking it
+    let bless = std::env::var_os("BLESS").is_some();
+    let mut failed = vec![];
+    for example in examples {
+        let name = example.file_stem().unwrap().to_string_lossy().to_string();
+        let recorded = expected.join(format!("{}.out", name));
+        let found = output(&example);
+        if bless {
+            fs::write(&recorded, &found).unwrap();
+        } else if fs::read_to_string(&recorded).ok().as_deref() != Some(found.as_str()) {
+            eprintln!("{} printed:\n{}", name, found);
+            failed.push(name);
+        }
+    }
+    assert!(failed.is_empty(), "Output differs for {:?}", failed);
+}
@@ -1,28 +1,80 @@
 // chunk.rs - Utilities for representing chunks of bytecode
 use lliw::{Fg, Style, Reset};
-use crate::Value;
+use crate::{Value, Type};
+use std::collections::HashMap;
 use std::fmt;
 
+// What is left to do once a finally clause has run
+pub const PENDING_NONE: i64 = 0;
+pub const PENDING_THROW: i64 = 1;
+pub const PENDING_RETURN: i64 = 2;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum OpCode {
     OpConstant(u16),
     OpDefineGlobal(u16),
+    OpDefineConst(u16),
     OpGetGlobal(u16),
     OpSetGlobal(u16),
+    OpDefineLocal,
+    OpGetLocal(u16),
+    OpSetLocal(u16),
+    OpPopLocals(u16),
     OpAdd,
     OpSub,
     OpMul,
     OpDiv,
+    OpIntDiv,
     OpMod,
     OpPow,
     OpNegate,
     OpNot,
+    OpBitNot,
+    OpBitAnd,
+    OpBitOr,
+    OpBitXor,
+    OpShiftLeft,
+    OpShiftRight,
     OpTrue,
     OpFalse,
     OpNil,
     OpEqual,
     OpGreater,
     OpLess,
+    OpInRange(bool),
+    OpRange(bool, bool),
+    OpContains,
+    OpNoMatch,
+    OpJump(u16),
+    OpJumpIfFalse(u16),
+    OpJumpIfNil(u16),
+    OpCall(u16, Option<u16>),
+    OpGetProperty(u16),
+    OpSetProperty(u16),
+    OpInvoke(u16, u16, Option<u16>),
+    OpDup,
+    OpDupPair,
+    OpPipe(u16),
+    OpTuple(u16),
+    OpList(u16),
+    OpUnpack(u16, bool),
+    OpMatchVariant(u16, u16),
+    OpMap(u16),
+    OpIndex,
+    OpSetIndex,
+    OpIter(bool),
+    OpNext(u16),
+    OpCheckNext(u16),
+    OpLoop(u16),
+    OpImport(u16),
+    OpTry(u16, u16),
+    OpEndTry,
+    OpThrow,
+    OpPending,
+    OpFinally,
+    OpAssert(u16, u16, bool),
+    OpCheckType(u16),
+    OpYield,
     OpPrint,
     OpPop,
     OpReturn,
@@ -31,7 +83,14 @@ pub enum OpCode {
 #[derive(Debug, Clone, PartialEq)]
 pub struct Chunk {
     pub code: Vec<(usize, usize, OpCode)>,
+    pub lines: Vec<usize>,
     pub constants: Vec<Value>,
+    // Types that values are checked against while running
+    pub types: Vec<Type>,
+    // Constants declared at the top level, with the line and column of their declaration
+    pub consts: HashMap<String, (usize, usize)>,
+    pub docs: HashMap<String, String>,
+    pub module: Option<String>,
     pub line: usize,
 }
 
@@ -40,14 +99,20 @@ impl Chunk {
         // Create a new chunk
         Self {
             code: vec![],
+            lines: vec![],
             constants: Vec::with_capacity(256),
+            types: vec![],
+            consts: HashMap::new(),
+            docs: HashMap::new(),
+            module: None,
             line,
         }
     }
 
-    pub fn write(&mut self, code: OpCode, col: usize, len: usize) {
+    pub fn write(&mut self, code: OpCode, line: usize, col: usize, len: usize) {
         // Add an instruction to this chunk
-        self.code.push((col, len, code))
+        self.code.push((col, len, code));
+        self.lines.push(line);
     }
 
     pub fn add_constant(&mut self, value: Value) -> u16 {
@@ -57,29 +122,132 @@ impl Chunk {
         idx as u16
     }
 
+    pub fn add_type(&mut self, kind: Type) -> u16 {
+        // Add a type for checking values against
+        let idx = self.types.len();
+        self.types.push(kind);
+        idx as u16
+    }
+
     pub fn display(&self) {
         // Display the chunk in text format
-        for (c, _, i) in &self.code {
-            self.disassemble_instruction(i, *c);
+        for ((c, _, i), l) in self.code.iter().zip(&self.lines) {
+            self.disassemble_instruction(i, *l, *c);
+        }
+        // Display the bytecode of any functions defined in this chunk
+        for constant in &self.constants {
+            match constant {
+                Value::Function(func) => {
+                    println!("{}{}{:?}:{}", Fg::Yellow, Style::Bold, func, Reset);
+                    func.chunk.display();
+                }
+                Value::Struct(kind) => for method in kind.methods.values() {
+                    println!("{}{}{:?}.{:?}:{}", Fg::Yellow, Style::Bold, kind, method, Reset);
+                    method.chunk.display();
+                }
+                _ => (),
+            }
         }
     }
 
-    pub fn disassemble_instruction(&self, instruction: &OpCode, col: usize) {
+    pub fn disassemble_instruction(&self, instruction: &OpCode, line: usize, col: usize) {
         // Disassemble and display an instruction
         match instruction {
             OpCode::OpConstant(idx) | 
             OpCode::OpDefineGlobal(idx) | 
+            OpCode::OpDefineConst(idx) | 
+            OpCode::OpImport(idx) | 
             OpCode::OpSetGlobal(idx) |
-            OpCode::OpGetGlobal(idx) => println!(
+            OpCode::OpGetGlobal(idx) |
+            OpCode::OpGetProperty(idx) |
+            OpCode::OpSetProperty(idx) => println!(
                 "=> {}{:04} {:03} {}{}{} {}{}{} {}{}", 
-                Fg::Blue, self.line, col,
+                Fg::Blue, line, col,
                 Fg::LightBlack, Style::Bold, instruction, Style::NoBold,
                 Fg::Blue, idx, self.constants[*idx as usize],
                 Fg::Reset,
             ),
+            OpCode::OpGetLocal(slot) |
+            OpCode::OpSetLocal(slot) |
+            OpCode::OpPopLocals(slot) |
+            OpCode::OpPipe(slot) |
+            OpCode::OpTuple(slot) |
+            OpCode::OpList(slot) |
+            OpCode::OpMap(slot) |
+            OpCode::OpCall(slot, None) => println!(
+                "=> {}{:04} {:03} {}{}{} {}{}{}", 
+                Fg::Blue, line, col,
+                Fg::LightBlack, Style::Bold, instruction, Style::NoBold,
+                slot, Reset,
+            ),
+            OpCode::OpCall(argc, Some(names)) => println!(
+                "=> {}{:04} {:03} {}{}{} {}{} {}{}{}", 
+                Fg::Blue, line, col,
+                Fg::LightBlack, Style::Bold, instruction, Style::NoBold,
+                argc, Fg::Blue, self.constants[*names as usize],
+                Fg::Reset,
+            ),
+            OpCode::OpInvoke(idx, argc, names) => println!(
+                "=> {}{:04} {:03} {}{}{} {}{}{} {} ({} args){}{}", 
+                Fg::Blue, line, col,
+                Fg::LightBlack, Style::Bold, instruction, Style::NoBold,
+                Fg::Blue, idx, self.constants[*idx as usize], argc,
+                names.map_or(String::new(), |n| format!(" {}", self.constants[n as usize])),
+                Fg::Reset,
+            ),
+            OpCode::OpUnpack(count, rest) => println!(
+                "=> {}{:04} {:03} {}{}{} {}{}{}{}", 
+                Fg::Blue, line, col,
+                Fg::LightBlack, Style::Bold, instruction, Style::NoBold,
+                count, if *rest { " (with rest)" } else { "" }, Reset,
+            ),
+            OpCode::OpAssert(idx, count, message) => println!(
+                "=> {}{:04} {:03} {}{}{} {}{}{} {} ({} operands{}){}", 
+                Fg::Blue, line, col,
+                Fg::LightBlack, Style::Bold, instruction, Style::NoBold,
+                Fg::Blue, idx, self.constants[*idx as usize], count,
+                if *message { ", with message" } else { "" },
+                Fg::Reset,
+            ),
+            OpCode::OpCheckType(idx) => println!(
+                "=> {}{:04} {:03} {}{}{} {}{}{} {}{}", 
+                Fg::Blue, line, col,
+                Fg::LightBlack, Style::Bold, instruction, Style::NoBold,
+                Fg::Blue, idx, self.types[*idx as usize],
+                Fg::Reset,
+            ),
+            OpCode::OpMatchVariant(idx, count) => println!(
+                "=> {}{:04} {:03} {}{}{} {}{}{} {} ({} values){}", 
+                Fg::Blue, line, col,
+                Fg::LightBlack, Style::Bold, instruction, Style::NoBold,
+                Fg::Blue, idx, self.constants[*idx as usize], count,
+                Fg::Reset,
+            ),
+            OpCode::OpLoop(offset) => println!(
+                "=> {}{:04} {:03} {}{}{} {}-{}{}", 
+                Fg::Blue, line, col,
+                Fg::LightBlack, Style::Bold, instruction, Style::NoBold,
+                offset, Reset,
+            ),
+            OpCode::OpJump(offset) |
+            OpCode::OpJumpIfFalse(offset) |
+            OpCode::OpJumpIfNil(offset) |
+            OpCode::OpNext(offset) |
+            OpCode::OpCheckNext(offset) => println!(
+                "=> {}{:04} {:03} {}{}{} {}+{}{}", 
+                Fg::Blue, line, col,
+                Fg::LightBlack, Style::Bold, instruction, Style::NoBold,
+                offset, Reset,
+            ),
+            OpCode::OpTry(catch, finally) => println!(
+                "=> {}{:04} {:03} {}{}{} {}+{} (finally +{}){}", 
+                Fg::Blue, line, col,
+                Fg::LightBlack, Style::Bold, instruction, Style::NoBold,
+                catch, finally, Reset,
+            ),
             _ => println!(
                 "=> {}{:04} {:03} {}{}{}{}", 
-                Fg::Blue, self.line, col,
+                Fg::Blue, line, col,
                 Fg::LightBlack, Style::Bold, instruction,
                 Reset,
             ),
@@ -93,12 +261,14 @@ impl fmt::Display for OpCode {
         write!(fmt, "{}", match self {
             OpCode::OpConstant(_) => "OP_CONSTANT",
             OpCode::OpDefineGlobal(_) => "OP_DEFINE_GLOBAL",
+            OpCode::OpDefineConst(_) => "OP_DEFINE_CONST",
             OpCode::OpGetGlobal(_) => "OP_GET_GLOBAL",
             OpCode::OpSetGlobal(_) => "OP_SET_GLOBAL",
             OpCode::OpAdd => "OP_ADD",
             OpCode::OpSub => "OP_SUB",
             OpCode::OpMul => "OP_MUL",
             OpCode::OpDiv => "OP_DIV",
+            OpCode::OpIntDiv => "OP_INT_DIV",
             OpCode::OpMod => "OP_MOD",
             OpCode::OpPow => "OP_POW",
             OpCode::OpTrue => "OP_TRUE",
@@ -107,9 +277,53 @@ impl fmt::Display for OpCode {
             OpCode::OpReturn => "OP_RETURN",
             OpCode::OpNegate => "OP_NEGATE",
             OpCode::OpNot => "OP_NOT",
+            OpCode::OpBitNot => "OP_BIT_NOT",
+            OpCode::OpBitAnd => "OP_BIT_AND",
+            OpCode::OpBitOr => "OP_BIT_OR",
+            OpCode::OpBitXor => "OP_BIT_XOR",
+            OpCode::OpShiftLeft => "OP_SHIFT_LEFT",
+            OpCode::OpShiftRight => "OP_SHIFT_RIGHT",
             OpCode::OpGreater => "OP_GREATER",
             OpCode::OpLess => "OP_LESS",
             OpCode::OpEqual => "OP_EQUAL",
+            OpCode::OpDefineLocal => "OP_DEFINE_LOCAL",
+            OpCode::OpGetLocal(_) => "OP_GET_LOCAL",
+            OpCode::OpSetLocal(_) => "OP_SET_LOCAL",
+            OpCode::OpPopLocals(_) => "OP_POP_LOCALS",
+            OpCode::OpInRange(_) => "OP_IN_RANGE",
+            OpCode::OpRange(_, _) => "OP_RANGE",
+            OpCode::OpContains => "OP_CONTAINS",
+            OpCode::OpNoMatch => "OP_NO_MATCH",
+            OpCode::OpJump(_) => "OP_JUMP",
+            OpCode::OpJumpIfFalse(_) => "OP_JUMP_IF_FALSE",
+            OpCode::OpJumpIfNil(_) => "OP_JUMP_IF_NIL",
+            OpCode::OpCall(_, _) => "OP_CALL",
+            OpCode::OpGetProperty(_) => "OP_GET_PROPERTY",
+            OpCode::OpSetProperty(_) => "OP_SET_PROPERTY",
+            OpCode::OpInvoke(_, _, _) => "OP_INVOKE",
+            OpCode::OpDup => "OP_DUP",
+            OpCode::OpDupPair => "OP_DUP_PAIR",
+            OpCode::OpTuple(_) => "OP_TUPLE",
+            OpCode::OpList(_) => "OP_LIST",
+            OpCode::OpPipe(_) => "OP_PIPE",
+            OpCode::OpUnpack(_, _) => "OP_UNPACK",
+            OpCode::OpMatchVariant(_, _) => "OP_MATCH_VARIANT",
+            OpCode::OpMap(_) => "OP_MAP",
+            OpCode::OpIndex => "OP_INDEX",
+            OpCode::OpSetIndex => "OP_SET_INDEX",
+            OpCode::OpIter(_) => "OP_ITER",
+            OpCode::OpNext(_) => "OP_NEXT",
+            OpCode::OpCheckNext(_) => "OP_CHECK_NEXT",
+            OpCode::OpCheckType(_) => "OP_CHECK_TYPE",
+            OpCode::OpLoop(_) => "OP_LOOP",
+            OpCode::OpImport(_) => "OP_IMPORT",
+            OpCode::OpTry(_, _) => "OP_TRY",
+            OpCode::OpEndTry => "OP_END_TRY",
+            OpCode::OpThrow => "OP_THROW",
+            OpCode::OpPending => "OP_PENDING",
+            OpCode::OpFinally => "OP_FINALLY",
+            OpCode::OpAssert(_, _, _) => "OP_ASSERT",
+            OpCode::OpYield => "OP_YIELD",
             OpCode::OpPrint => "OP_PRINT",
             OpCode::OpPop => "OP_POP",
         })
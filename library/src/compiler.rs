@@ -1,51 +1,178 @@
 // compiler.rs - For emitting bytecode given a stream of tokens
 use crate::{
     Chunk, 
+    Lexer,
     Token, 
     OpCode, 
     Error, 
     TokenKind as Tk, 
     Value, 
+    Function,
+    Struct,
+    Enum,
+    Module,
+    Loader,
+    Type,
+    Params,
     Precedence, 
     get_rule, 
     TokenKind
 };
+use crate::module::{self, Loaded};
+use crate::methods;
+use crate::chunk::PENDING_NONE;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::fs;
 
-pub struct Compiler {
-    tokens: Vec<Token>,
+pub struct Compiler<'src> {
+    lexer: Lexer<'src>,
+    current: Token<'src>,
+    previous: Option<Token<'src>>,
+    comment: Option<Token<'src>>,
     pub chunk: Chunk,
-    ptr: usize,
+    pub warnings: Vec<Error>,
+    pub file: PathBuf,
+    pub loader: Rc<RefCell<Loader>>,
+    consts: HashMap<&'src str, (usize, usize)>,
+    globals: HashMap<&'src str, Type>,
+    structs: HashMap<&'src str, Definition<'src>>,
+    // The variants of each enum, along with the types of the values they carry
+    enums: HashMap<&'src str, Vec<(&'src str, Vec<Type>)>>,
+    locals: Vec<Local<'src>>,
+    types: Vec<Typed>,
+    returns: Vec<Type>,
+    // Whether the function being compiled has yielded, making it a generator
+    yields: bool,
+    // The value on the left of a |>, along with where the call it goes into will be
+    // (by the height of the type stack and how deeply nested in functions it is)
+    piped: Option<(Typed, usize, usize)>,
+    depth: usize,
+    doc: Vec<&'src str>,
+    replay: VecDeque<Token<'src>>,
+    // Whether this is the pass that checks types before any code is kept
+    checking: bool,
 }
 
-impl Compiler {
-    pub fn new(tokens: Vec<Token>) -> Self {
+struct Local<'src> {
+    name: &'src str,
+    kind: Type,
+    constant: Option<(usize, usize)>,
+}
+
+// What is known about a struct while type checking
+struct Definition<'src> {
+    fields: Vec<(&'src str, Type)>,
+    methods: HashMap<&'src str, (bool, Type)>,
+    complete: bool,
+}
+
+// The static type of an expression, along with where it is in the source
+struct Typed {
+    kind: Type,
+    line: usize,
+    col: usize,
+    len: usize,
+}
+
+// An item within a tuple or list literal, which could also be a destructuring target
+enum Element<'src> {
+    // Any other expression e.g. 8080
+    Value,
+    // A lone variable that can be assigned to e.g. host
+    Name(Token<'src>),
+    // A variable that collects the remaining values e.g. ...rest
+    Rest(Token<'src>),
+}
+
+enum Pattern<'src> {
+    // A pattern that compares against a literal value e.g. "prod"
+    Literal(Value),
+    // A pattern that matches anything e.g. _ or a binding
+    CatchAll,
+    // A pattern that matches a range of values e.g. 2..5
+    Range,
+    // A pattern that matches a variant of an enum, binding what it carries e.g. LogLevel.Warn(message)
+    Variant(Vec<(&'src str, Type)>),
+}
+
+impl<'src> Compiler<'src> {
+    pub fn new(lexer: Lexer<'src>) -> Self {
         // Create a new compiler
-        let mut ptr = 0;
-        // If the token stream begins with comments, jump over them
-        while let Some(t) = tokens.get(ptr) {
-            if t.kind == TokenKind::Comment { ptr += 1; } else { break }
-        }
         Self {
-            tokens,
+            lexer,
+            current: Token { kind: TokenKind::Delim, span: 0..0, len: 0, line: 1, col: 1 },
+            previous: None,
+            comment: None,
             chunk: Chunk::new(1),
-            ptr,
+            warnings: vec![],
+            file: PathBuf::from("."),
+            loader: Rc::new(RefCell::new(Loader::default())),
+            consts: HashMap::new(),
+            globals: HashMap::new(),
+            structs: HashMap::new(),
+            enums: HashMap::new(),
+            piped: None,
+            locals: vec![],
+            types: vec![],
+            returns: vec![],
+            yields: false,
+            depth: 0,
+            doc: vec![],
+            replay: VecDeque::new(),
+            checking: false,
         }
     }
 
     pub fn compile(&mut self) -> Result<(), Error> {
-        // Start the compilation
-        while let Ok(0) = self.present(TokenKind::EOI) {
+        // Type check the whole program before generating the code that is kept
+        if !self.checking {
+            self.check()?;
+        }
+        // Start the compilation, pulling in the first token
+        self.current = self.next_token()?;
+        while self.present(TokenKind::EOI)? == 0 {
             // Move until EOI is hit
             self.declaration()?;
         }
         Ok(())
     }
 
+    fn check(&mut self) -> Result<(), Error> {
+        // Run through the program once, throwing its code away but keeping what it declares,
+        // so that uses of functions, structs and variables declared further down are checked too
+        let mut checker = Compiler::new(self.lexer.clone());
+        checker.file = self.file.clone();
+        checker.loader = Rc::clone(&self.loader);
+        checker.checking = true;
+        checker.compile()?;
+        self.globals = checker.globals;
+        self.structs = checker.structs;
+        self.enums = checker.enums;
+        Ok(())
+    }
+
     fn declaration(&mut self) -> Result<(), Error> {
-        if self.present(TokenKind::Var)? == 0 {
-            self.statement()
+        // Doc comments only survive up until a variable declaration
+        let doc = std::mem::take(&mut self.doc);
+        if self.present(TokenKind::Var)? != 0 {
+            self.var_declaration(doc, false)
+        } else if self.present(TokenKind::Const)? != 0 {
+            self.var_declaration(doc, true)
+        } else if self.present(TokenKind::Fn)? != 0 {
+            self.fn_declaration(doc)
+        } else if self.present(TokenKind::Struct)? != 0 {
+            self.struct_declaration(doc)
+        } else if self.present(TokenKind::Enum)? != 0 {
+            self.enum_declaration(doc)
+        } else if self.present(TokenKind::Import)? != 0 {
+            self.import_declaration()
+        } else if self.present(TokenKind::From)? != 0 {
+            self.selective_import()
         } else {
-            self.var_declaration()
+            self.statement()
         }
     }
 
@@ -54,6 +181,14 @@ impl Compiler {
         let col = self.present(TokenKind::Print)?;
         if col != 0 {
             self.print_statement(col)?;
+        } else if self.current.kind == TokenKind::Return {
+            self.return_statement()?;
+        } else if self.current.kind == TokenKind::Try {
+            self.try_statement()?;
+        } else if self.current.kind == TokenKind::For {
+            self.for_statement()?;
+        } else if self.current.kind == TokenKind::Assert {
+            self.assert_statement()?;
         } else {
             self.expression_statement()?;
         }
@@ -69,33 +204,913 @@ impl Compiler {
     fn expression_statement(&mut self) -> Result<(), Error> {
         // Expression that acts as a statement
         self.expression()?;
-        self.consume(TokenKind::Delim)?;
+        self.pop_type();
+        self.end_statement()?;
         self.emit_byte(OpCode::OpPop, self.get_back().unwrap().col, 0);
         Ok(())
     }
 
-    fn var_declaration(&mut self) -> Result<(), Error> {
-        // For variable declaration
-        let global = self.parse_variable()?;
-        let present = self.present(TokenKind::Equal)?;
-        if present == 0 {
-            self.emit_byte(OpCode::OpNil, present, 3);
+    fn end_statement(&mut self) -> Result<(), Error> {
+        // Statements end with a delimiter, or the closing brace of a block
+        if self.depth > 0 && self.current.kind == TokenKind::RightCurly {
+            Ok(())
+        } else {
+            self.consume(TokenKind::Delim)?;
+            Ok(())
+        }
+    }
+
+    fn var_declaration(&mut self, doc: Vec<&str>, constant: bool) -> Result<(), Error> {
+        // For variable and constant declaration
+        if matches!(self.current.kind, Tk::LeftParen | Tk::LeftSquare) {
+            return self.var_destructure(constant);
+        }
+        let name = self.current.clone();
+        let id = if let TokenKind::Identifier(id) = name.kind { id } else {
+            self.consume(TokenKind::Identifier(""))?;
+            unreachable!()
+        };
+        self.advance()?;
+        // Check for a type annotation
+        let annotation = if self.present(TokenKind::Colon)? != 0 {
+            Some(self.annotation()?)
+        } else {
+            None
+        };
+        if constant {
+            // Constants must be given a value
+            self.consume(TokenKind::Equal)?;
+            self.expression()?;
+        } else if self.present(TokenKind::Equal)? == 0 {
+            self.emit_byte(OpCode::OpNil, name.col, name.len);
+            self.push_type(Type::Nil);
         } else {
             self.expression()?;
         }
-        self.consume(TokenKind::Delim)?;
-        self.define_variable(global)?;
+        // The value must match the annotation, constants keep the type of their value
+        let value = self.pop_type();
+        if let Some(kind) = &annotation {
+            self.expect_value(kind, &value)?;
+        }
+        let kind = match annotation {
+            Some(kind) => kind,
+            None if constant => value.kind,
+            None => Type::Any,
+        };
+        self.end_statement()?;
+        if !self.returns.is_empty() {
+            // Variables within functions are local
+            self.add_local(id, kind, name.col, name.len);
+            if constant {
+                self.locals.last_mut().unwrap().constant = Some((name.line, name.col));
+            }
+            return Ok(());
+        }
+        // Constants can't be declared again
+        self.check_const(&name)?;
+        let global = self.identifier_constant(name.clone())?;
+        // Attach any documentation to the variable
+        if !doc.is_empty() {
+            self.chunk.docs.insert(id.to_string(), doc.join("\n"));
+        }
+        self.globals.insert(id, kind);
+        if constant {
+            self.emit_byte(OpCode::OpDefineConst(global), name.col, name.len);
+            self.consts.insert(id, (name.line, name.col));
+            self.chunk.consts.insert(id.to_string(), (name.line, name.col));
+        } else {
+            self.define_variable(global)?;
+        }
+        Ok(())
+    }
+
+    fn var_destructure(&mut self, constant: bool) -> Result<(), Error> {
+        // For destructuring declaration e.g. var (host, port) = split_addr(addr)
+        let open = self.current.clone();
+        let (names, rest) = self.targets()?;
+        let (col, len) = self.span_from(&open);
+        self.consume(Tk::Equal)?;
+        self.expression()?;
+        let value = self.pop_type();
+        let kinds = self.unpacked(&value, names.len(), rest, open.line, (col, len))?;
+        self.end_statement()?;
+        // Values come out in order, ready to be defined one after another
+        self.emit_byte(OpCode::OpUnpack(names.len() as u16, rest), col, len);
+        for (name, kind) in names.iter().zip(kinds) {
+            // Like single declarations, only constants keep the type of their value
+            if !constant {
+                self.define(name, Type::Any)?;
+                continue;
+            }
+            let id = if let TokenKind::Identifier(id) = name.kind { id } else { unreachable!() };
+            if !self.returns.is_empty() {
+                self.add_local(id, kind, name.col, name.len);
+                self.locals.last_mut().unwrap().constant = Some((name.line, name.col));
+                continue;
+            }
+            self.check_const(name)?;
+            let global = self.identifier_constant(name.clone())?;
+            self.globals.insert(id, kind);
+            self.emit_byte(OpCode::OpDefineConst(global), name.col, name.len);
+            self.consts.insert(id, (name.line, name.col));
+            self.chunk.consts.insert(id.to_string(), (name.line, name.col));
+        }
+        Ok(())
+    }
+
+    fn targets(&mut self) -> Result<(Vec<Token<'src>>, bool), Error> {
+        // Read in the names of a destructuring pattern e.g. (host, port) or [first, ...rest]
+        let close = if self.present(Tk::LeftParen)? != 0 { Tk::RightParen } else {
+            self.consume(Tk::LeftSquare)?;
+            Tk::RightSquare
+        };
+        let (mut names, mut rest) = (vec![], false);
+        while self.current.kind != close {
+            // Only the last name can collect the remaining values
+            rest = self.present(Tk::DotDotDot)? != 0;
+            names.push(self.identifier()?);
+            if rest || self.present(Tk::Comma)? == 0 { break }
+        }
+        self.consume(close)?;
+        Ok((names, rest))
+    }
+
+    fn unpacked(
+        &self, 
+        value: &Typed, 
+        count: usize, 
+        rest: bool, 
+        line: usize, 
+        (col, len): (usize, usize),
+    ) -> Result<Vec<Type>, Error> {
+        // Work out the types of the values that destructuring gives back
+        match &value.kind {
+            Type::Tuple(items) => {
+                let fits = if rest { items.len() + 1 >= count } else { items.len() == count };
+                if !fits {
+                    let expected = if rest { format!("at least {}", count - 1) } else { count.to_string() };
+                    return Err(Error::UnpackMismatch(line, col, len, expected, items.len()));
+                }
+                let mut kinds = items.clone();
+                if rest {
+                    let remaining = kinds.split_off(count - 1);
+                    kinds.push(Type::Tuple(remaining));
+                }
+                Ok(kinds)
+            }
+            Type::List(item) => {
+                let mut kinds = vec![*item.clone(); count];
+                if rest { kinds[count - 1] = value.kind.clone() }
+                Ok(kinds)
+            }
+            Type::Any => Ok(vec![Type::Any; count]),
+            kind => Err(Error::MismatchedTypes(
+                value.line, value.col, value.len, 
+                format!("Can only unpack tuples and lists, not {}", kind)
+            )),
+        }
+    }
+
+    fn fn_declaration(&mut self, doc: Vec<&str>) -> Result<(), Error> {
+        // For function declaration e.g. fn port(env: string) -> int { 8080 }
+        let name = self.current.clone();
+        let id = if let TokenKind::Identifier(id) = name.kind { id } else {
+            self.consume(TokenKind::Identifier(""))?;
+            unreachable!()
+        };
+        self.advance()?;
+        if self.returns.is_empty() {
+            self.check_const(&name)?;
+        }
+        let (kind, function) = self.function(id, &name, None)?;
+        self.emit_constant(Value::Function(Rc::new(function)), name.col, name.len);
+        self.end_statement()?;
+        if !self.returns.is_empty() {
+            // Functions within functions are local
+            self.add_local(id, kind, name.col, name.len);
+            return Ok(());
+        }
+        let global = self.identifier_constant(name)?;
+        if !doc.is_empty() {
+            self.chunk.docs.insert(id.to_string(), doc.join("\n"));
+        }
+        self.define_variable(global)
+    }
+
+    fn struct_declaration(&mut self, doc: Vec<&str>) -> Result<(), Error> {
+        // For struct declaration e.g. struct Server { host: string, fn url(self) { ... } }
+        let name = self.current.clone();
+        let id = if let TokenKind::Identifier(id) = name.kind { id } else {
+            self.consume(TokenKind::Identifier(""))?;
+            unreachable!()
+        };
+        self.advance()?;
+        if self.returns.is_empty() {
+            self.check_const(&name)?;
+            self.globals.insert(id, Type::Struct(id.to_string()));
+        }
+        let definition = Definition { fields: vec![], methods: HashMap::new(), complete: false };
+        self.structs.insert(id, definition);
+        let mut kind = Struct { name: id.to_string(), fields: vec![], methods: HashMap::new() };
+        self.consume(TokenKind::LeftCurly)?;
+        self.skip_delims()?;
+        while self.current.kind != TokenKind::RightCurly {
+            if self.present(TokenKind::Fn)? != 0 {
+                // Compile a method, these take the instance as a "self" parameter
+                let method = self.current.clone();
+                let method_id = if let TokenKind::Identifier(id) = method.kind { id } else {
+                    self.consume(TokenKind::Identifier(""))?;
+                    unreachable!()
+                };
+                self.advance()?;
+                let receiver = Type::Instance(id.to_string());
+                let (signature, function) = self.function(method_id, &method, Some(receiver))?;
+                let has_self = function.params.first().is_some_and(|p| p == "self");
+                self.structs.get_mut(id).unwrap().methods.insert(method_id, (has_self, signature));
+                kind.methods.insert(method_id.to_string(), Rc::new(function));
+            } else {
+                // A field with an optional type
+                let field = self.current.clone();
+                let field_id = if let TokenKind::Identifier(id) = field.kind { id } else {
+                    self.consume(TokenKind::Identifier(""))?;
+                    unreachable!()
+                };
+                self.advance()?;
+                let field_kind = if self.present(TokenKind::Colon)? != 0 { 
+                    self.annotation()? 
+                } else { 
+                    Type::Any 
+                };
+                self.structs.get_mut(id).unwrap().fields.push((field_id, field_kind));
+                kind.fields.push(field_id.to_string());
+            }
+            self.present(TokenKind::Comma)?;
+            self.skip_delims()?;
+        }
+        self.consume(TokenKind::RightCurly)?;
+        self.structs.get_mut(id).unwrap().complete = true;
+        self.emit_constant(Value::Struct(Rc::new(kind)), name.col, name.len);
+        self.end_statement()?;
+        if !self.returns.is_empty() {
+            // Structs within functions are local
+            self.add_local(id, Type::Struct(id.to_string()), name.col, name.len);
+            return Ok(());
+        }
+        let global = self.identifier_constant(name)?;
+        if !doc.is_empty() {
+            self.chunk.docs.insert(id.to_string(), doc.join("\n"));
+        }
+        self.define_variable(global)
+    }
+
+    fn enum_declaration(&mut self, doc: Vec<&str>) -> Result<(), Error> {
+        // For enum declaration e.g. enum LogLevel { Debug, Info, Warn(string) }
+        let name = self.identifier()?;
+        let id = if let TokenKind::Identifier(id) = name.kind { id } else { unreachable!() };
+        if self.returns.is_empty() {
+            self.check_const(&name)?;
+            self.globals.insert(id, Type::Enum(id.to_string()));
+        }
+        let (mut variants, mut kind) = (vec![], Enum { name: id.to_string(), variants: vec![] });
+        self.consume(TokenKind::LeftCurly)?;
+        self.skip_delims()?;
+        while self.current.kind != TokenKind::RightCurly {
+            let variant = self.identifier()?;
+            let variant_id = if let TokenKind::Identifier(id) = variant.kind { id } else { unreachable!() };
+            if variants.iter().any(|(v, _)| *v == variant_id) {
+                return Err(Error::RepeatedVariant(variant.line, variant.col, variant.len, variant_id.to_string()));
+            }
+            // Variants can carry values e.g. Warn(string)
+            let mut payload = vec![];
+            if self.present(TokenKind::LeftParen)? != 0 {
+                while self.current.kind != TokenKind::RightParen {
+                    payload.push(self.annotation()?);
+                    if self.present(TokenKind::Comma)? == 0 { break }
+                }
+                self.consume(TokenKind::RightParen)?;
+            }
+            kind.variants.push((variant_id.to_string(), payload.len()));
+            variants.push((variant_id, payload));
+            self.present(TokenKind::Comma)?;
+            self.skip_delims()?;
+        }
+        self.consume(TokenKind::RightCurly)?;
+        self.enums.insert(id, variants);
+        self.emit_constant(Value::Enum(Rc::new(kind)), name.col, name.len);
+        self.end_statement()?;
+        if !self.returns.is_empty() {
+            // Enums within functions are local
+            self.add_local(id, Type::Enum(id.to_string()), name.col, name.len);
+            return Ok(());
+        }
+        let global = self.identifier_constant(name)?;
+        if !doc.is_empty() {
+            self.chunk.docs.insert(id.to_string(), doc.join("\n"));
+        }
+        self.define_variable(global)
+    }
+
+    fn import_declaration(&mut self) -> Result<(), Error> {
+        // For importing a whole module e.g. import "common.psi" as common
+        let path = self.module()?.0;
+        self.consume(TokenKind::As)?;
+        let name = self.identifier()?;
+        self.end_statement()?;
+        self.define(&name, Type::Module(path))
+    }
+
+    fn selective_import(&mut self) -> Result<(), Error> {
+        // For importing certain names from a module e.g. from "common.psi" import host, port
+        let (_, types) = self.module()?;
+        self.consume(TokenKind::Import)?;
+        loop {
+            let name = self.identifier()?;
+            let id = if let TokenKind::Identifier(id) = name.kind { id } else { unreachable!() };
+            let kind = types.get(id).cloned().ok_or_else(|| {
+                Error::UndefinedProperty(name.line, name.col, name.len, id.to_string())
+            })?;
+            let idx = self.identifier_constant(name.clone())?;
+            self.emit_byte(OpCode::OpDup, name.col, name.len);
+            self.emit_byte(OpCode::OpGetProperty(idx), name.col, name.len);
+            self.define(&name, kind)?;
+            if self.present(TokenKind::Comma)? == 0 { break }
+        }
+        self.end_statement()?;
+        // Discard the module itself
+        self.emit_byte(OpCode::OpPop, self.get_back().unwrap().col, 0);
+        Ok(())
+    }
+
+    fn module(&mut self) -> Result<(String, HashMap<String, Type>), Error> {
+        // Compile a module (or find it in the cache), and emit the import of it
+        let token = self.current.clone();
+        let name = if let TokenKind::String(name) = &token.kind { name.to_string() } else {
+            self.consume(TokenKind::String("".into()))?;
+            unreachable!()
+        };
+        self.advance()?;
+        let path = self.loader.borrow().resolve(&name, &self.file).ok_or_else(|| {
+            Error::ModuleNotFound(token.line, token.col, token.len, name.clone())
+        })?;
+        let cached = self.loader.borrow().get(&path);
+        let loaded = match cached {
+            Some(loaded) => loaded,
+            None => self.load(&path, &token)?,
+        };
+        let idx = self.chunk.add_constant(Value::Function(loaded.body.clone()));
+        self.emit_byte(OpCode::OpImport(idx), token.col, token.len);
+        Ok((path.display().to_string(), loaded.types))
+    }
+
+    fn load(&mut self, path: &Path, token: &Token) -> Result<Loaded, Error> {
+        // Compile a module from its file
+        let name = module::name(path);
+        self.loader.borrow_mut().start(path).map_err(|chain| {
+            Error::CyclicImport(token.line, token.col, token.len, chain)
+        })?;
+        let src = if let Ok(src) = fs::read_to_string(path) { src } else {
+            self.loader.borrow_mut().finish(path, None);
+            return Err(Error::ModuleNotFound(token.line, token.col, token.len, name));
+        };
+        // Modules are compiled separately, into their own namespace
+        let key = path.display().to_string();
+        let mut compiler = Compiler::new(Lexer::new(&src));
+        compiler.file = path.to_path_buf();
+        compiler.loader = self.loader.clone();
+        compiler.chunk.module = Some(key.clone());
+        if let Err(error) = compiler.compile() {
+            self.loader.borrow_mut().finish(path, None);
+            return Err(Error::InModule(key, Box::new(error)));
+        }
+        // Once it has run, the module gives back itself
+        let warnings = compiler.warnings.drain(..);
+        self.warnings.extend(warnings.map(|w| Error::InModule(key.clone(), Box::new(w))));
+        let module = Module { name: name.clone(), path: key };
+        compiler.emit_constant(Value::Module(Rc::new(module)), 1, 0);
+        compiler.emit_byte(OpCode::OpReturn, 1, 0);
+        let types = compiler.globals.iter().map(|(k, v)| (k.to_string(), v.clone())).collect();
+        let body = Function { 
+            name, params: vec![], required: 0, variadic: false, generator: false, chunk: Rc::new(compiler.chunk) 
+        };
+        let loaded = Loaded { body: Rc::new(body), types };
+        self.loader.borrow_mut().finish(path, Some(loaded.clone()));
+        Ok(loaded)
+    }
+
+    fn identifier(&mut self) -> Result<Token<'src>, Error> {
+        // Consume an identifier, giving it back
+        let token = self.current.clone();
+        if let TokenKind::Identifier(_) = token.kind {
+            self.advance()?;
+            Ok(token)
+        } else {
+            self.consume(TokenKind::Identifier(""))?;
+            unreachable!()
+        }
+    }
+
+    fn define(&mut self, name: &Token<'src>, kind: Type) -> Result<(), Error> {
+        // Move the value on top of the stack into a new variable, these are local in functions
+        let id = if let TokenKind::Identifier(id) = name.kind { id } else { unreachable!() };
+        if !self.returns.is_empty() {
+            self.add_local(id, kind, name.col, name.len);
+            return Ok(());
+        }
+        self.check_const(name)?;
+        self.globals.insert(id, kind);
+        let global = self.identifier_constant(name.clone())?;
+        self.define_variable(global)
+    }
+
+    fn function(
+        &mut self, name: &'src str, start: &Token<'src>, receiver: Option<Type>
+    ) -> Result<(Type, Function), Error> {
+        // Compile the parameters and body of a function
+        self.consume(TokenKind::LeftParen)?;
+        let (mut params, mut defaults, mut variadic) = (vec![], vec![], false);
+        while self.current.kind != TokenKind::RightParen {
+            // A rest parameter collects any extra arguments into a list e.g. ...extra
+            variadic = self.present(TokenKind::DotDotDot)? != 0;
+            let param = self.current.clone();
+            let id = if let TokenKind::Identifier(id) = param.kind { id } else {
+                self.consume(TokenKind::Identifier(""))?;
+                unreachable!()
+            };
+            self.advance()?;
+            let kind = if self.present(TokenKind::Colon)? != 0 { 
+                self.annotation()? 
+            } else if variadic {
+                Type::List(Box::new(Type::Any))
+            } else if let (Some(receiver), "self", true) = (&receiver, id, params.is_empty()) {
+                // Methods know the type of the instance they are called on
+                receiver.clone()
+            } else { 
+                Type::Any 
+            };
+            if !variadic && self.present(TokenKind::Equal)? != 0 {
+                // Defaults are compiled into the body, once all the parameters are known
+                defaults.push((params.len(), param, self.buffer()?));
+            } else if !variadic && !defaults.is_empty() {
+                // Parameters with defaults can't be followed by ones without
+                return Err(Error::ExpectedToken("'='".to_string(), self.current.line, self.current.col, 1));
+            }
+            params.push(Local { name: id, kind, constant: None });
+            if variadic || self.present(TokenKind::Comma)? == 0 { break }
+        }
+        self.consume(TokenKind::RightParen)?;
+        let returns = if self.present(TokenKind::Arrow)? != 0 {
+            self.annotation()?
+        } else {
+            Type::Any
+        };
+        let names: Vec<String> = params.iter().map(|p| p.name.to_string()).collect();
+        let required = params.len() - defaults.len() - variadic as usize;
+        let signature = Params { 
+            kinds: params.iter().map(|p| p.kind.clone()).collect(), 
+            names: names.clone(), 
+            required, 
+            variadic,
+        };
+        let kind = Type::Function(signature.clone(), Box::new(returns.clone()));
+        // Knowing the type before the body allows recursive calls to be checked
+        if self.returns.is_empty() && !name.is_empty() {
+            self.globals.insert(name, kind.clone());
+        }
+        // The body gets its own chunk, with the parameters as its first locals
+        // (followed by whether each parameter with a default was left out)
+        let mut body = Chunk::new(start.line);
+        body.module = self.chunk.module.clone();
+        let chunk = std::mem::replace(&mut self.chunk, body);
+        let locals = std::mem::replace(&mut self.locals, params);
+        for _ in &defaults {
+            self.locals.push(Local { name: "", kind: Type::Boolean, constant: None });
+        }
+        let types = std::mem::take(&mut self.types);
+        let yields = std::mem::replace(&mut self.yields, false);
+        // What a generator returns isn't what calling it gives back, so it isn't checked
+        self.returns.push(if returns == Type::Generator { Type::Any } else { returns.clone() });
+        for (flag, (slot, param, tokens)) in defaults.into_iter().enumerate() {
+            self.default(slot as u16, (names.len() + flag) as u16, &param, tokens)?;
+        }
+        self.block()?;
+        let value = self.pop_type();
+        let generator = std::mem::replace(&mut self.yields, yields);
+        if !generator {
+            self.expect_type(&returns, &value)?;
+        } else if !Type::Generator.accepts(&returns) {
+            return Err(Error::MismatchedTypes(
+                start.line, start.col, start.len,
+                format!("Functions that yield give back a generator, not {}", returns)
+            ));
+        }
+        self.emit_byte(OpCode::OpReturn, start.col, start.len);
+        self.returns.pop();
+        self.types = types;
+        self.locals = locals;
+        let chunk = std::mem::replace(&mut self.chunk, chunk);
+        let function = Function { 
+            name: name.to_string(), params: names, required, variadic, generator, chunk: Rc::new(chunk) 
+        };
+        if !generator { return Ok((kind, function)) }
+        // Calling a generator function gives back a generator
+        let kind = Type::Function(signature, Box::new(Type::Generator));
+        if self.returns.is_empty() && !name.is_empty() {
+            self.globals.insert(name, kind.clone());
+        }
+        Ok((kind, function))
+    }
+
+    fn default(&mut self, slot: u16, flag: u16, param: &Token, tokens: Vec<Token<'src>>) -> Result<(), Error> {
+        // Work out the value of a parameter that was left out when the function was called
+        self.emit_byte(OpCode::OpGetLocal(flag), param.col, param.len);
+        let given = self.emit_jump(OpCode::OpJumpIfFalse(0), param.col, param.len);
+        self.emit_byte(OpCode::OpPop, param.col, param.len);
+        self.replay(tokens)?;
+        let value = self.pop_type();
+        self.expect_type(&self.locals[slot as usize].kind.clone(), &value)?;
+        self.emit_byte(OpCode::OpSetLocal(slot), param.col, param.len);
+        self.emit_byte(OpCode::OpPop, param.col, param.len);
+        let done = self.emit_jump(OpCode::OpJump(0), param.col, param.len);
+        self.patch_jump(given);
+        self.emit_byte(OpCode::OpPop, param.col, param.len);
+        self.patch_jump(done);
+        Ok(())
+    }
+
+    fn buffer(&mut self) -> Result<Vec<Token<'src>>, Error> {
+        // Collect the tokens of an expression within a list, so it can be compiled later on
+        let (mut tokens, mut depth) = (vec![], 0);
+        loop {
+            match self.current.kind {
+                Tk::Comma | Tk::RightParen | Tk::EOI if depth == 0 => break,
+                Tk::LeftParen | Tk::LeftSquare | Tk::LeftCurly => depth += 1,
+                Tk::RightParen | Tk::RightSquare | Tk::RightCurly => depth -= 1,
+                _ => (),
+            }
+            tokens.push(self.current.clone());
+            self.advance()?;
+        }
+        if tokens.is_empty() {
+            let current = &self.current;
+            return Err(Error::ExpectedExpression(current.line, current.col, current.len));
+        }
+        Ok(tokens)
+    }
+
+    fn replay(&mut self, tokens: Vec<Token<'src>>) -> Result<(), Error> {
+        // Compile an expression from tokens that were collected earlier
+        let mut tokens: VecDeque<Token<'src>> = tokens.into();
+        let last = tokens.back().unwrap();
+        let end = Token { 
+            kind: Tk::Delim, 
+            span: last.span.end..last.span.end, 
+            len: 0, 
+            line: last.line, 
+            col: last.col + last.len,
+        };
+        // Once the expression is done, carry on from where the compiler was
+        let first = tokens.pop_front().unwrap();
+        tokens.push_back(end);
+        tokens.push_back(std::mem::replace(&mut self.current, first));
+        tokens.extend(self.replay.drain(..));
+        self.replay = tokens;
+        self.expression()?;
+        self.consume(Tk::Delim)?;
+        Ok(())
+    }
+
+    fn block(&mut self) -> Result<(), Error> {
+        // Compile a block, the value of the final expression is left on the stack
+        self.consume(TokenKind::LeftCurly)?;
+        self.depth += 1;
+        self.skip_delims()?;
+        let scope = self.locals.len();
+        let mut returned = false;
+        loop {
+            if self.current.kind == TokenKind::RightCurly {
+                // There was no final expression, so the block gives back nil
+                // (unless a return statement means the end is never reached)
+                let close = self.current.clone();
+                self.emit_byte(OpCode::OpNil, close.col, close.len);
+                self.push_typed(if returned { Type::Any } else { Type::Nil }, &close);
+                break;
+            } else if let Tk::Var | Tk::Const | Tk::Fn | Tk::Struct | Tk::Enum |
+                Tk::Import | Tk::From | Tk::Print | Tk::Return | Tk::Try | Tk::For | Tk::Assert = self.current.kind {
+                returned = self.current.kind == Tk::Return;
+                self.declaration()?;
+            } else {
+                self.expression()?;
+                self.end_statement()?;
+                self.skip_delims()?;
+                if self.current.kind == TokenKind::RightCurly { break }
+                self.pop_type();
+                self.emit_byte(OpCode::OpPop, self.get_back().unwrap().col, 0);
+            }
+            self.skip_delims()?;
+        }
+        // Variables declared within the block go out of scope at its end
+        if self.locals.len() > scope {
+            self.pop_locals(self.locals.len() - scope, self.current.col);
+        }
+        self.depth -= 1;
+        self.consume(TokenKind::RightCurly)?;
+        Ok(())
+    }
+
+    fn return_statement(&mut self) -> Result<(), Error> {
+        // Give back a value from a function
+        let keyword = self.current.clone();
+        self.advance()?;
+        let returns = if let Some(returns) = self.returns.last() { returns.clone() } else {
+            return Err(Error::ReturnOutsideFunction(keyword.line, keyword.col, keyword.len));
+        };
+        if let Tk::Delim | Tk::RightCurly = self.current.kind {
+            self.emit_byte(OpCode::OpNil, keyword.col, keyword.len);
+            self.push_typed(Type::Nil, &keyword);
+        } else {
+            self.expression()?;
+        }
+        if self.current.kind == Tk::Comma {
+            // Returning several values gives back a tuple
+            let mut items = vec![self.pop_type().kind];
+            while self.present(Tk::Comma)? != 0 {
+                self.expression()?;
+                items.push(self.pop_type().kind);
+            }
+            self.emit_byte(OpCode::OpTuple(items.len() as u16), keyword.col, keyword.len);
+            self.push_typed(Type::Tuple(items), &keyword);
+        }
+        let value = self.pop_type();
+        self.expect_type(&returns, &value)?;
+        self.end_statement()?;
+        self.emit_byte(OpCode::OpReturn, keyword.col, keyword.len);
+        Ok(())
+    }
+
+    fn try_statement(&mut self) -> Result<(), Error> {
+        // For exception handling e.g. try { connect() } catch e { print e.message }
+        let keyword = self.current.clone();
+        let (col, len) = (keyword.col, keyword.len);
+        self.advance()?;
+        // Errors within the try block jump to the catch clause, and returns to the finally clause
+        let handler = self.emit_jump(OpCode::OpTry(0, 0), col, len);
+        let mut returns = vec![handler];
+        self.block_statement()?;
+        self.emit_byte(OpCode::OpEndTry, col, len);
+        // Nothing is left pending when a clause finishes normally
+        self.emit_byte(OpCode::OpNil, col, len);
+        self.emit_constant(Value::Integer(PENDING_NONE), col, len);
+        let mut finished = vec![self.emit_jump(OpCode::OpJump(0), col, len)];
+        self.patch_jump(handler);
+        let catch = self.current.clone();
+        if self.present(Tk::Catch)? != 0 {
+            // The error is bound to a name, or discarded
+            let bound = if let Tk::Identifier(name) = self.current.kind {
+                let name_token = self.current.clone();
+                self.advance()?;
+                self.add_local(name, Type::Any, name_token.col, name_token.len);
+                true
+            } else {
+                self.emit_byte(OpCode::OpPop, catch.col, catch.len);
+                false
+            };
+            // Errors within the catch clause still run the finally clause
+            let handler = self.emit_jump(OpCode::OpTry(0, 0), catch.col, catch.len);
+            self.block_statement()?;
+            self.emit_byte(OpCode::OpEndTry, catch.col, catch.len);
+            if bound { self.pop_locals(1, catch.col) }
+            self.emit_byte(OpCode::OpNil, catch.col, catch.len);
+            self.emit_constant(Value::Integer(PENDING_NONE), catch.col, catch.len);
+            finished.push(self.emit_jump(OpCode::OpJump(0), catch.col, catch.len));
+            if bound {
+                // Returning from the catch clause lets go of the error on the way
+                self.patch_finally(handler);
+                self.emit_byte(OpCode::OpPopLocals(1), catch.col, 0);
+                finished.push(self.emit_jump(OpCode::OpJump(0), catch.col, catch.len));
+            } else {
+                returns.push(handler);
+            }
+            self.patch_jump(handler);
+            if bound { self.emit_byte(OpCode::OpPopLocals(1), catch.col, 0) }
+        }
+        // An uncaught error is kept pending until the finally clause has run
+        self.emit_byte(OpCode::OpPending, col, len);
+        for jump in finished {
+            self.patch_jump(jump);
+        }
+        for handler in returns {
+            self.patch_finally(handler);
+        }
+        let pending = self.add_local("", Type::Any, col, len);
+        let value = self.add_local("", Type::Any, col, len);
+        if self.present(Tk::Finally)? != 0 {
+            self.block_statement()?;
+        }
+        // Carry on with whatever was pending now that everything has been cleaned up
+        self.emit_byte(OpCode::OpGetLocal(value), col, len);
+        self.emit_byte(OpCode::OpGetLocal(pending), col, len);
+        self.emit_byte(OpCode::OpFinally, col, len);
+        self.pop_locals(2, col);
+        self.end_statement()
+    }
+
+    fn for_statement(&mut self) -> Result<(), Error> {
+        // For looping over a collection e.g. for host in hosts { print host }
+        let keyword = self.current.clone();
+        let (col, len) = (keyword.col, keyword.len);
+        self.advance()?;
+        // A second name gets the index (or key) along with each value e.g. for i, host in hosts
+        let mut names = vec![self.identifier()?];
+        if self.present(Tk::Comma)? != 0 {
+            names.push(self.identifier()?);
+        }
+        self.consume(Tk::In)?;
+        self.expression()?;
+        let subject = self.pop_type();
+        let pairs = names.len() == 2;
+        self.emit_byte(OpCode::OpIter(pairs), subject.col, subject.len);
+        let kinds = self.iterated(&subject, pairs)?;
+        // The iterator stays on the stack while the loop runs
+        let top = self.chunk.code.len();
+        let next = self.emit_jump(OpCode::OpNext(0), col, len);
+        let check = self.emit_jump(OpCode::OpCheckNext(0), col, len);
+        for (name, kind) in names.iter().zip(kinds) {
+            let id = if let Tk::Identifier(id) = name.kind { id } else { unreachable!() };
+            self.add_local(id, kind, name.col, name.len);
+        }
+        self.block_statement()?;
+        self.pop_locals(names.len(), col);
+        self.emit_loop(top, col, len);
+        self.patch_jump(next);
+        self.patch_jump(check);
+        self.emit_byte(OpCode::OpPop, col, len);
+        self.end_statement()
+    }
+
+    fn iterated(&self, subject: &Typed, pairs: bool) -> Result<Vec<Type>, Error> {
+        // Work out the types of the variables of a for loop
+        let (key, value) = match &subject.kind {
+            Type::List(item) => (Type::Integer, *item.clone()),
+            Type::Tuple(items) => (Type::Integer, items.iter().cloned().reduce(Type::join).unwrap_or(Type::Any)),
+            // Looping over a map on its own gives back the keys
+            Type::Map(key, _) if !pairs => (Type::Nil, *key.clone()),
+            Type::Map(key, value) => (*key.clone(), *value.clone()),
+            Type::String => (Type::Integer, Type::String),
+            Type::Range if !pairs => (Type::Nil, Type::Integer),
+            Type::Generator if !pairs => (Type::Nil, Type::Any),
+            Type::Instance(_) if !pairs => (Type::Nil, Type::Any),
+            Type::Any => (Type::Any, Type::Any),
+            kind => return Err(Error::MismatchedTypes(
+                subject.line, subject.col, subject.len, 
+                if pairs {
+                    format!("Can't loop over the index and value of {}", kind)
+                } else {
+                    format!("Can't loop over {}", kind)
+                }
+            )),
+        };
+        Ok(if pairs { vec![key, value] } else { vec![value] })
+    }
+
+    fn block_statement(&mut self) -> Result<(), Error> {
+        // Compile a block whose value isn't needed
+        self.block()?;
+        self.pop_type();
+        self.emit_byte(OpCode::OpPop, self.get_back().unwrap().col, 0);
+        Ok(())
+    }
+
+    fn annotation(&mut self) -> Result<Type, Error> {
+        // Read in a type annotation e.g. int, [string], {string: int} or fn(string) -> bool
+        let token = self.current.clone();
+        self.advance()?;
+        match token.kind {
+            TokenKind::Identifier(name) => match Type::from_name(name) {
+                Some(kind) => Ok(kind),
+                None if self.structs.contains_key(name) => Ok(Type::Instance(name.to_string())),
+                // Values of an enum are typed by the name of the enum, just like instances
+                None if self.enums.contains_key(name) => Ok(Type::Instance(name.to_string())),
+                None => Err(Error::UnknownType(token.line, token.col, token.len, name.to_string())),
+            },
+            TokenKind::Nil => Ok(Type::Nil),
+            TokenKind::LeftParen => {
+                let mut items = vec![];
+                while self.current.kind != TokenKind::RightParen {
+                    items.push(self.annotation()?);
+                    if self.present(TokenKind::Comma)? == 0 { break }
+                }
+                self.consume(TokenKind::RightParen)?;
+                Ok(Type::Tuple(items))
+            }
+            TokenKind::LeftCurly => {
+                let key = self.annotation()?;
+                self.consume(TokenKind::Colon)?;
+                let value = self.annotation()?;
+                self.consume(TokenKind::RightCurly)?;
+                Ok(Type::Map(Box::new(key), Box::new(value)))
+            }
+            TokenKind::LeftSquare => {
+                let item = self.annotation()?;
+                self.consume(TokenKind::RightSquare)?;
+                Ok(Type::List(Box::new(item)))
+            }
+            TokenKind::Fn => {
+                self.consume(TokenKind::LeftParen)?;
+                let mut params = vec![];
+                while self.current.kind != TokenKind::RightParen {
+                    params.push(self.annotation()?);
+                    if self.present(TokenKind::Comma)? == 0 { break }
+                }
+                self.consume(TokenKind::RightParen)?;
+                let returns = if self.present(TokenKind::Arrow)? != 0 {
+                    self.annotation()?
+                } else {
+                    Type::Any
+                };
+                Ok(Type::Function(Params::positional(params), Box::new(returns)))
+            }
+            _ => Err(Error::ExpectedToken("type".to_string(), token.line, token.col, token.len)),
+        }
+    }
+
+    fn check_const(&self, name: &Token) -> Result<(), Error> {
+        // Refuse to assign to a variable that was declared as a constant
+        if let TokenKind::Identifier(id) = name.kind {
+            if let Some((line, col)) = self.consts.get(id) {
+                return Err(Error::ConstantAssignment(
+                    name.line, name.col, name.len, id.to_string(), *line, *col
+                ));
+            }
+        }
         Ok(())
     }
 
     fn print_statement(&mut self, col: usize) -> Result<(), Error> {
         // Consume a print statement and emit print operation
         self.expression()?;
-        self.consume(TokenKind::Delim)?;
+        self.pop_type();
+        self.end_statement()?;
         self.emit_byte(OpCode::OpPrint, col, 5);
         Ok(())
     }
 
+    fn assert_statement(&mut self) -> Result<(), Error> {
+        // Compile an assertion e.g. assert port > 1024, "Ports below 1024 need root"
+        let keyword = self.consume(TokenKind::Assert)?;
+        let start = self.current.clone();
+        let code = self.chunk.code.len();
+        self.expression()?;
+        self.condition()?;
+        let end = self.previous.clone().unwrap();
+        let (col, len) = self.span_from(&start);
+        let source = self.lexer.source(start.span.start..end.span.end).to_string();
+        let operands = if start.line == end.line { self.keep_operands(code) } else { 0 };
+        // Discard the condition and any operands when the assertion holds
+        let fail = self.emit_jump(OpCode::OpJumpIfFalse(0), keyword, 6);
+        for _ in 0..=operands {
+            self.emit_byte(OpCode::OpPop, keyword, 6);
+        }
+        let end_jump = self.emit_jump(OpCode::OpJump(0), keyword, 6);
+        self.patch_jump(fail);
+        self.emit_byte(OpCode::OpPop, keyword, 6);
+        // The message is only worked out when the assertion fails
+        let message = self.present(TokenKind::Comma)? != 0;
+        if message {
+            self.expression()?;
+            self.pop_type();
+        }
+        let idx = self.chunk.add_constant(Value::String(source));
+        self.emit_byte(OpCode::OpAssert(idx, operands as u16, message), col, len);
+        self.patch_jump(end_jump);
+        self.end_statement()
+    }
+
+    fn keep_operands(&mut self, start: usize) -> usize {
+        // Copy the operands of a comparison at the root of a condition, so they can be shown
+        let code = &self.chunk.code[start..];
+        let comparison = |op: &OpCode| matches!(
+            op, OpCode::OpEqual | OpCode::OpGreater | OpCode::OpLess | OpCode::OpContains
+        );
+        let root = match code {
+            [.., (_, _, op), (_, _, OpCode::OpNot)] if comparison(op) => self.chunk.code.len() - 2,
+            [.., (_, _, op)] if comparison(op) => self.chunk.code.len() - 1,
+            _ => return 0,
+        };
+        // With branches the last operation might not be the root
+        if code.iter().any(|(_, _, op)| matches!(
+            op, OpCode::OpJump(_) | OpCode::OpJumpIfFalse(_) | OpCode::OpJumpIfNil(_) | OpCode::OpLoop(_)
+        )) {
+            return 0;
+        }
+        let ops: Vec<_> = self.chunk.code.drain(root..).collect();
+        let lines: Vec<_> = self.chunk.lines.drain(root..).collect();
+        self.chunk.write(OpCode::OpDupPair, lines[0], ops[0].0, ops[0].1);
+        for ((col, len, op), line) in ops.into_iter().zip(lines) {
+            self.chunk.write(op, line, col, len);
+        }
+        2
+    }
+
     fn parse_precedence(&mut self, precedence: Precedence) -> Result<(), Error> {
         // Parse a precedence level
         let precedence = precedence as u8;
@@ -103,22 +1118,26 @@ impl Compiler {
         // Get the left hand side
         let current = self.get_back().unwrap();
         // Handle a prefix rule (allows for negation)
-        let prefix_rule = get_rule(current.kind).prefix;
+        let prefix_rule = get_rule(&current.kind).prefix;
         if let Some(prefix) = prefix_rule {
             // Work out if it is possible to assign to an expression
             let can_assign = precedence <= Precedence::Assignment as u8;
             prefix(self, can_assign)?;
+            self.locate(&current);
             // Walk down the precedence
-            while precedence <= get_rule(self.get().unwrap().kind).prec as u8 {
+            while precedence <= get_rule(&self.current.kind).prec as u8 {
                 self.advance()?;
                 // Handle an infix rule (allows for arithmetic operations)
-                let infix_rule = get_rule(self.get_back().unwrap().kind).infix;
+                let infix_rule = get_rule(&self.get_back().unwrap().kind).infix;
                 if let Some(infix) = infix_rule {
                     infix(self, can_assign)?;
+                    self.locate(&current);
                 }
             }
-            if !can_assign && self.present(TokenKind::Equal)? != 0 {
-                Err(Error::InvalidAssignmentTarget(current.line, current.col, current.len))
+            // Anything assignable will have consumed the assignment already
+            if can_assign && self.current.kind.is_assignment() {
+                let (col, len) = self.span_from(&current);
+                Err(Error::InvalidAssignmentTarget(current.line, col, len))
             } else {
                 Ok(())
             }
@@ -130,23 +1149,12 @@ impl Compiler {
 
     pub fn identifier_constant(&mut self, token: Token) -> Result<u16, Error> {
         if let Token { kind: TokenKind::Identifier(id), .. } = token {
-            Ok(self.chunk.add_constant(Value::String(id)))
+            Ok(self.chunk.add_constant(Value::String(id.to_string())))
         } else {
             unreachable!()
         }
     }
 
-    pub fn parse_variable(&mut self) -> Result<u16, Error> {
-        let tok = self.get().unwrap();
-        if let Token { kind: TokenKind::Identifier(_), .. } = tok {
-            self.advance()?;
-            self.identifier_constant(tok)
-        } else {
-            self.consume(TokenKind::Identifier("".to_string()))?;
-            unreachable!()
-        }
-    }
-
     pub fn define_variable(&mut self, global: u16) -> Result<(), Error> {
         let semi = self.get_back().unwrap();
         self.emit_byte(OpCode::OpDefineGlobal(global), semi.col, semi.len);
@@ -156,17 +1164,23 @@ impl Compiler {
     pub fn binary(&mut self, _: bool) -> Result<(), Error> {
         // Compile a binary operation
         let op_type = self.get_back().unwrap();
-        let rule = get_rule(op_type.kind.clone());
+        let rule = get_rule(&op_type.kind);
         // Move onto the lower precedence
         self.parse_precedence(rule.prec.shift())?;
         // Emit the correct operation
-        self.emit_byte(match op_type.kind {
+        let op = match op_type.kind {
             Tk::Plus => OpCode::OpAdd,
             Tk::Minus => OpCode::OpSub,
             Tk::Asterisk => OpCode::OpMul,
             Tk::Slash => OpCode::OpDiv,
+            Tk::Div => OpCode::OpIntDiv,
             Tk::Percent => OpCode::OpMod,
             Tk::Hat => OpCode::OpPow,
+            Tk::Ampersand => OpCode::OpBitAnd,
+            Tk::Pipe => OpCode::OpBitOr,
+            Tk::HatHat => OpCode::OpBitXor,
+            Tk::LessLess => OpCode::OpShiftLeft,
+            Tk::GreaterGreater => OpCode::OpShiftRight,
             Tk::Equals => OpCode::OpEqual,
             Tk::Greater => OpCode::OpGreater,
             Tk::Less => OpCode::OpLess,
@@ -174,7 +1188,9 @@ impl Compiler {
             Tk::GreaterEq => OpCode::OpLess,
             Tk::LessEq => OpCode::OpGreater,
             _ => unreachable!(),
-        }, op_type.col, op_type.len);
+        };
+        self.operate(&op, &op_type)?;
+        self.emit_byte(op, op_type.col, op_type.len);
         // Inverse specific operations (more efficent than direct operations)
         if let Tk::NotEquals | Tk::GreaterEq | Tk::LessEq = op_type.kind {
             self.emit_byte(OpCode::OpNot, op_type.col, op_type.len);
@@ -185,20 +1201,34 @@ impl Compiler {
     pub fn literal(&mut self, _: bool) -> Result<(), Error> {
         // Emit a literal
         let val = self.get_back().unwrap();
-        self.emit_byte(match val.kind {
-            Tk::False => OpCode::OpFalse,
-            Tk::True => OpCode::OpTrue,
-            Tk::Nil => OpCode::OpNil,
+        let (op, kind) = match val.kind {
+            Tk::False => (OpCode::OpFalse, Type::Boolean),
+            Tk::True => (OpCode::OpTrue, Type::Boolean),
+            Tk::Nil => (OpCode::OpNil, Type::Nil),
             _ => unreachable!(),
-        }, val.col, val.len);
+        };
+        self.emit_byte(op, val.col, val.len);
+        self.push_type(kind);
         Ok(())
     }
 
     pub fn number(&mut self, _: bool) -> Result<(), Error> {
         // Emit a number constant
         let val = self.get_back().unwrap();
-        if let Tk::Number(float) = val.kind {
-            self.emit_constant(Value::Number(float), val.col, val.len);
+        match val.kind {
+            // This only comes from a literal one too large, that wasn't negated straight away
+            Tk::Integer(i64::MIN) => return Err(Error::IntegerOverflow(
+                val.line, val.col, val.len, "literal is too large".to_string()
+            )),
+            Tk::Integer(int) => {
+                self.emit_constant(Value::Integer(int), val.col, val.len);
+                self.push_type(Type::Integer);
+            }
+            Tk::Number(float) => {
+                self.emit_constant(Value::Number(float), val.col, val.len);
+                self.push_type(Type::Number);
+            }
+            _ => unreachable!(),
         }
         Ok(())
     }
@@ -206,39 +1236,580 @@ impl Compiler {
     pub fn string(&mut self, _: bool) -> Result<(), Error> {
         // Emit a string constant
         if let Some(Token{ kind: Tk::String(s), col, len, .. }) = self.get_back() {
-            self.emit_constant(Value::String(s), col, len);
+            self.emit_constant(Value::String(s.into_owned()), col, len);
+            self.push_type(Type::String);
         }
         Ok(())
     }
 
     pub fn named_variable(&mut self, name: Token, can_assign: bool) -> Result<(), Error> {
-        let arg = self.identifier_constant(name.clone())?;
-        if self.present(TokenKind::Equal)? == 0 {
-            self.emit_byte(OpCode::OpGetGlobal(arg), name.col, name.len);
-        } else if can_assign {
+        let (local, get, set, kind) = self.resolve(&name)?;
+        // Constants can't be assigned to
+        if can_assign && self.current.kind.is_assignment() {
+            self.check_assignable(&name, local)?;
+        }
+        if can_assign && self.present(TokenKind::Equal)? != 0 {
+            // Assign to the variable
             self.expression()?;
-            self.emit_byte(OpCode::OpSetGlobal(arg), name.col, name.len);
+            let value = self.pop_type();
+            self.expect_value(&kind, &value)?;
+            self.emit_byte(set, name.col, name.len);
+            self.types.push(value);
+        } else if let Some((op, token)) = self.compound_assignment(can_assign)? {
+            // Operate on the variable and then assign the result to it
+            self.emit_byte(get, name.col, name.len);
+            self.push_typed(kind.clone(), &name);
+            self.expression()?;
+            self.operate(&op, &token)?;
+            let value = self.pop_type();
+            self.emit_byte(op, token.col, token.len);
+            self.expect_value(&kind, &value)?;
+            self.emit_byte(set, name.col, name.len);
+            self.types.push(value);
+        } else {
+            self.emit_byte(get, name.col, name.len);
+            self.push_typed(kind, &name);
+        }
+        Ok(())
+    }
+
+    fn resolve(&mut self, name: &Token) -> Result<(Option<u16>, OpCode, OpCode, Type), Error> {
+        // Work out if this is a local or global variable, giving back how to get and set it
+        let local = self.resolve_local(name);
+        if let Some(slot) = local {
+            let kind = self.locals[slot as usize].kind.clone();
+            Ok((local, OpCode::OpGetLocal(slot), OpCode::OpSetLocal(slot), kind))
+        } else {
+            let kind = match name.kind {
+                TokenKind::Identifier(id) => self.globals.get(id).cloned(),
+                _ => None,
+            };
+            let arg = self.identifier_constant(name.clone())?;
+            Ok((None, OpCode::OpGetGlobal(arg), OpCode::OpSetGlobal(arg), kind.unwrap_or(Type::Any)))
+        }
+    }
+
+    fn check_assignable(&self, name: &Token, local: Option<u16>) -> Result<(), Error> {
+        // Refuse to assign to a local or global constant
+        match local.and_then(|slot| self.locals[slot as usize].constant) {
+            Some((line, col)) => if let TokenKind::Identifier(id) = name.kind {
+                return Err(Error::ConstantAssignment(
+                    name.line, name.col, name.len, id.to_string(), line, col
+                ));
+            }
+            None if local.is_none() => self.check_const(name)?,
+            None => (),
         }
         Ok(())
     }
 
+    fn resolve_local(&self, name: &Token) -> Option<u16> {
+        // Find the slot of the innermost local variable with this name
+        if let TokenKind::Identifier(id) = name.kind {
+            self.locals.iter().rposition(|l| l.name == id).map(|slot| slot as u16)
+        } else {
+            None
+        }
+    }
+
+    fn add_local(&mut self, name: &'src str, kind: Type, col: usize, len: usize) -> u16 {
+        // Move the value on top of the stack into a new local variable
+        self.emit_byte(OpCode::OpDefineLocal, col, len);
+        self.locals.push(Local { name, kind, constant: None });
+        (self.locals.len() - 1) as u16
+    }
+
+    fn pop_locals(&mut self, count: usize, col: usize) {
+        // Discard the most recently defined local variables
+        self.emit_byte(OpCode::OpPopLocals(count as u16), col, 0);
+        self.locals.truncate(self.locals.len() - count);
+    }
+
+    fn compound_assignment(&mut self, can_assign: bool) -> Result<Option<(OpCode, Token<'src>)>, Error> {
+        // Consume a compound assignment e.g. +=, giving back the operation it performs
+        if !can_assign { return Ok(None) }
+        let op = match self.current.kind {
+            Tk::PlusEqual => OpCode::OpAdd,
+            Tk::MinusEqual => OpCode::OpSub,
+            Tk::AsteriskEqual => OpCode::OpMul,
+            Tk::SlashEqual => OpCode::OpDiv,
+            Tk::PercentEqual => OpCode::OpMod,
+            Tk::HatEqual => OpCode::OpPow,
+            _ => return Ok(None),
+        };
+        let token = self.current.clone();
+        self.advance()?;
+        Ok(Some((op, token)))
+    }
+
+    fn span_from(&self, start: &Token) -> (usize, usize) {
+        // Work out the span from the start token to the last token
+        match &self.previous {
+            Some(end) if end.line == start.line && end.col >= start.col => 
+                (start.col, end.col + end.len - start.col),
+            _ => (start.col, start.len),
+        }
+    }
+
     pub fn variable(&mut self, can_assign: bool) -> Result<(), Error> {
         self.named_variable(self.get_back().unwrap(), can_assign)
     }
 
-    pub fn grouping(&mut self, _: bool) -> Result<(), Error> {
-        // Compile a grouping operation, this is for brackets
+    pub fn conditional(&mut self, _: bool) -> Result<(), Error> {
+        // Compile an if expression e.g. if debug then 1 else 4
+        let keyword = self.get_back().unwrap();
         self.expression()?;
+        self.condition()?;
+        self.consume(Tk::Then)?;
+        self.branches(keyword, Tk::Else)
+    }
+
+    pub fn ternary(&mut self, _: bool) -> Result<(), Error> {
+        // Compile a ternary expression e.g. debug ? 1 : 4
+        let question = self.get_back().unwrap();
+        self.condition()?;
+        self.branches(question, Tk::Colon)
+    }
+
+    pub fn coalesce(&mut self, _: bool) -> Result<(), Error> {
+        // Compile a nil-coalescing operation e.g. port ?? 8080, the right side only runs if needed
+        let op = self.get_back().unwrap();
+        let nil_jump = self.emit_jump(OpCode::OpJumpIfNil(0), op.col, op.len);
+        let end_jump = self.emit_jump(OpCode::OpJump(0), op.col, op.len);
+        self.patch_jump(nil_jump);
+        self.emit_byte(OpCode::OpPop, op.col, op.len);
+        // Chains are grouped to the right e.g. a ?? b ?? c
+        self.parse_precedence(Precedence::Coalesce)?;
+        self.patch_jump(end_jump);
+        let fallback = self.pop_type();
+        let value = self.pop_type();
+        let kind = if value.kind == Type::Nil { fallback.kind } else { value.kind.join(fallback.kind) };
+        self.types.push(Typed { kind, ..value });
+        Ok(())
+    }
+
+    pub fn optional(&mut self, _: bool) -> Result<(), Error> {
+        // Compile safe navigation e.g. config?.server or hosts?[0], which gives nil for a nil object
+        let op = self.get_back().unwrap();
+        if let Some(object) = self.types.last_mut().filter(|t| t.kind == Type::Nil) {
+            object.kind = Type::Any;
+        }
+        let skip = self.emit_jump(OpCode::OpJumpIfNil(0), op.col, op.len);
+        // Optional accesses can't be assigned to
+        if op.kind == Tk::QuestionDot {
+            self.dot(false)?;
+        } else {
+            self.index(false)?;
+        }
+        // A nil object skips the rest of the chain too, so a?.b.c is like a?.b?.c
+        while get_rule(&self.current.kind).prec == Precedence::Call {
+            self.advance()?;
+            if let Some(infix) = get_rule(&self.get_back().unwrap().kind).infix {
+                infix(self, false)?;
+            }
+        }
+        self.patch_jump(skip);
+        let result = self.pop_type();
+        self.types.push(Typed { kind: result.kind.join(Type::Nil), ..result });
+        Ok(())
+    }
+
+    fn branches(&mut self, op: Token, separator: Tk) -> Result<(), Error> {
+        // Compile the two branches of a conditional, the condition is on the stack
+        let then_jump = self.emit_jump(OpCode::OpJumpIfFalse(0), op.col, op.len);
+        self.emit_byte(OpCode::OpPop, op.col, op.len);
+        self.expression()?;
+        let then = self.pop_type();
+        let col = self.consume(separator)?;
+        let else_jump = self.emit_jump(OpCode::OpJump(0), col, 0);
+        self.patch_jump(then_jump);
+        self.emit_byte(OpCode::OpPop, op.col, op.len);
+        // The else branch swallows as much as it can, allowing for chaining
+        self.parse_precedence(Precedence::Ternary)?;
+        self.patch_jump(else_jump);
+        let otherwise = self.pop_type();
+        self.push_type(then.kind.join(otherwise.kind));
+        Ok(())
+    }
+
+    pub fn match_expr(&mut self, _: bool) -> Result<(), Error> {
+        // Compile a match expression e.g. match env { "prod" => 3, _ => 1 }
+        let keyword = self.get_back().unwrap();
+        self.expression()?;
+        // Keep the value being matched in a hidden local variable
+        let kind = self.pop_type().kind;
+        let subject = self.add_local("", kind.clone(), keyword.col, keyword.len);
+        let mut result: Option<Type> = None;
+        self.consume(Tk::LeftCurly)?;
+        self.skip_delims()?;
+        let (mut ends, mut seen, mut exhausted) = (vec![], vec![], false);
+        while self.current.kind != Tk::RightCurly && self.current.kind != Tk::EOI {
+            let start = self.current.clone();
+            let mut literals = vec![];
+            let mut catch_all = false;
+            // Compile the patterns, this leaves a boolean on the stack
+            let qualified = matches!(self.current.kind, Tk::Identifier(_)) && self.peek()?.kind == Tk::Dot;
+            let bound = match self.current.kind {
+                Tk::Identifier(name) if name != "_" && !qualified => {
+                    // Bind the value to a name for use in the guard and arm
+                    self.advance()?;
+                    self.emit_byte(OpCode::OpGetLocal(subject), start.col, start.len);
+                    self.add_local(name, kind.clone(), start.col, start.len);
+                    self.emit_byte(OpCode::OpTrue, start.col, start.len);
+                    catch_all = true;
+                    1
+                }
+                _ => {
+                    let (mut alternatives, mut matched) = (0, vec![]);
+                    let bound = loop {
+                        let bindings = match self.pattern(subject)? {
+                            Pattern::Literal(value) => { literals.push(value); vec![] }
+                            Pattern::CatchAll => { catch_all = true; vec![] }
+                            Pattern::Range => vec![],
+                            Pattern::Variant(bindings) => bindings,
+                        };
+                        alternatives += 1;
+                        let col = self.present(Tk::Pipe)?;
+                        if !bindings.is_empty() {
+                            // Only a lone pattern can bind what a variant carries
+                            if alternatives > 1 || col != 0 {
+                                return Err(Error::ExpectedToken(
+                                    "'=>' after a pattern that binds values".to_string(), start.line, start.col, start.len
+                                ));
+                            }
+                            // Move the values out from under the boolean, in order
+                            for (name, kind) in &bindings {
+                                self.add_local(name, kind.clone(), start.col, start.len);
+                            }
+                            break bindings.len();
+                        }
+                        if col == 0 { break 0 }
+                        // Try the next pattern if this one didn't match
+                        let next = self.emit_jump(OpCode::OpJumpIfFalse(0), col, 1);
+                        matched.push(self.emit_jump(OpCode::OpJump(0), col, 1));
+                        self.patch_jump(next);
+                        self.emit_byte(OpCode::OpPop, col, 1);
+                    };
+                    // Skip the remaining patterns once one has matched
+                    for jump in matched {
+                        self.patch_jump(jump);
+                    }
+                    bound
+                }
+            };
+            // Warn about arms that can never be reached
+            let (col, len) = self.span_from(&start);
+            let all_seen = !literals.is_empty() && literals.iter().all(|l| seen.contains(l));
+            if exhausted || (!catch_all && all_seen) {
+                self.warnings.push(Error::UnreachableArm(start.line, col, len));
+            }
+            // Compile the guard
+            let guard = self.present(Tk::If)?;
+            if guard != 0 {
+                let skip = self.emit_jump(OpCode::OpJumpIfFalse(0), guard, 2);
+                self.emit_byte(OpCode::OpPop, guard, 2);
+                self.expression()?;
+                self.condition()?;
+                self.patch_jump(skip);
+            } else {
+                exhausted |= catch_all;
+                seen.extend(literals);
+            }
+            // Compile the arm
+            let arrow = self.consume(Tk::FatArrow)?;
+            let next = self.emit_jump(OpCode::OpJumpIfFalse(0), arrow, 2);
+            self.emit_byte(OpCode::OpPop, arrow, 2);
+            self.expression()?;
+            let arm = self.pop_type().kind;
+            result = Some(match result {
+                Some(result) => result.join(arm),
+                None => arm,
+            });
+            if bound != 0 { self.pop_locals(bound, arrow); }
+            ends.push(self.emit_jump(OpCode::OpJump(0), arrow, 2));
+            self.patch_jump(next);
+            self.emit_byte(OpCode::OpPop, arrow, 2);
+            if bound != 0 { 
+                self.emit_byte(OpCode::OpPopLocals(bound as u16), arrow, 0);
+            }
+            // Arms are separated by commas and/or newlines
+            self.present(Tk::Comma)?;
+            self.skip_delims()?;
+        }
+        self.consume(Tk::RightCurly)?;
+        // Nothing matched, report it where the match began rather than where it ended
+        self.chunk.write(OpCode::OpNoMatch, keyword.line, keyword.col, keyword.len);
+        for end in ends {
+            self.patch_jump(end);
+        }
+        self.pop_locals(1, keyword.col);
+        self.push_type(result.unwrap_or(Type::Any));
+        Ok(())
+    }
+
+    fn pattern(&mut self, subject: u16) -> Result<Pattern<'src>, Error> {
+        // Compile a pattern into a test, leaving a boolean on the stack
+        let start = self.current.clone();
+        if let Tk::Identifier("_") = start.kind {
+            self.advance()?;
+            self.emit_byte(OpCode::OpTrue, start.col, start.len);
+            return Ok(Pattern::CatchAll);
+        }
+        self.emit_byte(OpCode::OpGetLocal(subject), start.col, start.len);
+        if let Tk::Identifier(_) = start.kind {
+            return self.variant_pattern(start);
+        }
+        let value = self.pattern_literal()?;
+        let inclusive = match self.current.kind {
+            Tk::DotDot => false,
+            Tk::DotDotEq => true,
+            _ => {
+                // Just a single literal value
+                let (col, len) = self.span_from(&start);
+                self.emit_constant(value.clone(), col, len);
+                self.emit_byte(OpCode::OpEqual, col, len);
+                return Ok(Pattern::Literal(value));
+            }
+        };
+        // A range of values
+        self.advance()?;
+        self.emit_constant(value, start.col, start.len);
+        let hi = self.pattern_literal()?;
+        let (col, len) = self.span_from(&start);
+        self.emit_constant(hi, col, len);
+        self.emit_byte(OpCode::OpInRange(inclusive), col, len);
+        Ok(Pattern::Range)
+    }
+
+    fn variant_pattern(&mut self, start: Token<'src>) -> Result<Pattern<'src>, Error> {
+        // Compile a test for a variant of an enum, leaving what it carries under the boolean
+        self.advance()?;
+        self.named_variable(start.clone(), false)?;
+        let kind = self.pop_type().kind;
+        self.consume(Tk::Dot)?;
+        let variant = self.identifier()?;
+        let id = if let Tk::Identifier(id) = variant.kind { id } else { unreachable!() };
+        let mut names = vec![];
+        if self.present(Tk::LeftParen)? != 0 {
+            while self.current.kind != Tk::RightParen {
+                let name = self.identifier()?;
+                names.push(if let Tk::Identifier(name) = name.kind { name } else { unreachable!() });
+                if self.present(Tk::Comma)? == 0 { break }
+            }
+            self.consume(Tk::RightParen)?;
+        }
+        let (col, len) = self.span_from(&start);
+        // Check the variant against the enum when it is known
+        let known = match &kind {
+            Type::Enum(e) => self.enums.get(e.as_str()),
+            Type::Any => None,
+            _ => return Err(Error::MismatchedTypes(
+                start.line, start.col, start.len, format!("Expected an enum but found {}", kind)
+            )),
+        };
+        let payload = match known.map(|v| v.iter().find(|v| v.0 == id)) {
+            Some(None) => return Err(Error::UndefinedProperty(variant.line, variant.col, variant.len, id.to_string())),
+            Some(Some((_, payload))) => payload.clone(),
+            None => vec![Type::Any; names.len()],
+        };
+        if !names.is_empty() && names.len() != payload.len() {
+            return Err(Error::UnpackMismatch(start.line, col, len, names.len().to_string(), payload.len()));
+        }
+        let idx = self.identifier_constant(variant)?;
+        self.emit_byte(OpCode::OpMatchVariant(idx, names.len() as u16), col, len);
+        // Values that are ignored with _ still take up a hidden local
+        let bindings = names.into_iter().zip(payload).map(|(name, kind)| match name {
+            "_" => ("", kind),
+            name => (name, kind),
+        });
+        Ok(Pattern::Variant(bindings.collect()))
+    }
+
+    fn pattern_literal(&mut self) -> Result<Value, Error> {
+        // Consume a literal value in a pattern
+        let negative = self.present(Tk::Minus)? != 0;
+        let token = self.current.clone();
+        let value = match (token.kind, negative) {
+            (Tk::Integer(int), _) => Value::Integer(if negative { -int } else { int }),
+            (Tk::Number(float), _) => Value::Number(if negative { -float } else { float }),
+            (Tk::String(s), false) => Value::String(s.into_owned()),
+            (Tk::True, false) => Value::Boolean(true),
+            (Tk::False, false) => Value::Boolean(false),
+            (Tk::Nil, false) => Value::Nil,
+            _ => return Err(Error::ExpectedExpression(token.line, token.col, token.len)),
+        };
+        self.advance()?;
+        Ok(value)
+    }
+
+    fn skip_delims(&mut self) -> Result<(), Error> {
+        // Jump over any statement delimiters
+        while self.present(Tk::Delim)? != 0 {}
+        Ok(())
+    }
+
+    pub fn grouping(&mut self, can_assign: bool) -> Result<(), Error> {
+        // Compile a grouping operation, this is for brackets (or tuples when there are commas)
+        let open = self.get_back().unwrap();
+        let start = self.chunk.code.len();
+        let first = self.element()?;
+        let tuple = self.present(Tk::Comma)? != 0;
+        if !tuple && !matches!(first, Element::Rest(_)) {
+            self.consume(Tk::RightParen)?;
+            return Ok(());
+        }
+        let mut elements = vec![first];
+        while tuple && self.current.kind != Tk::RightParen {
+            elements.push(self.element()?);
+            if self.present(Tk::Comma)? == 0 { break }
+        }
         self.consume(Tk::RightParen)?;
+        self.sequence(elements, &open, start, can_assign, true)
+    }
+
+    pub fn list(&mut self, can_assign: bool) -> Result<(), Error> {
+        // Compile a list literal e.g. [80, 443]
+        let open = self.get_back().unwrap();
+        let start = self.chunk.code.len();
+        let mut elements = vec![];
+        while self.current.kind != Tk::RightSquare {
+            elements.push(self.element()?);
+            if self.present(Tk::Comma)? == 0 { break }
+        }
+        self.consume(Tk::RightSquare)?;
+        self.sequence(elements, &open, start, can_assign, false)
+    }
+
+    pub fn map(&mut self, _: bool) -> Result<(), Error> {
+        // Compile a map literal e.g. {"host": "localhost", "port": 80}
+        let open = self.get_back().unwrap();
+        let (mut keys, mut values) = (vec![], vec![]);
+        self.skip_delims()?;
+        while self.current.kind != Tk::RightCurly {
+            self.expression()?;
+            keys.push(self.pop_type().kind);
+            self.consume(Tk::Colon)?;
+            self.expression()?;
+            values.push(self.pop_type().kind);
+            self.skip_delims()?;
+            if self.present(Tk::Comma)? == 0 { break }
+            self.skip_delims()?;
+        }
+        self.consume(Tk::RightCurly)?;
+        let (col, len) = self.span_from(&open);
+        self.emit_byte(OpCode::OpMap(keys.len() as u16), col, len);
+        let key = keys.into_iter().reduce(Type::join).unwrap_or(Type::Any);
+        let value = values.into_iter().reduce(Type::join).unwrap_or(Type::Any);
+        self.types.push(Typed { kind: Type::Map(Box::new(key), Box::new(value)), line: open.line, col, len });
+        Ok(())
+    }
+
+    fn element(&mut self) -> Result<Element<'src>, Error> {
+        // Compile an item of a tuple or list, noting whether it could be assigned to
+        if self.present(Tk::DotDotDot)? != 0 {
+            return Ok(Element::Rest(self.identifier()?));
+        }
+        let start = self.current.clone();
+        self.expression()?;
+        let lone = matches!(start.kind, Tk::Identifier(_)) && self.previous.as_ref() == Some(&start);
+        Ok(if lone { Element::Name(start) } else { Element::Value })
+    }
+
+    fn sequence(
+        &mut self, 
+        elements: Vec<Element<'src>>, 
+        open: &Token, 
+        start: usize, 
+        can_assign: bool, 
+        tuple: bool,
+    ) -> Result<(), Error> {
+        // Build a tuple or list out of its elements, or destructure into them when assigned to
+        let (col, len) = self.span_from(open);
+        let count = elements.len();
+        let rest = matches!(elements.last(), Some(Element::Rest(_)));
+        if can_assign && self.current.kind == Tk::Equal {
+            let mut targets = vec![];
+            for (i, element) in elements.into_iter().enumerate() {
+                match element {
+                    Element::Name(name) => targets.push(name),
+                    // The remaining values can only be collected at the end
+                    Element::Rest(name) if i == count - 1 => targets.push(name),
+                    _ => return Err(Error::InvalidAssignmentTarget(open.line, col, len)),
+                }
+            }
+            self.advance()?;
+            // The elements were compiled as values, so that code is thrown away
+            self.chunk.code.truncate(start);
+            self.chunk.lines.truncate(start);
+            self.types.truncate(self.types.len() + rest as usize - count);
+            self.expression()?;
+            let value = self.pop_type();
+            let kinds = self.unpacked(&value, count, rest, open.line, (col, len))?;
+            // The assignment gives back the whole value that was destructured
+            self.emit_byte(OpCode::OpDup, col, len);
+            self.emit_byte(OpCode::OpUnpack(count as u16, rest), col, len);
+            for (target, kind) in targets.iter().zip(kinds) {
+                let (local, _, set, expected) = self.resolve(target)?;
+                self.check_assignable(target, local)?;
+                self.expect_type(&expected, &Typed { kind, line: open.line, col, len })?;
+                self.emit_byte(set, target.col, target.len);
+                self.emit_byte(OpCode::OpPop, target.col, target.len);
+            }
+            self.types.push(value);
+            return Ok(());
+        }
+        for element in &elements {
+            if let Element::Rest(name) = element {
+                // Collecting values only makes sense when destructuring
+                return Err(Error::ExpectedExpression(name.line, name.col, name.len));
+            }
+        }
+        let start = self.types.len() - count;
+        let items = self.types.split_off(start).into_iter().map(|t| t.kind);
+        let kind = if tuple {
+            self.emit_byte(OpCode::OpTuple(count as u16), col, len);
+            Type::Tuple(items.collect())
+        } else {
+            self.emit_byte(OpCode::OpList(count as u16), col, len);
+            Type::List(Box::new(items.reduce(Type::join).unwrap_or(Type::Any)))
+        };
+        self.types.push(Typed { kind, line: open.line, col, len });
         Ok(())
     }
 
     pub fn unary(&mut self, _: bool) -> Result<(), Error> {
         // Compile a unary operation like negation or not
         let op_type = self.get_back().unwrap();
+        if op_type.kind == Tk::Minus && self.current.kind == Tk::Integer(i64::MIN) {
+            // The smallest integer is written negated, as its positive is too large
+            let literal = self.current.clone();
+            self.advance()?;
+            let (col, len) = (op_type.col, literal.col + literal.len - op_type.col);
+            self.emit_constant(Value::Integer(i64::MIN), col, len);
+            self.push_type(Type::Integer);
+            return Ok(());
+        }
         self.parse_precedence(Precedence::Unary)?;
+        // Check the operand is of the right type
+        let operand = self.pop_type();
+        let (valid, message) = match op_type.kind {
+            Tk::Minus => (operand.kind.is_numeric(), "Operand must be a number"),
+            Tk::Tilde => (operand.kind == Type::Integer, "Operand must be an integer"),
+            _ => (operand.kind == Type::Boolean, "Operand must be a boolean"),
+        };
+        if !valid && operand.kind != Type::Any {
+            return Err(Error::MismatchedTypes(
+                operand.line, operand.col, operand.len, message.to_string()
+            ));
+        }
+        self.push_type(match op_type.kind {
+            Tk::Minus => operand.kind,
+            Tk::Tilde => Type::Integer,
+            _ => Type::Boolean,
+        });
         match op_type.kind {
             Tk::Minus => self.emit_byte(OpCode::OpNegate, op_type.col, op_type.len),
+            Tk::Tilde => self.emit_byte(OpCode::OpBitNot, op_type.col, op_type.len),
             Tk::Exclamation | Tk::Not => 
                 self.emit_byte(OpCode::OpNot, op_type.col, op_type.len),
             _ => unreachable!(),
@@ -246,14 +1817,628 @@ impl Compiler {
         Ok(())
     }
 
-    fn end_compiler(&mut self, end: usize) {
-        // Finalise compilation
-        self.emit_return(end, 0);
+    pub fn throw(&mut self, _: bool) -> Result<(), Error> {
+        // Raise an exception e.g. throw "port out of range"
+        let keyword = self.get_back().unwrap();
+        self.expression()?;
+        self.pop_type();
+        self.emit_byte(OpCode::OpThrow, keyword.col, keyword.len);
+        // Execution never carries on past a throw, so it can stand in for any value
+        self.push_typed(Type::Any, &keyword);
+        Ok(())
+    }
+
+    pub fn suspend(&mut self, _: bool) -> Result<(), Error> {
+        // Pause a generator e.g. yield step, giving back whatever it is resumed with
+        let keyword = self.get_back().unwrap();
+        if self.returns.is_empty() {
+            return Err(Error::YieldOutsideFunction(keyword.line, keyword.col, keyword.len));
+        }
+        if let Tk::Delim | Tk::RightCurly | Tk::RightParen | Tk::RightSquare | Tk::Comma = self.current.kind {
+            self.emit_byte(OpCode::OpNil, keyword.col, keyword.len);
+        } else {
+            self.expression()?;
+            self.pop_type();
+        }
+        self.yields = true;
+        self.emit_byte(OpCode::OpYield, keyword.col, keyword.len);
+        self.push_typed(Type::Any, &keyword);
+        Ok(())
+    }
+
+    pub fn lambda(&mut self, _: bool) -> Result<(), Error> {
+        // Compile an anonymous function e.g. fn(x) { x * 2 }
+        let keyword = self.get_back().unwrap();
+        let (kind, function) = self.function("", &keyword, None)?;
+        self.emit_constant(Value::Function(Rc::new(function)), keyword.col, keyword.len);
+        self.push_type(kind);
+        Ok(())
+    }
+
+    pub fn call(&mut self, _: bool) -> Result<(), Error> {
+        // Compile a function call, the function is on the stack
+        let piped = self.take_piped();
+        let (mut args, names, close) = self.arguments()?;
+        let callee = self.pop_type();
+        if let Some(value) = piped {
+            self.emit_byte(OpCode::OpPipe(args.len() as u16), close.col, close.len);
+            args.insert(0, value);
+        }
+        let returns = self.check_call(&callee, &args, &names, &close)?;
+        let keywords = self.keywords(&names);
+        self.emit_byte(OpCode::OpCall(args.len() as u16, keywords), close.col, close.len);
+        self.push_type(returns);
+        Ok(())
+    }
+
+    pub fn pipeline(&mut self, _: bool) -> Result<(), Error> {
+        // Compile a pipeline e.g. hosts |> filter(enabled), which calls filter(hosts, enabled)
+        let op = self.get_back().unwrap();
+        let value = self.pop_type();
+        let outer = self.piped.replace((value, self.types.len() + 1, self.returns.len()));
+        self.parse_precedence(Precedence::Comparison)?;
+        if let Some((value, _, _)) = std::mem::replace(&mut self.piped, outer) {
+            // Without a call to go into, whatever is on the right is called with the value
+            let end = self.previous.clone().unwrap();
+            let callee = self.pop_type();
+            let returns = self.check_call(&callee, &[value], &[], &end)?;
+            self.emit_byte(OpCode::OpPipe(0), op.col, op.len);
+            self.emit_byte(OpCode::OpCall(1, None), op.col, op.len);
+            self.push_type(returns);
+        }
+        Ok(())
+    }
+
+    fn take_piped(&mut self) -> Option<Typed> {
+        // Take the value from a |> if this is the call it goes into, rather than one within it
+        match &self.piped {
+            Some((_, height, depth)) if *height == self.types.len() && *depth == self.returns.len() =>
+                self.piped.take().map(|(value, _, _)| value),
+            _ => None,
+        }
+    }
+
+    pub fn range(&mut self, _: bool) -> Result<(), Error> {
+        // Compile a range e.g. 0..10, 1..=5 or 0..100 step 5
+        let op = self.get_back().unwrap();
+        self.parse_precedence(Precedence::Term)?;
+        // Step is only special straight after a range, so it can still be used as a name
+        let stepped = self.current.kind == Tk::Identifier("step");
+        if stepped {
+            self.advance()?;
+            self.parse_precedence(Precedence::Term)?;
+        }
+        let mut bounds: Vec<Typed> = (0..2 + stepped as usize).map(|_| self.pop_type()).collect();
+        for bound in bounds.iter().rev() {
+            self.expect_type(&Type::Integer, bound)?;
+        }
+        self.emit_byte(OpCode::OpRange(op.kind == Tk::DotDotEq, stepped), op.col, op.len);
+        let start = bounds.pop().unwrap();
+        self.types.push(Typed { kind: Type::Range, ..start });
+        Ok(())
+    }
+
+    pub fn contains(&mut self, _: bool) -> Result<(), Error> {
+        // Compile a membership test e.g. port in ports, or 3 in 1..5
+        let op = self.get_back().unwrap();
+        self.parse_precedence(Precedence::Range)?;
+        let collection = self.pop_type();
+        let value = self.pop_type();
+        let expected = match &collection.kind {
+            Type::Range => Type::Integer,
+            Type::String => Type::String,
+            Type::List(item) => *item.clone(),
+            Type::Map(key, _) => *key.clone(),
+            Type::Tuple(_) | Type::Any => Type::Any,
+            kind => return Err(Error::MismatchedTypes(
+                collection.line, collection.col, collection.len,
+                format!("Can't look for values within {}", kind)
+            )),
+        };
+        self.expect_type(&expected, &value)?;
+        self.emit_byte(OpCode::OpContains, op.col, op.len);
+        self.types.push(Typed { kind: Type::Boolean, ..value });
+        Ok(())
+    }
+
+    pub fn index(&mut self, can_assign: bool) -> Result<(), Error> {
+        // Compile an index e.g. hosts[0], or a slice e.g. name[0..5]
+        self.expression()?;
+        let close = self.current.clone();
+        self.consume(Tk::RightSquare)?;
+        let index = self.pop_type();
+        let object = self.pop_type();
+        let kind = self.indexed(&object, &index)?;
+        if can_assign && self.present(Tk::Equal)? != 0 {
+            // Assign to an item
+            self.check_item_assignable(&object, &index)?;
+            self.expression()?;
+            let value = self.pop_type();
+            self.expect_type(&kind, &value)?;
+            self.emit_byte(OpCode::OpSetIndex, close.col, close.len);
+            self.types.push(value);
+        } else if let Some((op, token)) = self.compound_assignment(can_assign)? {
+            // Operate on an item and then assign the result to it
+            self.check_item_assignable(&object, &index)?;
+            self.emit_byte(OpCode::OpDupPair, close.col, close.len);
+            self.emit_byte(OpCode::OpIndex, close.col, close.len);
+            self.push_typed(kind.clone(), &close);
+            self.expression()?;
+            self.operate(&op, &token)?;
+            let value = self.pop_type();
+            self.expect_type(&kind, &value)?;
+            self.emit_byte(op, token.col, token.len);
+            self.emit_byte(OpCode::OpSetIndex, close.col, close.len);
+            self.types.push(value);
+        } else {
+            self.emit_byte(OpCode::OpIndex, close.col, close.len);
+            self.push_type(kind);
+        }
+        Ok(())
+    }
+
+    fn indexed(&self, object: &Typed, index: &Typed) -> Result<Type, Error> {
+        // Work out the type of an item (or slice) of a value, checking the index suits it
+        if let Some(kind) = self.special(object, "index", Some(index))? {
+            return Ok(kind);
+        }
+        let (expected, kind) = match &object.kind {
+            Type::Map(key, value) => (*key.clone(), *value.clone()),
+            // Slicing gives back the same kind of sequence
+            Type::List(_) | Type::String if index.kind == Type::Range => (Type::Range, object.kind.clone()),
+            Type::Tuple(_) if index.kind == Type::Range => (Type::Range, Type::Any),
+            Type::List(item) => (Type::Integer, *item.clone()),
+            Type::Tuple(items) => (Type::Integer, items.iter().cloned().reduce(Type::join).unwrap_or(Type::Any)),
+            Type::String => (Type::Integer, Type::String),
+            Type::Any => (Type::Any, Type::Any),
+            kind => return Err(Error::MismatchedTypes(
+                object.line, object.col, object.len,
+                format!("Can't index into {}", kind)
+            )),
+        };
+        self.expect_type(&expected, index)?;
+        Ok(kind)
+    }
+
+    fn check_item_assignable(&self, object: &Typed, index: &Typed) -> Result<(), Error> {
+        // Only the items of lists and maps can be changed, and only one at a time
+        match (&object.kind, &index.kind) {
+            (Type::Map(_, _), _) => Ok(()),
+            (Type::List(_) | Type::Any, Type::Range) => Err(Error::MismatchedTypes(
+                index.line, index.col, index.len, "Can't assign to a slice".to_string()
+            )),
+            (Type::List(_) | Type::Any, _) => Ok(()),
+            (kind, _) => Err(Error::MismatchedTypes(
+                object.line, object.col, object.len,
+                format!("Can't change the items of {}", kind)
+            )),
+        }
+    }
+
+    pub fn dot(&mut self, can_assign: bool) -> Result<(), Error> {
+        // Compile a property access e.g. server.port, or a method call e.g. server.url()
+        let name = self.current.clone();
+        if let TokenKind::Identifier(_) = name.kind { self.advance()?; } else {
+            self.consume(TokenKind::Identifier(""))?;
+        }
+        let idx = self.identifier_constant(name.clone())?;
+        let piped = if self.current.kind == Tk::LeftParen { self.take_piped() } else { None };
+        let object = self.pop_type();
+        if self.present(Tk::LeftParen)? != 0 {
+            // Call a method
+            let (mut args, names, close) = self.arguments()?;
+            if let Some(value) = piped {
+                self.emit_byte(OpCode::OpPipe(args.len() as u16), close.col, close.len);
+                args.insert(0, value);
+            }
+            let method = Typed { kind: self.member(&object, &name, true)?, ..object };
+            let returns = self.check_call(&method, &args, &names, &close)?;
+            let keywords = self.keywords(&names);
+            self.emit_byte(OpCode::OpInvoke(idx, args.len() as u16, keywords), close.col, close.len);
+            self.push_type(returns);
+            return Ok(());
+        }
+        let kind = self.member(&object, &name, false)?;
+        if can_assign && self.present(Tk::Equal)? != 0 {
+            // Assign to a field
+            self.expression()?;
+            let value = self.pop_type();
+            self.expect_type(&kind, &value)?;
+            self.emit_byte(OpCode::OpSetProperty(idx), name.col, name.len);
+            self.types.push(value);
+        } else if let Some((op, token)) = self.compound_assignment(can_assign)? {
+            // Operate on a field and then assign the result to it
+            self.emit_byte(OpCode::OpDup, name.col, name.len);
+            self.emit_byte(OpCode::OpGetProperty(idx), name.col, name.len);
+            self.push_typed(kind.clone(), &name);
+            self.expression()?;
+            self.operate(&op, &token)?;
+            let value = self.pop_type();
+            self.expect_type(&kind, &value)?;
+            self.emit_byte(op, token.col, token.len);
+            self.emit_byte(OpCode::OpSetProperty(idx), name.col, name.len);
+            self.types.push(value);
+        } else {
+            self.emit_byte(OpCode::OpGetProperty(idx), name.col, name.len);
+            self.push_type(kind);
+        }
+        Ok(())
+    }
+
+    fn arguments(&mut self) -> Result<(Vec<Typed>, Vec<Token<'src>>, Token<'src>), Error> {
+        // Compile the arguments of a call, giving back their types, any keywords and the closing bracket
+        let (mut args, mut names) = (vec![], vec![]);
+        while self.current.kind != Tk::RightParen {
+            // Keyword arguments name the parameter they are for e.g. port: 8080
+            let keyword = match self.current.kind {
+                Tk::Identifier(_) => self.peek()?.kind == Tk::Colon,
+                _ => false,
+            };
+            if keyword {
+                names.push(self.current.clone());
+                self.advance()?;
+                self.advance()?;
+            } else if let Some(Token { kind: Tk::Identifier(name), .. }) = names.last() {
+                // Arguments given in order must come before any keyword arguments
+                let current = &self.current;
+                return Err(Error::ExpectedToken(
+                    format!("keyword argument after '{}'", name), current.line, current.col, current.len
+                ));
+            }
+            self.expression()?;
+            args.push(self.pop_type());
+            if self.present(Tk::Comma)? == 0 { break }
+        }
+        let close = self.current.clone();
+        self.consume(Tk::RightParen)?;
+        Ok((args, names, close))
+    }
+
+    fn keywords(&mut self, names: &[Token]) -> Option<u16> {
+        // Store the names of keyword arguments as a constant, for the VM to match up
+        if names.is_empty() { return None }
+        let names = names.iter().map(|n| match n.kind {
+            Tk::Identifier(id) => Value::String(id.to_string()),
+            _ => unreachable!(),
+        });
+        Some(self.chunk.add_constant(Value::Tuple(Rc::new(names.collect()))))
+    }
+
+    fn check_call(
+        &self, callee: &Typed, args: &[Typed], names: &[Token], close: &Token
+    ) -> Result<Type, Error> {
+        // Check the arguments against the parameters of what is being called
+        let (params, returns) = match &callee.kind {
+            Type::Function(params, returns) => (params.clone(), *returns.clone()),
+            // Calling a struct creates an instance from its fields
+            Type::Struct(name) => match self.structs.get(name.as_str()) {
+                Some(definition) => (
+                    Params { 
+                        kinds: definition.fields.iter().map(|f| f.1.clone()).collect(),
+                        names: definition.fields.iter().map(|f| f.0.to_string()).collect(),
+                        required: definition.fields.len(),
+                        variadic: false,
+                    },
+                    Type::Instance(name.clone()),
+                ),
+                None => return Ok(Type::Instance(name.clone())),
+            },
+            Type::Any => return Ok(Type::Any),
+            _ => return Err(Error::MismatchedTypes(
+                callee.line, callee.col, callee.len, "Can only call functions".to_string()
+            )),
+        };
+        // Errors point at the whole call
+        let len = if close.line == callee.line { close.col + 1 - callee.col } else { callee.len };
+        let (line, col) = (callee.line, callee.col);
+        let (fixed, positional) = (params.fixed(), args.len() - names.len());
+        if positional > fixed && !params.variadic {
+            return Err(Error::WrongArgumentCount(line, col, len, params.arity(), args.len()));
+        }
+        let rest = match params.kinds.last() {
+            Some(Type::List(item)) if params.variadic => *item.clone(),
+            _ => Type::Any,
+        };
+        let mut given = vec![false; fixed];
+        for (i, arg) in args[..positional].iter().enumerate() {
+            if i < fixed {
+                given[i] = true;
+                self.expect_type(&params.kinds[i], arg)?;
+            } else {
+                self.expect_type(&rest, arg)?;
+            }
+        }
+        // Parameter names aren't known for functions that only have an annotation
+        if !names.is_empty() && params.names.is_empty() { return Ok(returns) }
+        for (name, arg) in names.iter().zip(&args[positional..]) {
+            let id = if let Tk::Identifier(id) = name.kind { id } else { unreachable!() };
+            match params.names[..fixed].iter().position(|n| n == id) {
+                Some(i) if given[i] => return Err(Error::RepeatedArgument(line, col, len, id.to_string())),
+                Some(i) => {
+                    given[i] = true;
+                    self.expect_type(&params.kinds[i], arg)?;
+                }
+                None => return Err(Error::UnknownKeyword(line, col, len, id.to_string())),
+            }
+        }
+        if let Some(i) = given[..params.required].iter().position(|g| !g) {
+            return Err(if names.is_empty() || params.names.is_empty() {
+                Error::WrongArgumentCount(line, col, len, params.arity(), args.len())
+            } else {
+                Error::MissingArgument(line, col, len, params.names[i].clone())
+            });
+        }
+        Ok(returns)
+    }
+
+    fn member(&self, object: &Typed, name: &Token, invoke: bool) -> Result<Type, Error> {
+        // Work out the type of a field or method, as seen from the object it is accessed on
+        let id = if let TokenKind::Identifier(id) = name.kind { id } else { unreachable!() };
+        let (definition, instance) = match &object.kind {
+            Type::Instance(s) => (self.structs.get(s.as_str()), true),
+            Type::Struct(s) => (self.structs.get(s.as_str()), false),
+            // Modules know the types of everything defined in them
+            Type::Module(path) => {
+                let loaded = self.loader.borrow().get(Path::new(path));
+                return match loaded.and_then(|l| l.types.get(id).cloned()) {
+                    Some(kind) => Ok(kind),
+                    None => Err(Error::UndefinedProperty(name.line, name.col, name.len, id.to_string())),
+                };
+            }
+            // Enums give back their variants, those carrying values are called to create them
+            Type::Enum(e) => {
+                let variant = self.enums.get(e.as_str()).map(|v| v.iter().find(|v| v.0 == id));
+                let value = Type::Instance(e.clone());
+                return match variant {
+                    // Enums from other modules aren't known
+                    None => Ok(Type::Any),
+                    Some(None) => Err(Error::UndefinedProperty(name.line, name.col, name.len, id.to_string())),
+                    Some(Some((_, payload))) if invoke => 
+                        Ok(Type::Function(Params::positional(payload.clone()), Box::new(value))),
+                    Some(Some((_, payload))) if payload.is_empty() => Ok(value),
+                    Some(Some((_, payload))) => Err(Error::WrongArgumentCount(
+                        name.line, name.col, name.len, payload.len().to_string(), 0
+                    )),
+                };
+            }
+            // Generators are resumed through their own methods
+            Type::Generator => return match (id, invoke) {
+                ("next", true) => Ok(Type::Function(Params::positional(vec![]), Box::new(Type::Any))),
+                ("send", true) => Ok(Type::Function(Params::positional(vec![Type::Any]), Box::new(Type::Any))),
+                ("done", false) => Ok(Type::Boolean),
+                _ => Err(Error::UndefinedProperty(name.line, name.col, name.len, id.to_string())),
+            },
+            Type::Any => return Ok(Type::Any),
+            // Other values only have the built-in methods
+            kind if invoke => return methods::signature(kind, id).ok_or_else(||
+                Error::UndefinedProperty(name.line, name.col, name.len, id.to_string())
+            ),
+            _ => return Err(Error::MismatchedTypes(
+                object.line, object.col, object.len, "Only instances have properties".to_string()
+            )),
+        };
+        let definition = if let Some(definition) = definition { definition } else {
+            return Ok(Type::Any)
+        };
+        if let Some((_, kind)) = definition.fields.iter().find(|f| f.0 == id).filter(|_| instance) {
+            return Ok(kind.clone());
+        }
+        match definition.methods.get(id) {
+            // Calling a method on an instance passes the instance in as self
+            Some((true, Type::Function(params, returns))) if instance && invoke => {
+                Ok(Type::Function(params.without_self(), returns.clone()))
+            }
+            Some((_, kind)) => Ok(kind.clone()),
+            // Methods defined later in an unfinished struct aren't known yet
+            None if !definition.complete => Ok(Type::Any),
+            None => Err(Error::UndefinedProperty(name.line, name.col, name.len, id.to_string())),
+        }
+    }
+
+    fn operate(&mut self, op: &OpCode, token: &Token) -> Result<(), Error> {
+        // Work out the type of a binary operation, rejecting operands that can't work
+        let rhs = self.pop_type();
+        let lhs = self.pop_type();
+        if let Some(kind) = self.overload(op, &lhs, &rhs)? {
+            self.types.push(Typed { kind, ..lhs });
+            return Ok(());
+        }
+        let comparison = matches!(op, OpCode::OpGreater | OpCode::OpLess);
+        let bitwise = matches!(op, 
+            OpCode::OpBitAnd | OpCode::OpBitOr | OpCode::OpBitXor | OpCode::OpShiftLeft | OpCode::OpShiftRight
+        );
+        let kind = if let OpCode::OpEqual = op { Type::Boolean } else if bitwise {
+            // Bitwise operations only work on integers
+            for operand in [&lhs, &rhs].iter() {
+                if let Type::Integer | Type::Any = operand.kind {} else {
+                    return Err(Error::MismatchedTypes(
+                        operand.line, operand.col, operand.len,
+                        "Operands must be integers".to_string()
+                    ));
+                }
+            }
+            Type::Integer
+        } else {
+            // Only numbers and strings can be operated on, unless an instance has a special method for it
+            let special = special_method(op, &rhs.kind);
+            for (i, operand) in [&lhs, &rhs].iter().enumerate() {
+                if let (Type::Instance(kind), Some((method, swapped))) = (&operand.kind, special) {
+                    if i == swapped as usize {
+                        return Err(Error::MismatchedTypes(
+                            operand.line, operand.col, operand.len,
+                            format!("{} has no '{}' method for {}", kind, method, token.kind)
+                        ));
+                    }
+                }
+                if let Type::Boolean | Type::Nil | Type::Function(_, _) | Type::Range | Type::Generator |
+                    Type::Struct(_) | Type::Enum(_) | Type::Instance(_) | Type::Module(_) = operand.kind {
+                    return Err(Error::MismatchedTypes(
+                        operand.line, operand.col, operand.len,
+                        "Operands must be either numbers or strings".to_string()
+                    ));
+                }
+            }
+            match (&lhs.kind, &rhs.kind) {
+                (Type::Any, _) | (_, Type::Any) if comparison => Type::Boolean,
+                (Type::Any, _) | (_, Type::Any) => Type::Any,
+                (Type::String, Type::String) if *op == OpCode::OpAdd => Type::String,
+                (Type::String, Type::String) => {
+                    let symbol = token.kind.to_string();
+                    return Err(Error::ImpossibleOperation(
+                        token.line, token.col, token.len, symbol.trim_matches('\'').to_string()
+                    ));
+                }
+                (a, b) if a.is_numeric() && b.is_numeric() => match op {
+                    _ if comparison => Type::Boolean,
+                    OpCode::OpDiv => Type::Number,
+                    // Negative powers of integers give back floats
+                    OpCode::OpPow if *a == Type::Integer && *b == Type::Integer => Type::Any,
+                    _ if *a == Type::Integer && *b == Type::Integer => Type::Integer,
+                    _ => Type::Number,
+                },
+                // Strings can't be mixed with numbers
+                (a, _) => {
+                    let expected = if a.is_numeric() { "a number".to_string() } else { a.to_string() };
+                    return Err(Error::MismatchedTypes(
+                        rhs.line, rhs.col, rhs.len, 
+                        format!("Expected {} but found {}", expected, rhs.kind)
+                    ));
+                }
+            }
+        };
+        self.types.push(Typed { kind, ..lhs });
+        Ok(())
+    }
+
+    fn overload(&self, op: &OpCode, lhs: &Typed, rhs: &Typed) -> Result<Option<Type>, Error> {
+        // Find the type of an operation carried out by a special method of an instance
+        let (name, receiver, other) = match special_method(op, &rhs.kind) {
+            Some((name, false)) => (name, lhs, rhs),
+            Some((name, true)) => (name, rhs, lhs),
+            None => return Ok(None),
+        };
+        // A value of unknown type could turn out to be an instance with the method
+        if let (Type::Any, Type::Instance(_)) = (&receiver.kind, &other.kind) {
+            return Ok(Some(Type::Any));
+        }
+        self.special(receiver, name, Some(other))
+    }
+
+    fn special(&self, receiver: &Typed, name: &str, other: Option<&Typed>) -> Result<Option<Type>, Error> {
+        // Look up a special method of an instance, checking the operand it is given
+        let definition = match &receiver.kind {
+            Type::Instance(s) => self.structs.get(s.as_str()),
+            _ => None,
+        };
+        let definition = if let Some(definition) = definition { definition } else { return Ok(None) };
+        match definition.methods.get(name) {
+            Some((true, Type::Function(params, returns))) => {
+                if let (Some(expected), Some(other)) = (params.kinds.get(1), other) {
+                    self.expect_type(expected, other)?;
+                }
+                Ok(Some(*returns.clone()))
+            }
+            // Methods defined later in an unfinished struct aren't known yet
+            None if !definition.complete => Ok(Some(Type::Any)),
+            _ => Ok(None),
+        }
+    }
+
+    fn condition(&mut self) -> Result<(), Error> {
+        // Check that a condition is a boolean
+        let condition = self.pop_type();
+        if let Type::Boolean | Type::Any = condition.kind {
+            Ok(())
+        } else {
+            Err(Error::MismatchedTypes(
+                condition.line, condition.col, condition.len, 
+                "Condition must be a boolean".to_string()
+            ))
+        }
+    }
+
+    fn expect_type(&self, expected: &Type, found: &Typed) -> Result<(), Error> {
+        // Check that a value can be used where a certain type is expected
+        if expected.accepts(&found.kind) {
+            Ok(())
+        } else {
+            Err(Error::MismatchedTypes(
+                found.line, found.col, found.len, 
+                format!("Expected {} but found {}", expected, found.kind)
+            ))
+        }
+    }
+
+    fn expect_value(&mut self, expected: &Type, found: &Typed) -> Result<(), Error> {
+        // Check the type of a value, leaving it to the VM when it isn't known until it runs
+        self.expect_type(expected, found)?;
+        if found.kind == Type::Any && *expected != Type::Any {
+            let idx = self.chunk.add_type(expected.clone());
+            self.emit_byte(OpCode::OpCheckType(idx), found.col, found.len);
+        }
+        Ok(())
+    }
+
+    fn push_type(&mut self, kind: Type) {
+        // Record the type of an expression that ends at the last token
+        let token = self.get_back().unwrap();
+        self.push_typed(kind, &token);
+    }
+
+    fn push_typed(&mut self, kind: Type, token: &Token) {
+        // Record the type of an expression found at a token
+        self.types.push(Typed { kind, line: token.line, col: token.col, len: token.len });
+    }
+
+    fn pop_type(&mut self) -> Typed {
+        // Take the type of the last expression
+        self.types.pop().unwrap_or(Typed { kind: Type::Any, line: 0, col: 0, len: 0 })
+    }
+
+    fn locate(&mut self, start: &Token) {
+        // Widen the last expression's position to cover everything from the start token
+        let (col, len) = self.span_from(start);
+        if let Some(typed) = self.types.last_mut() {
+            typed.line = start.line;
+            typed.col = col;
+            typed.len = len;
+        }
     }
 
     fn emit_byte(&mut self, code: OpCode, col: usize, len: usize) {
-        // Emit a byte into the chunk
-        self.chunk.write(code, col, len);
+        // Emit a byte into the chunk, on the line of the last token
+        let line = self.previous.as_ref().map_or(self.current.line, |t| t.line);
+        self.chunk.write(code, line, col, len);
+    }
+
+    fn emit_jump(&mut self, code: OpCode, col: usize, len: usize) -> usize {
+        // Emit a jump with a placeholder offset, giving back where it is
+        self.emit_byte(code, col, len);
+        self.chunk.code.len() - 1
+    }
+
+    fn patch_jump(&mut self, idx: usize) {
+        // Point a jump to the end of the chunk
+        let offset = (self.chunk.code.len() - idx - 1) as u16;
+        match &mut self.chunk.code[idx].2 {
+            OpCode::OpJump(o) | OpCode::OpJumpIfFalse(o) | OpCode::OpJumpIfNil(o) | OpCode::OpTry(o, _) |
+            OpCode::OpNext(o) | OpCode::OpCheckNext(o) => *o = offset,
+            _ => unreachable!(),
+        }
+    }
+
+    fn patch_finally(&mut self, idx: usize) {
+        // Point the finally clause of a try block to the end of the chunk
+        let offset = (self.chunk.code.len() - idx - 1) as u16;
+        if let OpCode::OpTry(_, o) = &mut self.chunk.code[idx].2 {
+            *o = offset;
+        }
+    }
+
+    fn emit_loop(&mut self, start: usize, col: usize, len: usize) {
+        // Jump back to an earlier point in the chunk
+        let offset = (self.chunk.code.len() - start + 1) as u16;
+        self.emit_byte(OpCode::OpLoop(offset), col, len);
     }
 
     fn emit_constant(&mut self, val: Value, col: usize, len: usize) {
@@ -262,65 +2447,78 @@ impl Compiler {
         self.emit_byte(OpCode::OpConstant(idx), col, len)
     }
 
-    fn emit_return(&mut self, col: usize, len: usize) {
-        // Emit a return operation
-        self.emit_byte(OpCode::OpReturn, col, len)
-    }
-
     fn advance(&mut self) -> Result<(), Error> {
         // Move the token focus forward
-        self.ptr += 1;
-        while let Some(com) = self.get() {
-            if com.kind != TokenKind::Comment { break }
-            self.ptr += 1;
-        }
+        let next = self.next_token()?;
+        self.previous = Some(std::mem::replace(&mut self.current, next));
         Ok(())
     }
 
+    fn next_token(&mut self) -> Result<Token<'src>, Error> {
+        // Pull in the next token, jumping over comments and collecting documentation
+        if let Some(token) = self.replay.pop_front() {
+            return Ok(token);
+        }
+        self.comment = None;
+        loop {
+            let token = match self.lexer.next() {
+                Some(token) => token?,
+                // Stay on the end of input
+                None => return Ok(self.current.clone()),
+            };
+            match token.kind {
+                TokenKind::Comment => (),
+                TokenKind::DocComment(doc) => self.doc.push(doc),
+                TokenKind::Var | TokenKind::Const | TokenKind::Fn | TokenKind::Struct | TokenKind::Enum => 
+                    return Ok(token),
+                _ => {
+                    // Documentation isn't attached to anything but declarations
+                    self.doc.clear();
+                    return Ok(token)
+                }
+            }
+            self.comment.get_or_insert(token);
+        }
+    }
+
+    fn peek(&mut self) -> Result<Token<'src>, Error> {
+        // Look at the token after the focus without moving
+        let token = self.next_token()?;
+        self.replay.push_front(token.clone());
+        Ok(token)
+    }
+
     fn present(&mut self, kind: TokenKind) -> Result<usize, Error> {
         // Returns Ok(0) if not present, returns Ok(col) if present
-        let tok = self.get().unwrap();
-        if tok.kind != kind { Ok(0) }
-        else { self.advance()?; Ok(tok.col) }
+        if self.current.kind != kind { Ok(0) }
+        else { 
+            let col = self.current.col;
+            self.advance()?;
+            Ok(col)
+        }
     }
 
     fn consume(&mut self, kind: Tk) -> Result<usize, Error> {
         // Consume a token if present, otherwise display an error
-        let current = self.get().ok_or_else(|| Error::UnexpectedEOI(format!("Expected {}", kind)))?;
+        let current = self.current.clone();
         if current.kind == kind {
-            self.advance().unwrap();
+            self.advance()?;
             Ok(current.col)
         } else if current.kind == TokenKind::EOI && kind != TokenKind::EOI {
             // Run back along the line to find appropriate place for token
-            if self.tokens[self.ptr - 1].kind == TokenKind::Comment {
-                self.ptr -= 1;
-                while let Some(Token { kind: TokenKind::Comment, .. }) = self.get_back() {
-                    self.ptr -= 1;
-                }
+            if let Some(comment) = &self.comment {
+                Err(Error::ExpectedToken(kind.to_string(), comment.line, comment.col, 1))
+            } else {
+                Err(Error::ExpectedToken(kind.to_string(), current.line, current.col, 0))
             }
-            let current = self.get().unwrap();
-            let len = if current.kind == TokenKind::EOI { 0 } else { 1 };
-            Err(Error::ExpectedToken(kind, current.line, current.col, len))
         } else {
-            Err(Error::ExpectedToken(kind, current.line, current.col, current.len))
+            Err(Error::ExpectedToken(kind.to_string(), current.line, current.col, current.len))
         }
     }
 
-    fn get(&self) -> Option<Token> {
-        // Retrieve the current token focus
-        Some(self.tokens.get(self.ptr)?.clone())
-    }
-
-    fn get_back(&self) -> Option<Token> {
+    fn get_back(&self) -> Option<Token<'src>> {
         // Look back at the token before the focus
-        let mut target = self.tokens.get(self.ptr - 1)?;
-        let mut offset: isize = -1;
-        while let TokenKind::Comment = target.kind {
-            offset -= 1;
-            if self.ptr as isize + offset < 0 { return None; }
-            target = self.tokens.get((self.ptr as isize + offset) as usize)?;
-        }
-        Some(target.clone())
+        self.previous.clone()
     }
 
     pub fn display(&self) {
@@ -328,3 +2526,17 @@ impl Compiler {
         self.chunk.display();
     }
 }
+
+fn special_method(op: &OpCode, rhs: &Type) -> Option<(&'static str, bool)> {
+    // Find the special method an operation can be carried out by, and whether its operands are swapped
+    match op {
+        OpCode::OpAdd => Some(("add", false)),
+        OpCode::OpSub => Some(("sub", false)),
+        OpCode::OpMul => Some(("mul", false)),
+        OpCode::OpEqual if *rhs != Type::Nil => Some(("eq", false)),
+        OpCode::OpLess => Some(("lt", false)),
+        // Greater than is less than with the operands swapped
+        OpCode::OpGreater => Some(("lt", true)),
+        _ => None,
+    }
+}
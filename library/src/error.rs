@@ -1,21 +1,19 @@
 // error.rs - For handling the formatting and types of errors
 use unicode_segmentation::UnicodeSegmentation;
-use unicode_width::UnicodeWidthStr as width;
 use thiserror::Error as ThisError;
 use lliw::{Fg, Style, Reset};
-use crate::TokenKind;
 
-#[derive(ThisError, Debug, PartialEq)]
+#[derive(ThisError, Debug, Clone, PartialEq)]
 pub enum Error {
     // When the lexer hits a character it doesn't know, e.g. a unicode char
     #[error("[line {1}:{2}] Unexpected character: '{0}'")]
     UnexpectedCharacter(char, usize, usize, usize),
     // When the lexer hits the EOI while collecting a token e.g. unterminated string
-    #[error("Unexpected end of input: {0}")]
-    UnexpectedEOI(String),
+    #[error("[line {1}:{2}] Unexpected end of input: {0}")]
+    UnexpectedEOI(String, usize, usize, usize),
     // When the consume method misses a token e.g. missing end bracket
     #[error("[line {1}:{2}] Expected {0}")]
-    ExpectedToken(TokenKind, usize, usize, usize),
+    ExpectedToken(String, usize, usize, usize),
     // When the the compiler tries to parse a dodgy token stream e.g. "1 + * 2"
     #[error("[line {0}:{1}] Expected expression")]
     ExpectedExpression(usize, usize, usize),
@@ -28,41 +26,225 @@ pub enum Error {
     // When the user specifies a non-existant e.g. `prins`
     #[error("[line {0}:{1}] Undefined variable '{3}'")]
     UndefinedVariable(usize, usize, usize, String),
+    // When an integer operation gives a result too big to store e.g. "2 ^ 64"
+    #[error("[line {0}:{1}] Integer overflow: {3}")]
+    IntegerOverflow(usize, usize, usize, String),
+    // When an integer is divided by zero e.g. "3 div 0"
+    #[error("[line {0}:{1}] Division by zero")]
+    DivisionByZero(usize, usize, usize),
+    // When no arm of a match expression matches the value e.g. "match 3 { 1 => 2 }"
+    #[error("[line {0}:{1}] No match arm matches the value {3}")]
+    NoMatchingArm(usize, usize, usize, String),
+    // A warning for when a match arm can never be reached e.g. an arm after "_ => 1"
+    #[error("[line {0}:{1}] Unreachable match arm")]
+    UnreachableArm(usize, usize, usize),
+    // When the user assigns to a constant e.g. "const a = 1; a = 2"
+    #[error("[line {0}:{1}] Can't assign to constant '{3}' (declared on line {4}:{5})")]
+    ConstantAssignment(usize, usize, usize, String, usize, usize),
+    // When the host application tries to change a constant the script declared
+    #[error("Can't override constant '{0}'")]
+    ConstantOverride(String),
+    // When a function is called with the wrong number of arguments e.g. "f(1, 2)"
+    #[error("[line {0}:{1}] Expected {3} arguments but got {4}")]
+    WrongArgumentCount(usize, usize, usize, String, usize),
+    // When a keyword argument doesn't name a parameter e.g. "server(prot: 80)"
+    #[error("[line {0}:{1}] Unknown keyword argument '{3}'")]
+    UnknownKeyword(usize, usize, usize, String),
+    // When an argument is given both in order and by name e.g. "server("a", host: "b")"
+    #[error("[line {0}:{1}] Argument '{3}' was given more than once")]
+    RepeatedArgument(usize, usize, usize, String),
+    // When a parameter without a default isn't given e.g. "server(port: 80)"
+    #[error("[line {0}:{1}] Missing argument '{3}'")]
+    MissingArgument(usize, usize, usize, String),
+    // When a return statement is used outside of a function e.g. "return 3"
+    #[error("[line {0}:{1}] Can't return from outside a function")]
+    ReturnOutsideFunction(usize, usize, usize),
+    // When functions call each other too deeply e.g. endless recursion
+    #[error("[line {0}:{1}] Stack overflow: too many nested function calls")]
+    StackOverflow(usize, usize, usize),
+    // When an annotation names a type that doesn't exist e.g. "var a: integer"
+    #[error("[line {0}:{1}] Unknown type '{3}'")]
+    UnknownType(usize, usize, usize, String),
+    // When a property that doesn't exist is accessed e.g. "server.hots"
+    #[error("[line {0}:{1}] Undefined property '{3}'")]
+    UndefinedProperty(usize, usize, usize, String),
+    // When an imported file can't be found e.g. "import "comon.psi""
+    #[error("[line {0}:{1}] Can't find module '{3}'")]
+    ModuleNotFound(usize, usize, usize, String),
+    // When modules import each other in a loop e.g. a.psi imports b.psi which imports a.psi
+    #[error("[line {0}:{1}] Cyclic import: {3}")]
+    CyclicImport(usize, usize, usize, String),
+    // When destructuring gets the wrong number of values e.g. "var (a, b) = (1, 2, 3)"
+    #[error("[line {0}:{1}] Expected {3} values to unpack but got {4}")]
+    UnpackMismatch(usize, usize, usize, String, usize),
+    // When an index or slice goes past the end of a sequence e.g. "[1, 2][2]"
+    #[error("[line {0}:{1}] Index {3} is out of range for a length of {4}")]
+    IndexOutOfRange(usize, usize, usize, String, usize),
+    // When a map is indexed with a key it doesn't have e.g. "{"a": 1}["b"]"
+    #[error("[line {0}:{1}] Key {3} not found")]
+    MissingKey(usize, usize, usize, String),
+    // When a range is given a step that would never reach the end e.g. "0..10 step 0"
+    #[error("[line {0}:{1}] Range step can't be zero")]
+    ZeroStep(usize, usize, usize),
+    // When yield is used outside of a function e.g. "yield 3"
+    #[error("[line {0}:{1}] Can't yield from outside a function")]
+    YieldOutsideFunction(usize, usize, usize),
+    // When a generator resumes itself e.g. calling "steps.next()" within steps
+    #[error("[line {0}:{1}] Can't resume a generator that is already running")]
+    GeneratorRunning(usize, usize, usize),
+    // When an enum lists the same variant twice e.g. "enum Mode { Fast, Fast }"
+    #[error("[line {0}:{1}] Variant '{3}' is already part of this enum")]
+    RepeatedVariant(usize, usize, usize, String),
+    // When an assertion doesn't hold e.g. "assert port > 1024"
+    #[error("[line {0}:{1}] Assertion failed: {3}")]
+    AssertionFailed(usize, usize, usize, String),
+    // When a thrown value isn't caught e.g. "throw "no hosts""
+    #[error("[line {0}:{1}] Uncaught exception: {3}")]
+    Thrown(usize, usize, usize, String),
+    // When an error occurs within an imported module
+    #[error("{1} (in {})", crate::module::name(std::path::Path::new(.0)))]
+    InModule(String, Box<Error>),
     // When the user specifies a non-existant e.g. `a * b = 3`
     #[error("[line {0}:{1}] Invalid assignment target")]
     InvalidAssignmentTarget(usize, usize, usize),
 }
 
+pub fn arity(required: usize, fixed: usize, variadic: bool) -> String {
+    // Describe how many arguments a function takes e.g. "1 to 3" or "at least 2"
+    if variadic {
+        format!("at least {}", required)
+    } else if required == fixed {
+        required.to_string()
+    } else {
+        format!("{} to {}", required, fixed)
+    }
+}
+
 impl Error {
-    pub fn display_line(&self, line: &str, repl: bool) {
-        // This is a function that creates very nice error reporting info
-        let (col, len) = match self {
+    pub fn line(&self) -> usize {
+        // Find out which line of the source code this error occured on
+        match self {
+            Error::UnexpectedCharacter(_, l, _, _) => *l,
+            Error::UnexpectedEOI(_, l, _, _) => *l,
+            Error::ExpectedToken(_, l, _, _) => *l,
+            Error::ExpectedExpression(l, _, _) => *l,
+            Error::MismatchedTypes(l, _, _, _) => *l,
+            Error::ImpossibleOperation(l, _, _, _) => *l,
+            Error::UndefinedVariable(l, _, _, _) => *l,
+            Error::IntegerOverflow(l, _, _, _) => *l,
+            Error::DivisionByZero(l, _, _) => *l,
+            Error::NoMatchingArm(l, _, _, _) => *l,
+            Error::UnreachableArm(l, _, _) => *l,
+            Error::ConstantAssignment(l, _, _, _, _, _) => *l,
+            Error::ConstantOverride(_) => 0,
+            Error::WrongArgumentCount(l, _, _, _, _) => *l,
+            Error::UnknownKeyword(l, _, _, _) => *l,
+            Error::RepeatedArgument(l, _, _, _) => *l,
+            Error::MissingArgument(l, _, _, _) => *l,
+            Error::ReturnOutsideFunction(l, _, _) => *l,
+            Error::StackOverflow(l, _, _) => *l,
+            Error::UnknownType(l, _, _, _) => *l,
+            Error::UndefinedProperty(l, _, _, _) => *l,
+            Error::ModuleNotFound(l, _, _, _) => *l,
+            Error::CyclicImport(l, _, _, _) => *l,
+            Error::UnpackMismatch(l, _, _, _, _) => *l,
+            Error::IndexOutOfRange(l, _, _, _, _) => *l,
+            Error::MissingKey(l, _, _, _) => *l,
+            Error::ZeroStep(l, _, _) => *l,
+            Error::YieldOutsideFunction(l, _, _) => *l,
+            Error::GeneratorRunning(l, _, _) => *l,
+            Error::RepeatedVariant(l, _, _, _) => *l,
+            Error::AssertionFailed(l, _, _, _) => *l,
+            Error::Thrown(l, _, _, _) => *l,
+            Error::InModule(_, error) => error.line(),
+            Error::InvalidAssignmentTarget(l, _, _) => *l,
+        }
+    }
+
+    pub fn file(&self) -> Option<&str> {
+        // Find out which module this error occured in, if it wasn't the main one
+        match self {
+            Error::InModule(path, error) => error.file().or(Some(path)),
+            _ => None,
+        }
+    }
+
+    pub fn span(&self) -> (usize, usize) {
+        // Find out which columns of the line this error covers
+        match self {
             Error::UnexpectedCharacter(_, _, c, l) => (*c, *l),
-            Error::UnexpectedEOI(_) => (width::width(line) + 1, 0),
+            Error::UnexpectedEOI(_, _, c, l) => (*c, *l),
             Error::ExpectedToken(_, _, c, l) => (*c, *l),
             Error::ExpectedExpression(_, c, l) => (*c, *l),
             Error::MismatchedTypes(_, c, l, _) => (*c, *l),
             Error::ImpossibleOperation(_, c, l, _) => (*c, *l),
             Error::UndefinedVariable(_, c, l, _) => (*c, *l),
+            Error::IntegerOverflow(_, c, l, _) => (*c, *l),
+            Error::DivisionByZero(_, c, l) => (*c, *l),
+            Error::NoMatchingArm(_, c, l, _) => (*c, *l),
+            Error::UnreachableArm(_, c, l) => (*c, *l),
+            Error::ConstantAssignment(_, c, l, _, _, _) => (*c, *l),
+            Error::ConstantOverride(_) => (1, 0),
+            Error::WrongArgumentCount(_, c, l, _, _) => (*c, *l),
+            Error::UnknownKeyword(_, c, l, _) => (*c, *l),
+            Error::RepeatedArgument(_, c, l, _) => (*c, *l),
+            Error::MissingArgument(_, c, l, _) => (*c, *l),
+            Error::ReturnOutsideFunction(_, c, l) => (*c, *l),
+            Error::StackOverflow(_, c, l) => (*c, *l),
+            Error::UnknownType(_, c, l, _) => (*c, *l),
+            Error::UndefinedProperty(_, c, l, _) => (*c, *l),
+            Error::ModuleNotFound(_, c, l, _) => (*c, *l),
+            Error::CyclicImport(_, c, l, _) => (*c, *l),
+            Error::UnpackMismatch(_, c, l, _, _) => (*c, *l),
+            Error::IndexOutOfRange(_, c, l, _, _) => (*c, *l),
+            Error::MissingKey(_, c, l, _) => (*c, *l),
+            Error::ZeroStep(_, c, l) => (*c, *l),
+            Error::YieldOutsideFunction(_, c, l) => (*c, *l),
+            Error::GeneratorRunning(_, c, l) => (*c, *l),
+            Error::RepeatedVariant(_, c, l, _) => (*c, *l),
+            Error::AssertionFailed(_, c, l, _) => (*c, *l),
+            Error::Thrown(_, c, l, _) => (*c, *l),
+            Error::InModule(_, error) => error.span(),
             Error::InvalidAssignmentTarget(_, c, l) => (*c, *l),
-        };
+        }
+    }
+
+    pub fn message(&self) -> String {
+        // Describe this error without saying where it happened
+        match self {
+            Error::InModule(_, error) => error.message(),
+            _ => {
+                let message = self.to_string();
+                match message.split_once("] ") {
+                    Some((location, rest)) if location.starts_with("[line") => rest.to_string(),
+                    _ => message,
+                }
+            }
+        }
+    }
+
+    pub fn display_line(&self, line: &str, repl: bool) {
+        // This is a function that creates very nice error reporting info
+        if let Error::InModule(_, error) = self {
+            return error.display_line(line, repl);
+        }
+        let (col, len) = self.span();
         // Split the source code into a list of strings
         let mut line: Vec<&str> = line.graphemes(true).collect();
-        // Work out the part of the code before the problematic area
-        let before: &str = &line[0..col - 1].join("");
-        let (during, after);
+        // Keep the problematic area within the line
+        let col = col.clamp(1, line.len() + 1);
         // Work out if we are reporting a column out of the span of the source
-        if col > line.len() {
+        let end = if col > line.len() {
             // Insert a space to allow for reporting of invisible end tokens
             line.push(" ");
-            // Work out the offending part and initiate an empty after part
-            during = line[col - 1..col + len].join("");
-            after = "".to_string();
+            col
         } else {
-            // Grab the offending source code area and the part afterwards
-            during = line[col - 1..col + len - 1].join("");
-            after = line[col + len - 1..].join("");
+            (col + len - 1).min(line.len())
         };
+        // Split into the part before, the offending part and the part afterwards
+        let before = line[0..col - 1].join("");
+        let during = line[col - 1..end].join("");
+        let after = line[end..].join("");
         // Format it and print it out
         println!(
             "{}{}{}{}{}{}{}{}{}{}{}", 
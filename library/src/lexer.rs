@@ -1,37 +1,59 @@
 // lexer.rs - For turning streams of characters into tokens
-use unicode_width::UnicodeWidthStr as width;
+use std::collections::VecDeque;
+use std::borrow::Cow;
+use std::ops::Range;
 use crate::Error;
 use std::fmt;
 
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, PartialEq)]
-pub enum TokenKind {
+pub enum TokenKind<'src> {
     // Single character tokens
     Plus, Minus, Asterisk, Slash, Percent, Hat,
-    LeftParen, RightParen, Exclamation,
-    Equal, LeftCurly, RightCurly, Semicolon,
+    LeftParen, RightParen, LeftSquare, RightSquare, Exclamation, Dot,
+    Equal, LeftCurly, RightCurly, Question, Colon, Comma, Pipe, Ampersand, Tilde,
     // Double character tokens
-    Equals, NotEquals,
-    Greater, Less, GreaterEq, LessEq,
+    Equals, NotEquals, FatArrow, Arrow, DotDot, DotDotEq, DotDotDot,
+    QuestionQuestion, QuestionDot, QuestionSquare,
+    Greater, Less, GreaterEq, LessEq, GreaterGreater, LessLess, HatHat, PipeGreater,
+    PlusEqual, MinusEqual, AsteriskEqual, SlashEqual, PercentEqual, HatEqual,
     // Datatypes
-    Number(f64), String(String),
+    Integer(i64), Number(f64), String(Cow<'src, str>),
     // Keywords
-    True, False, Nil, Not, Print, Var,
+    True, False, Nil, Not, Print, Var, Const, Div, If, Then, Else, Match, Fn, Return, Struct,
+    Enum, Import, From, As, Throw, Try, Catch, Finally, For, In, Yield, Assert,
     // Identifiers
-    Identifier(String),
+    Identifier(&'src str),
     // Special
-    EOI, Comment, Delim,
+    EOI, Comment, DocComment(&'src str), Delim,
 }
 
-impl fmt::Display for TokenKind {
+impl TokenKind<'_> {
+    pub fn is_comment(&self) -> bool {
+        // Determine if this token should be skipped over by the compiler
+        matches!(self, Self::Comment | Self::DocComment(_))
+    }
+
+    pub fn is_assignment(&self) -> bool {
+        // Determine if this token assigns to the expression before it
+        matches!(
+            self, 
+            Self::Equal | Self::PlusEqual | Self::MinusEqual | Self::AsteriskEqual |
+            Self::SlashEqual | Self::PercentEqual | Self::HatEqual
+        )
+    }
+}
+
+impl fmt::Display for TokenKind<'_> {
     #[cfg(not(tarpaulin_include))]
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         // Define how to display token kinds
         match self {
-            Self::Number(_) => write!(fmt, "number"),
+            Self::Integer(_) | Self::Number(_) => write!(fmt, "number"),
             Self::String(_) => write!(fmt, "string"),
             Self::Identifier(_) => write!(fmt, "identifier"),
             Self::Comment => write!(fmt, "comment"),
+            Self::DocComment(_) => write!(fmt, "doc comment"),
             Self::Plus => write!(fmt, "'+'"),
             Self::Minus => write!(fmt, "'-'"),
             Self::Asterisk => write!(fmt, "'*'"),
@@ -41,22 +63,69 @@ impl fmt::Display for TokenKind {
             Self::Equal => write!(fmt, "'='"),
             Self::LeftParen => write!(fmt, "'('"),
             Self::RightParen => write!(fmt, "')'"),
+            Self::LeftSquare => write!(fmt, "'['"),
+            Self::RightSquare => write!(fmt, "']'"),
             Self::LeftCurly => write!(fmt, "'{{'"),
             Self::RightCurly => write!(fmt, "'}}'"),
             Self::Exclamation => write!(fmt, "'!'"),
+            Self::Dot => write!(fmt, "'.'"),
             Self::Greater => write!(fmt, "'>'"),
             Self::Less => write!(fmt, "'<'"),
-            Self::Semicolon => write!(fmt, "';'"),
+            Self::Question => write!(fmt, "'?'"),
+            Self::Colon => write!(fmt, "':'"),
+            Self::Comma => write!(fmt, "','"),
+            Self::Pipe => write!(fmt, "'|'"),
+            Self::Ampersand => write!(fmt, "'&'"),
+            Self::Tilde => write!(fmt, "'~'"),
+            Self::GreaterGreater => write!(fmt, "'>>'"),
+            Self::LessLess => write!(fmt, "'<<'"),
+            Self::HatHat => write!(fmt, "'^^'"),
+            Self::PipeGreater => write!(fmt, "'|>'"),
             Self::GreaterEq => write!(fmt, "'>='"),
             Self::LessEq => write!(fmt, "'<='"),
             Self::Equals => write!(fmt, "'=='"),
             Self::NotEquals => write!(fmt, "'!='"),
+            Self::FatArrow => write!(fmt, "'=>'"),
+            Self::Arrow => write!(fmt, "'->'"),
+            Self::DotDot => write!(fmt, "'..'"),
+            Self::DotDotEq => write!(fmt, "'..='"),
+            Self::DotDotDot => write!(fmt, "'...'"),
+            Self::QuestionQuestion => write!(fmt, "'??'"),
+            Self::QuestionDot => write!(fmt, "'?.'"),
+            Self::QuestionSquare => write!(fmt, "'?['"),
+            Self::PlusEqual => write!(fmt, "'+='"),
+            Self::MinusEqual => write!(fmt, "'-='"),
+            Self::AsteriskEqual => write!(fmt, "'*='"),
+            Self::SlashEqual => write!(fmt, "'/='"),
+            Self::PercentEqual => write!(fmt, "'%='"),
+            Self::HatEqual => write!(fmt, "'^='"),
             Self::True => write!(fmt, "'true'"),
             Self::False => write!(fmt, "'false'"),
             Self::Nil => write!(fmt, "'nil'"),
             Self::Not => write!(fmt, "'not'"),
             Self::Print => write!(fmt, "'print'"),
             Self::Var => write!(fmt, "'var'"),
+            Self::Const => write!(fmt, "'const'"),
+            Self::Div => write!(fmt, "'div'"),
+            Self::If => write!(fmt, "'if'"),
+            Self::Then => write!(fmt, "'then'"),
+            Self::Else => write!(fmt, "'else'"),
+            Self::Match => write!(fmt, "'match'"),
+            Self::Fn => write!(fmt, "'fn'"),
+            Self::Return => write!(fmt, "'return'"),
+            Self::Struct => write!(fmt, "'struct'"),
+            Self::Enum => write!(fmt, "'enum'"),
+            Self::Import => write!(fmt, "'import'"),
+            Self::From => write!(fmt, "'from'"),
+            Self::As => write!(fmt, "'as'"),
+            Self::Throw => write!(fmt, "'throw'"),
+            Self::Yield => write!(fmt, "'yield'"),
+            Self::Assert => write!(fmt, "'assert'"),
+            Self::Try => write!(fmt, "'try'"),
+            Self::Catch => write!(fmt, "'catch'"),
+            Self::Finally => write!(fmt, "'finally'"),
+            Self::For => write!(fmt, "'for'"),
+            Self::In => write!(fmt, "'in'"),
             Self::Delim => write!(fmt, "delimeter"),
             Self::EOI => write!(fmt, "end of input"),
         }
@@ -64,321 +133,535 @@ impl fmt::Display for TokenKind {
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct Token {
-    pub kind: TokenKind,
-    pub start: usize,
+pub struct Token<'src> {
+    pub kind: TokenKind<'src>,
+    pub span: Range<usize>,
     pub len: usize,
     pub line: usize,
     pub col: usize,
 }
 
-pub struct Lexer {
-    chars: Vec<char>,
-    pub tokens: Vec<Token>,
-    level: usize,
+#[derive(Clone)]
+pub struct Lexer<'src> {
+    src: &'src str,
+    ready: VecDeque<Token<'src>>,
+    held: Vec<Token<'src>>,
+    delim: Option<Token<'src>>,
+    last: Option<TokenKind<'src>>,
+    // The kinds of bracket that are open, innermost last
+    brackets: Vec<char>,
     ptr: usize,
     line: usize,
     col: usize,
+    done: bool,
 }
 
-impl Lexer {
-    pub fn new(src: &str) -> Self {
+impl<'src> Lexer<'src> {
+    pub fn new(src: &'src str) -> Self {
         // Create a new lexer
         Self {
-            chars: src.chars().collect(),
-            tokens: vec![],
-            level: 0,
+            src,
+            ready: VecDeque::new(),
+            held: vec![],
+            delim: None,
+            last: None,
+            brackets: vec![],
             ptr: 0,
             line: 1,
-            col: 1
+            col: 1,
+            done: false,
         }
     }
 
-    pub fn run(&mut self) -> Result<(), Error> {
-        // Run the lexer
-        while let Some(c) = self.get() {
-            let (ptr, line, col) = (self.ptr, self.line, self.col);
-            match c {
-                // Capture single character tokens
-                '+' => self.mk_token(TokenKind::Plus, 1),
-                '-' => self.mk_token(TokenKind::Minus, 1),
-                '*' => self.mk_token(TokenKind::Asterisk, 1),
-                '%' => self.mk_token(TokenKind::Percent, 1),
-                '^' => self.mk_token(TokenKind::Hat, 1),
-                '(' => self.mk_token(TokenKind::LeftParen, 1),
-                ')' => self.mk_token(TokenKind::RightParen, 1),
-                '{' => self.mk_token(TokenKind::LeftCurly, 1),
-                '}' => self.mk_token(TokenKind::RightCurly, 1),
-                ';' => self.mk_token(TokenKind::Delim, 1),
-                // Check for the == token
-                '=' => if self.peek(1) == Some('=') {
-                    self.advance();
-                    self.mk_long_token(TokenKind::Equals, [2, ptr, line, col])
-                } else {
-                    self.mk_token(TokenKind::Equal, 1)
-                }
-                // Check for the > or >= tokens
-                '>' => if self.peek(1) == Some('=') {
-                    self.advance();
-                    self.mk_long_token(TokenKind::GreaterEq, [2, ptr, line, col]);
-                } else {
-                    self.mk_token(TokenKind::Greater, 1)
+    pub fn source(&self, span: Range<usize>) -> &'src str {
+        // Give back the source code that some tokens were lexed from
+        &self.src[span]
+    }
+
+    fn scan(&mut self) -> Result<(), Error> {
+        // Lex the next token from the source
+        let (ptr, line, col) = (self.ptr, self.line, self.col);
+        let c = if let Some(c) = self.get() { c } else {
+            self.finish();
+            return Ok(());
+        };
+        self.advance();
+        let kind = match c {
+            // Capture single character tokens
+            // Check for compound assignment tokens e.g. +=
+            '+' => if self.eat('=') { TokenKind::PlusEqual } else { TokenKind::Plus },
+            '-' => if self.eat('=') { 
+                TokenKind::MinusEqual 
+            } else if self.eat('>') { 
+                TokenKind::Arrow 
+            } else { 
+                TokenKind::Minus 
+            }
+            '*' => if self.eat('=') { TokenKind::AsteriskEqual } else { TokenKind::Asterisk },
+            '%' => if self.eat('=') { TokenKind::PercentEqual } else { TokenKind::Percent },
+            // Check for the ^=, ^^ or ^ tokens
+            '^' => if self.eat('=') { 
+                TokenKind::HatEqual 
+            } else if self.eat('^') { 
+                TokenKind::HatHat 
+            } else { 
+                TokenKind::Hat 
+            }
+            // Check for the .., ..= or ... tokens
+            '.' => if self.eat('.') {
+                if self.eat('=') { 
+                    TokenKind::DotDotEq 
+                } else if self.eat('.') { 
+                    TokenKind::DotDotDot 
+                } else { 
+                    TokenKind::DotDot 
                 }
-                // Check for the < or <= tokens
-                '<' => if self.peek(1) == Some('=') {
+            } else {
+                TokenKind::Dot
+            }
+            '(' => TokenKind::LeftParen,
+            ')' => TokenKind::RightParen,
+            '[' => TokenKind::LeftSquare,
+            ']' => TokenKind::RightSquare,
+            '{' => TokenKind::LeftCurly,
+            '}' => TokenKind::RightCurly,
+            ';' => TokenKind::Delim,
+            ',' => TokenKind::Comma,
+            // Check for the |> or | tokens
+            '|' => if self.eat('>') { TokenKind::PipeGreater } else { TokenKind::Pipe },
+            '&' => TokenKind::Ampersand,
+            '~' => TokenKind::Tilde,
+            // Check for the ??, ?. or ?[ tokens
+            '?' => if self.eat('?') {
+                TokenKind::QuestionQuestion
+            } else if self.eat('.') {
+                TokenKind::QuestionDot
+            } else if self.eat('[') {
+                TokenKind::QuestionSquare
+            } else {
+                TokenKind::Question
+            }
+            ':' => TokenKind::Colon,
+            // Check for the == token
+            '=' => if self.eat('=') { 
+                TokenKind::Equals 
+            } else if self.eat('>') { 
+                TokenKind::FatArrow 
+            } else { 
+                TokenKind::Equal 
+            }
+            // Check for the >, >= or >> tokens
+            '>' => if self.eat('=') { 
+                TokenKind::GreaterEq 
+            } else if self.eat('>') { 
+                TokenKind::GreaterGreater 
+            } else { 
+                TokenKind::Greater 
+            }
+            // Check for the <, <= or << tokens
+            '<' => if self.eat('=') { 
+                TokenKind::LessEq 
+            } else if self.eat('<') { 
+                TokenKind::LessLess 
+            } else { 
+                TokenKind::Less 
+            }
+            // Check for ! or != token
+            '!' => if self.eat('=') { TokenKind::NotEquals } else { TokenKind::Exclamation },
+            // Check for a single line or multiline comment or / token
+            '/' => if self.eat('/') {
+                // Single line comment (or doc comment if there are three slashes)
+                let doc = self.get() == Some('/') && self.peek(1) != Some('/');
+                if doc { self.advance(); }
+                let start = self.ptr;
+                // Keep on walkin' to the end of the line
+                while !matches!(self.get(), Some('\n') | None) {
                     self.advance();
-                    self.mk_long_token(TokenKind::LessEq, [2, ptr, line, col]);
-                } else {
-                    self.mk_token(TokenKind::Less, 1)
                 }
-                // Check for ! or != token
-                '!' => if self.peek(1) == Some('=') { 
-                    self.advance();
-                    self.mk_long_token(TokenKind::NotEquals, [2, ptr, line, col])
+                if doc {
+                    let text = &self.src[start..self.ptr];
+                    TokenKind::DocComment(text.strip_prefix(' ').unwrap_or(text).trim_end())
                 } else {
-                    self.mk_token(TokenKind::Exclamation, 1)
+                    TokenKind::Comment
                 }
-                // Check for a single line or multiline comment or / token
-                '/' => if self.peek(1) == Some('/') {
-                    // Single line comment
-                    let mut len = 2;
-                    self.advance();
-                    self.advance();
-                    while let Some(c) = self.get() {
-                        // Keep on walkin' to the end of the line
-                        if c == '\n' {
-                            self.line += 1;
-                            self.col = 1;
-                            break
-                        } else {
-                            len += 1;
+            } else if self.eat('*') {
+                // Mulitline comment, these can be nested
+                let mut depth = 1;
+                while depth > 0 {
+                    // Keep on walkin' to the end of the comment
+                    match (self.get(), self.peek(1)) {
+                        (Some('/'), Some('*')) => {
+                            depth += 1;
                             self.advance();
                         }
-                    }
-                    self.mk_long_token(TokenKind::Comment, [len, ptr, line, col]);
-                } else if Some('*') == self.peek(1) {
-                    // Mulitline comment
-                    let mut len = 2;
-                    self.advance();
-                    self.advance();
-                    while let Some(c) = self.get() {
-                        // Keep on walkin' to the end of the comment
-                        if c == '*' && self.peek(1) == Some('/') {
-                            self.advance();
-                            break;
-                        } else if c == '\n' {
-                            self.line += 1;
-                            self.col = 1;
-                            len += 1;
+                        (Some('*'), Some('/')) => {
+                            depth -= 1;
                             self.advance();
-                        } else {
-                            len += 1;
-                            self.advance();
-                        }
-                    }
-                    self.mk_long_token(TokenKind::Comment, [len + 2, ptr, line, col]);
-                } else {
-                    // Just your average slash character
-                    self.mk_token(TokenKind::Slash, 1);
-                }
-                // Capture identifiers and keywords
-                'a'..='z' => { 
-                    self.word()?; 
-                    continue; 
-                }
-                // Capture numbers
-                '0'..='9' => { 
-                    self.number();
-                    continue;
-                }
-                // Capture strings
-                '"' => { 
-                    self.string()?;
-                    continue;
-                }
-                // Ignore whitespace
-                ' ' | '\t' => (),
-                // Handle newline
-                '\n' => {
-                    self.line += 1;
-                    self.col = 1;
-                    if let Some(tok) = self.tokens.last() {
-                        match tok.kind {
-                            TokenKind::RightParen |
-                            TokenKind::RightCurly | 
-                            TokenKind::String(_) |
-                            TokenKind::Number(_) |
-                            TokenKind::Identifier(_) |
-                            TokenKind::True | 
-                            TokenKind::False |
-                            TokenKind::Nil | 
-                            TokenKind::Comment => self.mk_token(TokenKind::Delim, 0),
-                            TokenKind::Semicolon => {
-                                self.tokens.pop();
-                                self.mk_token(TokenKind::Delim, 1);
-                            }
-                            _ => (),
                         }
+                        (Some(_), _) => (),
+                        // Unterminated comment!
+                        (None, _) => return Err(Error::UnexpectedEOI(
+                            "Unterminated comment".to_string(), line, col, 2
+                        )),
                     }
+                    self.advance();
                 }
-                // Handle the event of an unrecognised character
-                _ => return Err(Error::UnexpectedCharacter(c, self.line, self.col, 1)),
+                TokenKind::Comment
+            } else if self.eat('=') {
+                TokenKind::SlashEqual
+            } else {
+                // Just your average slash character
+                TokenKind::Slash
             }
-            self.advance();
+            // Capture raw strings
+            'r' if matches!(self.get(), Some('"') | Some('#')) => self.raw_string(ptr, line, col)?,
+            // Capture identifiers and keywords
+            'a'..='z' | 'A'..='Z' | '_' => self.word(ptr),
+            // Capture numbers
+            '0'..='9' => self.number(ptr, line, col)?,
+            // Capture multiline strings
+            '"' if self.get() == Some('"') && self.peek(1) == Some('"') => {
+                self.long_string(ptr, line, col)?
+            }
+            // Capture strings
+            '"' => self.string(ptr, line, col)?,
+            // Ignore whitespace
+            ' ' | '\t' => return Ok(()),
+            // Handle newline
+            '\n' => {
+                self.newline(ptr, line, col);
+                return Ok(());
+            }
+            // Handle the event of an unrecognised character
+            _ => return Err(Error::UnexpectedCharacter(c, line, col, 1)),
+        };
+        self.mk_token(kind, ptr, line, col);
+        Ok(())
+    }
+
+    fn newline(&mut self, ptr: usize, line: usize, col: usize) {
+        // Newlines within brackets never end a statement, unless they are in a block
+        // (e.g. the body of a function passed as an argument)
+        if matches!(self.brackets.last(), Some('(' | '[')) || self.delim.is_some() { return }
+        // Decide if this newline should end the statement
+        if let Some(TokenKind::RightParen) |
+            Some(TokenKind::RightSquare) |
+            Some(TokenKind::RightCurly) | 
+            Some(TokenKind::String(_)) |
+            Some(TokenKind::Integer(_)) |
+            Some(TokenKind::Number(_)) |
+            Some(TokenKind::Identifier(_)) |
+            Some(TokenKind::True) | 
+            Some(TokenKind::False) |
+            Some(TokenKind::Nil) |
+            Some(TokenKind::Return) = self.last {
+            // Hold back the delimiter in case the next line continues this one
+            self.delim = Some(Token { 
+                kind: TokenKind::Delim, span: ptr..ptr, len: 0, line, col
+            });
         }
+    }
+
+    fn finish(&mut self) {
         // Add in a statement delimiter if needed
-        if let Some(Token{ kind: TokenKind::Delim, ..}) = self.tokens.last() {
-        } else {
-            self.mk_token(TokenKind::Delim, 0);
+        if let Some(delim) = self.delim.take() {
+            self.ready.push_back(delim);
+        } else if self.last != Some(TokenKind::Delim) {
+            self.ready.push_back(self.eoi(TokenKind::Delim));
         }
+        self.ready.extend(self.held.drain(..));
         // Append an EOI (end of input) token
-        self.mk_token(TokenKind::EOI, 0);
-        Ok(())
+        self.ready.push_back(self.eoi(TokenKind::EOI));
+        self.done = true;
     }
 
-    fn string(&mut self) -> Result<(), Error> {
+    fn string(&mut self, ptr: usize, line: usize, col: usize) -> Result<TokenKind<'src>, Error> {
         // Create a string token
-        let (ptr, line, col) = (self.ptr, self.line, self.col);
-        let mut result = String::new();
-        self.advance();
+        let start = self.ptr;
         loop {
             // Run through string characters
-            if let Some(c) = self.get() {
-                // There are still characters
-                if c == '"' && self.peek(-1) != Some('\\') {
-                    // A quote (non-escaped)
-                    self.advance();
-                    break;
-                } else if c == '\n' {
-                    // A newline token (allows for newlines in strings)
-                    self.line += 1;
-                    self.col = 1;
-                }
-                // Push the character into the strings content
-                self.advance();
-                result.push(c);
-            } else {
+            match self.get() {
+                // A quote (non-escaped)
+                Some('"') if !self.src[..self.ptr].ends_with('\\') => break,
+                // There are still characters (allows for newlines in strings)
+                Some(_) => self.advance(),
                 // Unterminated string!
-                return Err(Error::UnexpectedEOI("Unterminated string".to_string()));
+                None => return Err(Error::UnexpectedEOI(
+                    "Unterminated string".to_string(), line, col, self.span_len(ptr)
+                )),
             }
         }
-        // Create string token
-        let len = result.chars().count();
-        self.mk_long_token(
-            TokenKind::String(result), 
-            [len + 2, ptr, line, col]
-        );
-        Ok(())
+        let result = &self.src[start..self.ptr];
+        self.advance();
+        Ok(TokenKind::String(Cow::Borrowed(result)))
     }
 
-    fn number(&mut self) {
-        // Create a number token
-        let (ptr, line, col) = (self.ptr, self.line, self.col);
-        let mut result = String::new();
-        // Collect all digits
+    fn raw_string(&mut self, ptr: usize, line: usize, col: usize) -> Result<TokenKind<'src>, Error> {
+        // Create a string token from a raw string e.g. r"C:\Users" or r#"say "hi""#
+        let mut hashes = 0;
+        // Count the hashes that guard the string
+        while self.eat('#') {
+            hashes += 1;
+        }
+        if !self.eat('"') {
+            return match self.get() {
+                Some(c) => Err(Error::UnexpectedCharacter(c, self.line, self.col, 1)),
+                None => Err(Error::UnexpectedEOI(
+                    "Unterminated raw string".to_string(), line, col, self.span_len(ptr)
+                )),
+            };
+        }
+        let start = self.ptr;
+        loop {
+            // Run through string characters, escapes are not processed
+            match self.get() {
+                // A quote followed by the right number of hashes
+                Some('"') if (1..=hashes).all(|i| self.peek(i) == Some('#')) => break,
+                Some(_) => self.advance(),
+                // Unterminated string!
+                None => return Err(Error::UnexpectedEOI(
+                    "Unterminated raw string".to_string(), line, col, self.span_len(ptr)
+                )),
+            }
+        }
+        let result = &self.src[start..self.ptr];
+        for _ in 0..=hashes { self.advance(); }
+        Ok(TokenKind::String(Cow::Borrowed(result)))
+    }
+
+    fn long_string(&mut self, ptr: usize, line: usize, col: usize) -> Result<TokenKind<'src>, Error> {
+        // Create a string token from a triple quoted multiline string
+        self.advance();
+        self.advance();
+        let start = self.ptr;
+        loop {
+            // Run through string characters until the closing quotes
+            match self.get() {
+                Some('"') if self.peek(1) == Some('"') && self.peek(2) == Some('"') => break,
+                Some(_) => self.advance(),
+                // Unterminated string!
+                None => return Err(Error::UnexpectedEOI(
+                    "Unterminated string".to_string(), line, col, self.span_len(ptr)
+                )),
+            }
+        }
+        let result = &self.src[start..self.ptr];
+        for _ in 0..3 { self.advance(); }
+        Ok(TokenKind::String(dedent(result)))
+    }
+
+    fn number(&mut self, ptr: usize, line: usize, col: usize) -> Result<TokenKind<'src>, Error> {
+        // Create a number token, collecting all digits
         while let Some('0'..='9') = self.get() {
-            result.push(self.get().unwrap());
             self.advance();
         }
-        // Allow for float
-        if let Some('.') = self.get() {
-            result.push('.');
+        // Allow for float (but not a range e.g. 1..5, or a method call e.g. 5.abs())
+        let method = matches!(self.peek(1), Some('a'..='z' | 'A'..='Z' | '_'));
+        if self.get() == Some('.') && self.peek(1) != Some('.') && !method {
             self.advance();
             // Capture trailing numbers
             while let Some('0'..='9') = self.get() {
-                result.push(self.get().unwrap());
                 self.advance();
             }
+            return Ok(TokenKind::Number(self.src[ptr..self.ptr].parse().unwrap()));
+        }
+        // Otherwise it is an integer
+        let digits = &self.src[ptr..self.ptr];
+        match digits.parse() {
+            Ok(int) => Ok(TokenKind::Integer(int)),
+            // The smallest integer can only be written negated, the compiler makes sure it is
+            Err(_) if self.last == Some(TokenKind::Minus) && digits.parse() == Ok(1u64 << 63) =>
+                Ok(TokenKind::Integer(i64::MIN)),
+            Err(_) => Err(Error::IntegerOverflow(
+                line, col, self.span_len(ptr), "literal is too large".to_string()
+            )),
         }
-        // Create token and parse number
-        self.mk_long_token(
-            TokenKind::Number(result.parse().unwrap()), 
-            [result.chars().count(), ptr, line, col]
-        );
     }
 
-    fn word(&mut self) -> Result<(), Error> {
-        // For identifiers & keywords
-        let (ptr, line, col) = (self.ptr, self.line, self.col);
-        // Capture entire word
-        let mut word = String::new();
-        while let Some(c) = self.get() {
-            if let 'a'..='z' = c {
-                // Capture all the characters
-                word.push(c);
-                self.advance();
-            } else {
-                // Stop when you hit a non-alphabet character
-                break;
-            }
+    fn word(&mut self, ptr: usize) -> TokenKind<'src> {
+        // For identifiers & keywords, capture the entire word
+        while let Some('a'..='z' | 'A'..='Z' | '0'..='9' | '_') = self.get() {
+            self.advance();
         }
         // Look up the word and determine if keyword or identifier
-        match word.as_str() {
+        match &self.src[ptr..self.ptr] {
             // Keyword
-            "true" => self.mk_long_token(TokenKind::True, [4, ptr, line, col]),
-            "false" => self.mk_long_token(TokenKind::False, [5, ptr, line, col]),
-            "nil" => self.mk_long_token(TokenKind::Nil, [3, ptr, line, col]),
-            "not" => self.mk_long_token(TokenKind::Not, [3, ptr, line, col]),
-            "print" => self.mk_long_token(TokenKind::Print, [5, ptr, line, col]),
-            "var" => self.mk_long_token(TokenKind::Var, [3, ptr, line, col]),
+            "true" => TokenKind::True,
+            "false" => TokenKind::False,
+            "nil" => TokenKind::Nil,
+            "not" => TokenKind::Not,
+            "print" => TokenKind::Print,
+            "var" => TokenKind::Var,
+            "const" => TokenKind::Const,
+            "div" => TokenKind::Div,
+            "if" => TokenKind::If,
+            "then" => TokenKind::Then,
+            "else" => TokenKind::Else,
+            "match" => TokenKind::Match,
+            "fn" => TokenKind::Fn,
+            "return" => TokenKind::Return,
+            "struct" | "class" => TokenKind::Struct,
+            "enum" => TokenKind::Enum,
+            "import" => TokenKind::Import,
+            "from" => TokenKind::From,
+            "as" => TokenKind::As,
+            "throw" => TokenKind::Throw,
+            "try" => TokenKind::Try,
+            "catch" => TokenKind::Catch,
+            "finally" => TokenKind::Finally,
+            "for" => TokenKind::For,
+            "in" => TokenKind::In,
+            "yield" => TokenKind::Yield,
+            "assert" => TokenKind::Assert,
             // Identifier
-            _ => self.mk_long_token(
-                TokenKind::Identifier(word.clone()),
-                [width::width(word.as_str()), ptr, line, col]
-            ),
+            word => TokenKind::Identifier(word),
         }
-        Ok(())
     }
 
     fn advance(&mut self) {
-        // To move the character focus forward
-        self.ptr += 1;
-        self.col += 1;
+        // To move the character focus forward, keeping track of lines
+        if let Some(c) = self.get() {
+            if c == '\n' {
+                self.line += 1;
+                self.col = 1;
+            } else {
+                self.col += 1;
+            }
+            self.ptr += c.len_utf8();
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        // Move past a character if it is the current character focus
+        let found = self.get() == Some(c);
+        if found { self.advance(); }
+        found
+    }
+
+    fn span_len(&self, start: usize) -> usize {
+        // Work out the length of a token on the line it starts on
+        self.src[start..self.ptr].chars().take_while(|c| *c != '\n').count()
     }
 
-    fn get(&mut self) -> Option<char> {
+    fn get(&self) -> Option<char> {
         // To get the current character focus
-        Some(*self.chars.get(self.ptr)?)
+        self.src[self.ptr..].chars().next()
     }
 
-    fn peek(&mut self, vec: isize) -> Option<char> {
+    fn peek(&self, vec: usize) -> Option<char> {
         // Peek ahead a certain number of chars
-        Some(*self.chars.get((self.ptr as isize + vec) as usize)?)
+        self.src[self.ptr..].chars().nth(vec)
     }
 
-    fn mk_long_token(&mut self, kind: TokenKind, start: [usize; 4]) {
-        // Generates a long token with custom start points
-        self.tokens.push(Token {
-            kind,
-            len: start[0],
-            start: start[1],
-            line: start[2],
-            col: start[3],
-        })
+    fn eoi(&self, kind: TokenKind<'src>) -> Token<'src> {
+        // Generates a token at the end of the input
+        Token { kind, span: self.ptr..self.ptr, len: 0, line: self.line, col: self.col }
     }
 
-    fn mk_token(&mut self, kind: TokenKind, len: usize) {
-        // Generates a token from the current status
-        match kind {
-            TokenKind::LeftParen | TokenKind::LeftCurly =>  self.level += 1,
-            TokenKind::RightParen | TokenKind::RightCurly =>  self.level -= 1,
+    fn mk_token(&mut self, kind: TokenKind<'src>, ptr: usize, line: usize, col: usize) {
+        // Generates a token that starts at a certain point and ends at the focus
+        let len = self.span_len(ptr);
+        let token = Token { kind, span: ptr..self.ptr, len, line, col };
+        if token.kind.is_comment() {
+            // Comments wait behind any delimiter that is held back
+            if self.delim.is_some() { 
+                self.held.push(token);
+            } else {
+                self.ready.push_back(token);
+            }
+            return;
+        }
+        match token.kind {
+            TokenKind::LeftParen => self.brackets.push('('),
+            TokenKind::LeftSquare | TokenKind::QuestionSquare => self.brackets.push('['),
+            TokenKind::LeftCurly => self.brackets.push('{'),
+            TokenKind::RightParen | TokenKind::RightSquare | TokenKind::RightCurly => {
+                self.brackets.pop();
+            }
             _ => (),
         }
-        self.tokens.push(Token {
-            kind,
-            len,
-            start: self.ptr,
-            line: self.line,
-            col: self.col,
-        })
+        // Lines starting with a binary operator or '.' continue the previous line
+        // (as do the catch and finally clauses of a try statement)
+        // ('-' is left out as it may well be the start of a negation)
+        if let Some(delim) = self.delim.take() {
+            if !matches!(token.kind, TokenKind::Plus | TokenKind::Asterisk | 
+                TokenKind::Slash | TokenKind::Percent | TokenKind::Hat | TokenKind::Dot |
+                TokenKind::QuestionQuestion | TokenKind::QuestionDot |
+                TokenKind::Ampersand | TokenKind::HatHat | TokenKind::LessLess | TokenKind::GreaterGreater |
+                TokenKind::Equals | TokenKind::NotEquals |
+                TokenKind::Greater | TokenKind::GreaterEq |
+                TokenKind::Less | TokenKind::LessEq | TokenKind::Div |
+                TokenKind::Question | TokenKind::Colon | TokenKind::Then | TokenKind::Else |
+                TokenKind::Pipe | TokenKind::PipeGreater | TokenKind::FatArrow | TokenKind::Catch | TokenKind::Finally) {
+                self.ready.push_back(delim);
+            }
+        }
+        self.ready.extend(self.held.drain(..));
+        self.last = Some(token.kind.clone());
+        self.ready.push_back(token);
     }
 
     pub fn display(&self) {
         // Display the token stream
         print!("=> ");
-        for i in &self.tokens {
+        for i in self.clone().flatten() {
             print!("[ {:?} ] ", i.kind);
         }
         println!()
     }
 }
+
+impl<'src> Iterator for Lexer<'src> {
+    type Item = Result<Token<'src>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Lex until there is a token ready to hand over
+        loop {
+            if let Some(token) = self.ready.pop_front() {
+                return Some(Ok(token));
+            } else if self.done {
+                return None;
+            } else if let Err(error) = self.scan() {
+                self.done = true;
+                return Some(Err(error));
+            }
+        }
+    }
+}
+
+fn dedent(src: &str) -> Cow<'_, str> {
+    // Strip the indentation common to all lines of a multiline string
+    let mut lines: Vec<&str> = src.split('\n').collect();
+    // Ignore the line break after the opening quotes
+    if lines.len() > 1 && lines[0].trim().is_empty() {
+        lines.remove(0);
+    }
+    // Ignore the indentation before the closing quotes
+    if lines.len() > 1 && lines[lines.len() - 1].trim().is_empty() {
+        lines.pop();
+    }
+    // Only whitespace shared exactly by every line counts, so tabs and spaces aren't mixed up
+    let indent = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| &l[..l.len() - l.trim_start().len()])
+        .reduce(common_prefix)
+        .unwrap_or("");
+    let result = lines
+        .iter()
+        .map(|l| l.strip_prefix(indent).unwrap_or_else(|| l.trim_start()))
+        .collect::<Vec<&str>>()
+        .join("\n");
+    // Only allocate if the string was changed
+    if result == src { Cow::Borrowed(src) } else { Cow::Owned(result) }
+}
+
+fn common_prefix<'a>(a: &'a str, b: &str) -> &'a str {
+    // Find the longest start that two strings share, ending on a character boundary
+    let end = a
+        .char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map_or(a.len().min(b.len()), |((i, _), _)| i);
+    &a[..end]
+}
@@ -4,6 +4,9 @@ pub mod compiler;
 pub mod chunk;
 pub mod error;
 pub mod lexer;
+pub mod methods;
+pub mod module;
+pub mod types;
 pub mod value;
 pub mod vm;
 
@@ -14,5 +17,7 @@ pub use lexer::{Token, Lexer, TokenKind};
 pub use chunk::{OpCode, Chunk};
 pub use compiler::Compiler;
 pub use error::Error;
-pub use value::Value;
+pub use value::{Value, Userdata, Function, Struct, Instance, Enum, Variant, Module, Map, Range, Generator, Iter, Source, Step};
+pub use module::Loader;
+pub use types::{Type, Params};
 pub use vm::VM;
@@ -0,0 +1,172 @@
+// methods.rs - The built-in methods of strings, numbers and other values that aren't instances
+use crate::{Error, Params, Type, Value};
+use unicode_segmentation::UnicodeSegmentation;
+use std::convert::TryFrom;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub fn kind(value: &Value) -> Option<Type> {
+    // Work out which built-in type a value is, if it has built-in methods
+    Some(match value {
+        Value::Integer(_) => Type::Integer,
+        Value::Number(_) => Type::Number,
+        Value::Boolean(_) => Type::Boolean,
+        Value::String(_) => Type::String,
+        Value::Nil => Type::Nil,
+        Value::Tuple(_) => Type::Tuple(vec![]),
+        Value::List(_) => Type::List(Box::new(Type::Any)),
+        Value::Map(_) => Type::Map(Box::new(Type::Any), Box::new(Type::Any)),
+        Value::Range(_) => Type::Range,
+        _ => return None,
+    })
+}
+
+fn lookup(kind: &Type, name: &str) -> Option<(Vec<Type>, usize, Type)> {
+    // Find the parameters, how many of them are required, and the result of a method
+    Some(match (kind, name) {
+        (Type::String, "len") => (vec![], 0, Type::Integer),
+        (Type::String, "upper") | (Type::String, "lower") | (Type::String, "trim") =>
+            (vec![], 0, Type::String),
+        // Splitting without a separator splits on whitespace
+        (Type::String, "split") => (vec![Type::String], 0, Type::List(Box::new(Type::String))),
+        (Type::String, "replace") => (vec![Type::String, Type::String], 2, Type::String),
+        (Type::String, "starts_with") | (Type::String, "ends_with") | (Type::String, "contains") =>
+            (vec![Type::String], 1, Type::Boolean),
+        // Finding where some text is gives nil if it isn't there
+        (Type::String, "index") => (vec![Type::String], 1, Type::Integer.join(Type::Nil)),
+        (Type::Integer, "floor") | (Type::Integer, "round") | (Type::Integer, "abs") |
+        (Type::Number, "floor") | (Type::Number, "round") => (vec![], 0, Type::Integer),
+        (Type::Number, "abs") => (vec![], 0, Type::Number),
+        (Type::List(item), "push") => (vec![*item.clone()], 1, Type::Nil),
+        // Taking from an empty list gives nil
+        (Type::List(item), "pop") => (vec![], 0, item.clone().join(Type::Nil)),
+        (Type::List(item), "contains") => (vec![*item.clone()], 1, Type::Boolean),
+        (Type::List(_), "join") => (vec![Type::String], 1, Type::String),
+        (Type::Map(key, _), "keys") => (vec![], 0, Type::List(key.clone())),
+        (Type::Map(_, value), "values") => (vec![], 0, Type::List(value.clone())),
+        (Type::Map(key, _), "contains") => (vec![*key.clone()], 1, Type::Boolean),
+        (Type::List(_), "len") | (Type::Tuple(_), "len") | (Type::Map(_, _), "len") |
+        (Type::Range, "len") => (vec![], 0, Type::Integer),
+        // Every built-in value can be turned into a string
+        (_, "to_string") => (vec![], 0, Type::String),
+        _ => return None,
+    })
+}
+
+pub fn signature(kind: &Type, name: &str) -> Option<Type> {
+    // Give back the type of a built-in method, for checking calls to it
+    let (kinds, required, returns) = lookup(kind, name)?;
+    let params = Params { kinds, names: vec![], required, variadic: false };
+    Some(Type::Function(params, Box::new(returns)))
+}
+
+pub fn call(
+    object: Value,
+    name: &str,
+    args: Vec<Value>,
+    line: usize,
+    (col, len): (usize, usize),
+    show: &mut dyn FnMut(&Value) -> Result<String, Error>,
+) -> Result<Value, Error> {
+    // Carry out a built-in method on a value, showing values as text with the function given
+    // (which goes through the to_string methods of instances)
+    let kinds = kind(&object).and_then(|kind| lookup(&kind, name));
+    let (kinds, required, _) = if let Some(found) = kinds { found } else {
+        return Err(Error::UndefinedProperty(line, col, len, name.to_string()));
+    };
+    if args.len() < required || args.len() > kinds.len() {
+        let expected = crate::error::arity(required, kinds.len(), false);
+        return Err(Error::WrongArgumentCount(line, col, len, expected, args.len()));
+    }
+    // Arguments aren't always known when compiling, so they are checked again here
+    let mismatch = |expected: &Type| Error::MismatchedTypes(
+        line, col, len, format!("'{}' expected {}", name, expected)
+    );
+    for (expected, arg) in kinds.iter().zip(&args) {
+        // Values without built-in methods (e.g. instances) only fit where anything goes
+        let fits = match kind(arg) {
+            Some(found) => expected.accepts(&found),
+            None => *expected == Type::Any,
+        };
+        if !fits {
+            return Err(Error::MismatchedTypes(
+                line, col, len, format!("'{}' expected {} but found {}", name, expected, arg)
+            ));
+        }
+    }
+    let value = |idx: usize| args.get(idx).ok_or_else(|| mismatch(&kinds[idx]));
+    let text = |idx: usize| match value(idx)? {
+        Value::String(s) => Ok(s.as_str()),
+        _ => Err(mismatch(&Type::String)),
+    };
+    let whole = |f: f64| {
+        // Convert a float to an integer, as long as it fits
+        if f.is_finite() && f >= i64::MIN as f64 && f < i64::MAX as f64 {
+            Ok(Value::Integer(f as i64))
+        } else {
+            Err(Error::IntegerOverflow(line, col, len, format!("'{}' overflowed", name)))
+        }
+    };
+    Ok(match (object, name) {
+        (Value::String(s), "len") => Value::Integer(s.graphemes(true).count() as i64),
+        (Value::String(s), "upper") => Value::String(s.to_uppercase()),
+        (Value::String(s), "lower") => Value::String(s.to_lowercase()),
+        (Value::String(s), "trim") => Value::String(s.trim().to_string()),
+        (Value::String(s), "split") => {
+            let parts: Vec<Value> = match args.first() {
+                None => s.split_whitespace().map(|p| Value::String(p.to_string())).collect(),
+                // An empty separator splits the string into its characters
+                Some(_) if text(0)?.is_empty() => s.graphemes(true).map(|p| Value::String(p.to_string())).collect(),
+                Some(_) => s.split(text(0)?).map(|p| Value::String(p.to_string())).collect(),
+            };
+            Value::List(Rc::new(RefCell::new(parts)))
+        }
+        (Value::String(s), "replace") =>
+            Value::String(s.replace(text(0)?, text(1)?)),
+        (Value::String(s), "starts_with") => Value::Boolean(s.starts_with(text(0)?)),
+        (Value::String(s), "ends_with") => Value::Boolean(s.ends_with(text(0)?)),
+        (Value::String(s), "contains") => Value::Boolean(s.contains(text(0)?)),
+        (Value::String(s), "index") => match s.find(text(0)?) {
+            // Positions are counted in characters, like the length
+            Some(at) => Value::Integer(s[..at].graphemes(true).count() as i64),
+            None => Value::Nil,
+        },
+        (Value::Integer(i), "floor") | (Value::Integer(i), "round") => Value::Integer(i),
+        (Value::Integer(i), "abs") => match i.checked_abs() {
+            Some(i) => Value::Integer(i),
+            None => return Err(Error::IntegerOverflow(line, col, len, "'abs' overflowed".to_string())),
+        },
+        (Value::Number(f), "floor") => whole(f.floor())?,
+        (Value::Number(f), "round") => whole(f.round())?,
+        (Value::Number(f), "abs") => Value::Number(f.abs()),
+        (Value::List(items), "push") => {
+            items.borrow_mut().extend(args);
+            Value::Nil
+        }
+        (Value::List(items), "pop") => items.borrow_mut().pop().unwrap_or(Value::Nil),
+        (Value::List(items), "contains") => Value::Boolean(items.borrow().contains(value(0)?)),
+        (Value::List(items), "join") => {
+            let items = items.borrow().clone();
+            let parts = items.iter().map(&mut *show).collect::<Result<Vec<String>, Error>>()?;
+            Value::String(parts.join(text(0)?))
+        }
+        (Value::Map(map), "keys") => {
+            let keys = map.borrow().entries().iter().map(|(key, _)| key.clone()).collect();
+            Value::List(Rc::new(RefCell::new(keys)))
+        }
+        (Value::Map(map), "values") => {
+            let values = map.borrow().entries().iter().map(|(_, value)| value.clone()).collect();
+            Value::List(Rc::new(RefCell::new(values)))
+        }
+        (Value::Map(map), "contains") => Value::Boolean(map.borrow().get(value(0)?).is_some()),
+        (Value::List(items), "len") => Value::Integer(items.borrow().len() as i64),
+        (Value::Tuple(items), "len") => Value::Integer(items.len() as i64),
+        (Value::Map(map), "len") => Value::Integer(map.borrow().entries().len() as i64),
+        (Value::Range(range), "len") => match i64::try_from(range.len()) {
+            Ok(count) => Value::Integer(count),
+            Err(_) => return Err(Error::IntegerOverflow(line, col, len, "'len' overflowed".to_string())),
+        },
+        (object, "to_string") => Value::String(show(&object)?),
+        _ => unreachable!(),
+    })
+}
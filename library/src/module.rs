@@ -0,0 +1,65 @@
+// module.rs - For finding, caching and tracking imported modules
+use crate::{Function, Type};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+// A module that has already been compiled
+#[derive(Clone)]
+pub struct Loaded {
+    pub body: Rc<Function>,
+    pub types: HashMap<String, Type>,
+}
+
+#[derive(Default)]
+pub struct Loader {
+    pub search: Vec<PathBuf>,
+    loaded: HashMap<PathBuf, Loaded>,
+    loading: Vec<PathBuf>,
+}
+
+impl Loader {
+    pub fn new(search: Vec<PathBuf>) -> Self {
+        // Create a new loader that looks in the provided directories
+        Self { search, ..Self::default() }
+    }
+
+    pub fn resolve(&self, name: &str, from: &Path) -> Option<PathBuf> {
+        // Find a module next to the importing file, or failing that on the search path
+        let dir = from.parent().unwrap_or_else(|| Path::new(""));
+        std::iter::once(dir)
+            .chain(self.search.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+            .and_then(|path| path.canonicalize().ok())
+    }
+
+    pub fn get(&self, path: &Path) -> Option<Loaded> {
+        // Retrieve a module that has already been compiled
+        self.loaded.get(path).cloned()
+    }
+
+    pub fn start(&mut self, path: &Path) -> Result<(), String> {
+        // Mark a module as being compiled, giving back the chain of imports if it is cyclic
+        if let Some(idx) = self.loading.iter().position(|p| p == path) {
+            let mut chain: Vec<String> = self.loading[idx..].iter().map(|p| name(p)).collect();
+            chain.push(name(path));
+            return Err(chain.join(" -> "));
+        }
+        self.loading.push(path.to_path_buf());
+        Ok(())
+    }
+
+    pub fn finish(&mut self, path: &Path, loaded: Option<Loaded>) {
+        // Mark a module as compiled, caching it if it compiled successfully
+        self.loading.retain(|p| p != path);
+        if let Some(loaded) = loaded {
+            self.loaded.insert(path.to_path_buf(), loaded);
+        }
+    }
+}
+
+pub fn name(path: &Path) -> String {
+    // Work out a short name to refer to a module by in messages
+    path.file_name().map_or_else(|| path.display().to_string(), |f| f.to_string_lossy().into())
+}
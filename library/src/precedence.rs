@@ -1,22 +1,33 @@
 // precedence.rs - utilities for handling precedence within the language
 use crate::{Compiler, TokenKind, Error};
 
-pub type CompilerFn<'a> = Option<fn(&'a mut Compiler, bool) -> Result<(), Error>>;
+pub type CompilerFn<'src> = Option<fn(&mut Compiler<'src>, bool) -> Result<(), Error>>;
 
-pub struct ParseRule<'a> {
-    pub prefix: CompilerFn<'a>,
-    pub infix: CompilerFn<'a>,
+pub struct ParseRule<'src> {
+    pub prefix: CompilerFn<'src>,
+    pub infix: CompilerFn<'src>,
     pub prec: Precedence,
 }
 
+// Levels from loosest to tightest binding. Unlike C, the bitwise operators bind tighter
+// than comparisons and equality (as in Rust and Python), so a & b == 0 is (a & b) == 0,
+// but looser than shifts and arithmetic, so a | b + 1 is a | (b + 1)
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Precedence {
     None,
     Assignment,
+    Ternary,
+    Coalesce,
     Or,
     And,
     Equality,
+    Pipeline,
     Comparison,
+    Range,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shift,
     Term,
     Factor,
     Indices,
@@ -30,11 +41,19 @@ impl Precedence {
         // Move down the precedence ladder
         match self {
             Self::None => Self::Assignment,
-            Self::Assignment => Self::Or,
+            Self::Assignment => Self::Ternary,
+            Self::Ternary => Self::Coalesce,
+            Self::Coalesce => Self::Or,
             Self::Or => Self::And,
             Self::And => Self::Equality,
-            Self::Equality => Self::Comparison,
-            Self::Comparison => Self::Term,
+            Self::Equality => Self::Pipeline,
+            Self::Pipeline => Self::Comparison,
+            Self::Comparison => Self::Range,
+            Self::Range => Self::BitOr,
+            Self::BitOr => Self::BitXor,
+            Self::BitXor => Self::BitAnd,
+            Self::BitAnd => Self::Shift,
+            Self::Shift => Self::Term,
             Self::Term => Self::Factor,
             Self::Factor => Self::Indices,
             Self::Indices => Self::Unary,
@@ -45,20 +64,29 @@ impl Precedence {
     }
 }
 
-pub fn get_rule<'a>(kind: TokenKind) -> ParseRule<'a> {
+pub fn get_rule<'src>(kind: &TokenKind) -> ParseRule<'src> {
     // Take in a token and work out what rule to handle it with
     ParseRule {
         prefix: match kind {
             TokenKind::LeftParen => Some(Compiler::grouping),
+            TokenKind::LeftSquare => Some(Compiler::list),
+            TokenKind::LeftCurly => Some(Compiler::map),
             TokenKind::Minus => Some(Compiler::unary),
             TokenKind::False => Some(Compiler::literal),
             TokenKind::True => Some(Compiler::literal),
             TokenKind::Nil => Some(Compiler::literal),
             TokenKind::Exclamation => Some(Compiler::unary),
+            TokenKind::Tilde => Some(Compiler::unary),
             TokenKind::Not => Some(Compiler::unary),
+            TokenKind::Integer(_) => Some(Compiler::number),
             TokenKind::Number(_) => Some(Compiler::number),
             TokenKind::String(_) => Some(Compiler::string),
             TokenKind::Identifier(_) => Some(Compiler::variable),
+            TokenKind::If => Some(Compiler::conditional),
+            TokenKind::Match => Some(Compiler::match_expr),
+            TokenKind::Fn => Some(Compiler::lambda),
+            TokenKind::Throw => Some(Compiler::throw),
+            TokenKind::Yield => Some(Compiler::suspend),
             _ => None,
         },
         infix: match kind {
@@ -67,13 +95,30 @@ pub fn get_rule<'a>(kind: TokenKind) -> ParseRule<'a> {
             TokenKind::Asterisk => Some(Compiler::binary),
             TokenKind::Slash => Some(Compiler::binary),
             TokenKind::Percent => Some(Compiler::binary),
+            TokenKind::Div => Some(Compiler::binary),
             TokenKind::Hat => Some(Compiler::binary),
+            TokenKind::Pipe => Some(Compiler::binary),
+            TokenKind::HatHat => Some(Compiler::binary),
+            TokenKind::Ampersand => Some(Compiler::binary),
+            TokenKind::LessLess => Some(Compiler::binary),
+            TokenKind::GreaterGreater => Some(Compiler::binary),
             TokenKind::Equals => Some(Compiler::binary),
             TokenKind::NotEquals => Some(Compiler::binary),
             TokenKind::Greater => Some(Compiler::binary),
             TokenKind::GreaterEq => Some(Compiler::binary),
             TokenKind::Less => Some(Compiler::binary),
             TokenKind::LessEq => Some(Compiler::binary),
+            TokenKind::DotDot => Some(Compiler::range),
+            TokenKind::DotDotEq => Some(Compiler::range),
+            TokenKind::In => Some(Compiler::contains),
+            TokenKind::PipeGreater => Some(Compiler::pipeline),
+            TokenKind::Question => Some(Compiler::ternary),
+            TokenKind::QuestionQuestion => Some(Compiler::coalesce),
+            TokenKind::QuestionDot => Some(Compiler::optional),
+            TokenKind::QuestionSquare => Some(Compiler::optional),
+            TokenKind::LeftParen => Some(Compiler::call),
+            TokenKind::LeftSquare => Some(Compiler::index),
+            TokenKind::Dot => Some(Compiler::dot),
             _ => None,
         },
         prec: match kind {
@@ -82,13 +127,30 @@ pub fn get_rule<'a>(kind: TokenKind) -> ParseRule<'a> {
             TokenKind::Slash => Precedence::Factor,
             TokenKind::Asterisk => Precedence::Factor,
             TokenKind::Percent => Precedence::Factor,
+            TokenKind::Div => Precedence::Factor,
             TokenKind::Hat => Precedence::Indices,
+            TokenKind::Pipe => Precedence::BitOr,
+            TokenKind::HatHat => Precedence::BitXor,
+            TokenKind::Ampersand => Precedence::BitAnd,
+            TokenKind::LessLess => Precedence::Shift,
+            TokenKind::GreaterGreater => Precedence::Shift,
             TokenKind::Equals => Precedence::Equality,
             TokenKind::NotEquals => Precedence::Equality,
             TokenKind::Greater => Precedence::Comparison,
             TokenKind::GreaterEq => Precedence::Comparison,
             TokenKind::Less => Precedence::Comparison,
             TokenKind::LessEq => Precedence::Comparison,
+            TokenKind::DotDot => Precedence::Range,
+            TokenKind::DotDotEq => Precedence::Range,
+            TokenKind::In => Precedence::Comparison,
+            TokenKind::PipeGreater => Precedence::Pipeline,
+            TokenKind::Question => Precedence::Ternary,
+            TokenKind::QuestionQuestion => Precedence::Coalesce,
+            TokenKind::QuestionDot => Precedence::Call,
+            TokenKind::QuestionSquare => Precedence::Call,
+            TokenKind::LeftParen => Precedence::Call,
+            TokenKind::LeftSquare => Precedence::Call,
+            TokenKind::Dot => Precedence::Call,
             _ => Precedence::None,
         },
     }
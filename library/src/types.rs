@@ -0,0 +1,143 @@
+// types.rs - Static types for checking programs before they run
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Integer,
+    Number,
+    Boolean,
+    String,
+    Nil,
+    Function(Params, Box<Type>),
+    Struct(String),
+    Enum(String),
+    Instance(String),
+    Module(String),
+    Tuple(Vec<Type>),
+    List(Box<Type>),
+    Map(Box<Type>, Box<Type>),
+    Range,
+    Generator,
+    Any,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        // Define how types are shown, this matches how they are annotated
+        match self {
+            Self::Integer => write!(fmt, "int"),
+            Self::Number => write!(fmt, "float"),
+            Self::Boolean => write!(fmt, "bool"),
+            Self::String => write!(fmt, "string"),
+            Self::Nil => write!(fmt, "nil"),
+            Self::Function(params, returns) => {
+                let mut kinds: Vec<String> = params.kinds.iter().map(Type::to_string).collect();
+                if let (true, Some(rest)) = (params.variadic, kinds.last_mut()) {
+                    rest.insert_str(0, "...");
+                }
+                write!(fmt, "fn({}) -> {}", kinds.join(", "), returns)
+            }
+            Self::Struct(name) => write!(fmt, "struct {}", name),
+            Self::Enum(name) => write!(fmt, "enum {}", name),
+            Self::Instance(name) => write!(fmt, "{}", name),
+            Self::Module(_) => write!(fmt, "module"),
+            Self::Tuple(items) => {
+                let items: Vec<String> = items.iter().map(Type::to_string).collect();
+                write!(fmt, "({})", items.join(", "))
+            }
+            Self::List(item) => write!(fmt, "[{}]", item),
+            Self::Map(key, value) => write!(fmt, "{{{}: {}}}", key, value),
+            Self::Range => write!(fmt, "range"),
+            Self::Generator => write!(fmt, "generator"),
+            Self::Any => write!(fmt, "any"),
+        }
+    }
+}
+
+impl Type {
+    pub fn from_name(name: &str) -> Option<Self> {
+        // Look up a type by the name used in annotations
+        match name {
+            "int" => Some(Self::Integer),
+            "float" => Some(Self::Number),
+            "bool" => Some(Self::Boolean),
+            "string" => Some(Self::String),
+            "nil" => Some(Self::Nil),
+            "range" => Some(Self::Range),
+            "generator" => Some(Self::Generator),
+            "any" => Some(Self::Any),
+            _ => None,
+        }
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        // Determine if this type is an integer or a float
+        matches!(self, Self::Integer | Self::Number)
+    }
+
+    pub fn accepts(&self, other: &Type) -> bool {
+        // Determine if a value of the other type can be used where this type is expected
+        match (self, other) {
+            (Self::Any, _) | (_, Self::Any) => true,
+            // Integers are promoted to floats when needed
+            (Self::Number, Self::Integer) => true,
+            (Self::Function(a, r), Self::Function(b, s)) => {
+                let (a, b) = (&a.kinds, &b.kinds);
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| b.accepts(a)) && r.accepts(s)
+            }
+            (Self::Tuple(a), Self::Tuple(b)) => 
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.accepts(b)),
+            (Self::List(a), Self::List(b)) => a.accepts(b),
+            (Self::Map(a, b), Self::Map(c, d)) => a.accepts(c) && b.accepts(d),
+            (a, b) => a == b,
+        }
+    }
+
+    pub fn join(self, other: Type) -> Type {
+        // Find a type that covers the results of two different branches
+        match (self, other) {
+            (a, b) if a == b => a,
+            (a, b) if a.is_numeric() && b.is_numeric() => Self::Number,
+            (Self::List(a), Self::List(b)) => Self::List(Box::new(a.join(*b))),
+            (Self::Map(a, b), Self::Map(c, d)) => Self::Map(Box::new(a.join(*c)), Box::new(b.join(*d))),
+            _ => Self::Any,
+        }
+    }
+}
+
+// The parameters of a function, used to check the arguments it is called with
+#[derive(Debug, Clone, PartialEq)]
+pub struct Params {
+    pub kinds: Vec<Type>,
+    // These are unknown for functions that only have an annotation e.g. fn(int) -> int
+    pub names: Vec<String>,
+    pub required: usize,
+    pub variadic: bool,
+}
+
+impl Params {
+    pub fn positional(kinds: Vec<Type>) -> Self {
+        // Create parameters that can only be given in order, and must all be given
+        Self { required: kinds.len(), kinds, names: vec![], variadic: false }
+    }
+
+    pub fn fixed(&self) -> usize {
+        // Find out how many parameters there are, not counting a rest parameter
+        self.kinds.len() - self.variadic as usize
+    }
+
+    pub fn arity(&self) -> String {
+        // Describe how many arguments these parameters take
+        crate::error::arity(self.required, self.fixed(), self.variadic)
+    }
+
+    pub fn without_self(&self) -> Self {
+        // Drop the first parameter, for when self is passed in implicitly
+        Self {
+            kinds: self.kinds[1..].to_vec(),
+            names: self.names.iter().skip(1).cloned().collect(),
+            required: self.required.saturating_sub(1),
+            variadic: self.variadic,
+        }
+    }
+}
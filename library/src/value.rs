@@ -1,80 +1,696 @@
 // value.rs - Representation and operations of data types
 use std::ops::{Add, Sub, Mul, Div, Neg, Rem, BitXor};
+use std::cmp::Ordering;
+use std::any::Any;
+use std::convert::TryFrom;
+use crate::{Chunk, Type, Params};
 use round::round;
+use unicode_segmentation::UnicodeSegmentation;
+use std::collections::HashMap;
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::fmt;
 
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[derive(Clone, Debug)]
 pub enum Value {
+    Integer(i64),
     Number(f64),
     Boolean(bool),
     String(String),
+    Function(Rc<Function>),
+    Struct(Rc<Struct>),
+    Instance(Rc<RefCell<Instance>>),
+    Enum(Rc<Enum>),
+    Variant(Rc<Variant>),
+    Module(Rc<Module>),
+    Tuple(Rc<Vec<Value>>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<Map>>),
+    Iterator(Rc<RefCell<Iter>>),
+    Range(Range),
+    Generator(Rc<RefCell<Generator>>),
+    Userdata(Rc<dyn Userdata>),
     Nil,
 }
 
+// A value the host application hands over, which scripts use through its special methods
+// e.g. add for +, sub for -, mul for *, eq for ==, lt for <, index for [] and to_string
+pub trait Userdata: fmt::Debug {
+    // The name of its type, for showing it and checking it against annotations
+    fn name(&self) -> &str;
+    // Whether it has a special method
+    fn has(&self, method: &str) -> bool;
+    // Carry out a special method given the other operands, a failure is thrown in the script
+    fn call(&self, method: &str, args: Vec<Value>) -> Result<Value, String>;
+    // Give back the value as its own type, for when another operand is from the host too
+    fn as_any(&self) -> &dyn Any;
+}
+
+#[derive(Debug)]
+pub struct Module {
+    pub name: String,
+    pub path: String,
+}
+
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    // Parameters after the required ones have defaults, the last one may collect the rest
+    pub required: usize,
+    pub variadic: bool,
+    // Calling a function that yields gives back a generator instead of running it
+    pub generator: bool,
+    pub chunk: Rc<Chunk>,
+}
+
+impl Function {
+    pub fn fixed(&self) -> usize {
+        // Find out how many parameters there are, not counting a rest parameter
+        self.params.len() - self.variadic as usize
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        // Functions are shown by name rather than by their bytecode
+        write!(fmt, "<fn {}>", self.name)
+    }
+}
+
+pub struct Struct {
+    pub name: String,
+    pub fields: Vec<String>,
+    pub methods: HashMap<String, Rc<Function>>,
+}
+
+impl fmt::Debug for Struct {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        // Struct types are shown by name rather than by their methods
+        write!(fmt, "<struct {}>", self.name)
+    }
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub kind: Rc<Struct>,
+    pub fields: Vec<Value>,
+}
+
+impl Instance {
+    pub fn field(&self, name: &str) -> Option<usize> {
+        // Find the position of a field within this instance
+        self.kind.fields.iter().position(|f| f == name)
+    }
+}
+
+impl fmt::Display for Instance {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        // Show an instance along with the values of its fields
+        write!(fmt, "{}", show_fields(&self.kind, &self.fields, &mut |value| Ok::<_, fmt::Error>(value.to_string()))?)
+    }
+}
+
+fn show_fields<E>(
+    kind: &Struct, fields: &[Value], item: &mut dyn FnMut(&Value) -> Result<String, E>
+) -> Result<String, E> {
+    // Show the fields of an instance, leaving their values to be shown by the function given
+    let fields = kind.fields
+        .iter()
+        .zip(fields)
+        .map(|(name, value)| Ok(format!("{}: {}", name, item(value)?)))
+        .collect::<Result<Vec<String>, E>>()?;
+    Ok(format!("{} {{ {} }}", kind.name, fields.join(", ")))
+}
+
+pub struct Enum {
+    pub name: String,
+    // The name of each variant along with how many values it carries
+    pub variants: Vec<(String, usize)>,
+}
+
+impl Enum {
+    pub fn variant(&self, name: &str) -> Option<usize> {
+        // Find the tag of a variant within this enum
+        self.variants.iter().position(|v| v.0 == name)
+    }
+
+    pub fn create(self: &Rc<Self>, name: &str, values: Vec<Value>) -> Option<Value> {
+        // Create one of the values of this enum, as long as it is given the right values
+        let tag = self.variant(name).filter(|tag| self.variants[*tag].1 == values.len())?;
+        Some(Value::Variant(Rc::new(Variant { kind: self.clone(), tag, values })))
+    }
+}
+
+impl fmt::Debug for Enum {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        // Enum types are shown by name rather than by their variants
+        write!(fmt, "<enum {}>", self.name)
+    }
+}
+
+#[derive(Debug)]
+pub struct Variant {
+    pub kind: Rc<Enum>,
+    pub tag: usize,
+    pub values: Vec<Value>,
+}
+
+impl Variant {
+    pub fn name(&self) -> &str {
+        // Give back the name of the variant this value is
+        &self.kind.variants[self.tag].0
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        // Show a variant along with any values it carries
+        write!(fmt, "{}", self.show(&mut |value| Ok::<_, fmt::Error>(value.to_string()))?)
+    }
+}
+
+impl Variant {
+    fn show<E>(&self, item: &mut dyn FnMut(&Value) -> Result<String, E>) -> Result<String, E> {
+        // Show a variant, leaving the values it carries to be shown by the function given
+        if self.values.is_empty() {
+            return Ok(format!("{}.{}", self.kind.name, self.name()));
+        }
+        Ok(format!("{}.{}({})", self.kind.name, self.name(), show_items(&self.values, item)?))
+    }
+}
+
+// A call of a generator function, put aside each time it yields
+#[derive(Debug)]
+pub struct Generator {
+    pub function: Rc<Function>,
+    pub ip: usize,
+    pub locals: Vec<Value>,
+    // Values left on the stack, along with their positions
+    pub stack: Vec<(Value, (usize, usize))>,
+    // The try blocks it was within, as (catch ip, finally ip, stack height, locals)
+    pub handlers: Vec<(usize, usize, usize, usize)>,
+    pub started: bool,
+    pub running: bool,
+    pub done: bool,
+}
+
+impl Generator {
+    pub fn new(function: Rc<Function>, locals: Vec<Value>) -> Self {
+        // Create a generator that will start from the beginning of a function
+        Self { 
+            function, ip: 0, locals, stack: vec![], handlers: vec![], 
+            started: false, running: false, done: false,
+        }
+    }
+
+    pub fn finish(&mut self) {
+        // Mark this generator as having nothing more to give back
+        self.done = true;
+        self.running = false;
+        self.locals.clear();
+        self.stack.clear();
+        self.handlers.clear();
+    }
+}
+
+// Maps keep their entries in the order they were inserted
+#[derive(Debug, Default)]
+pub struct Map {
+    entries: Vec<(Value, Value)>,
+    // Where the entries with hashable keys are, so they are found without searching
+    index: HashMap<Key, usize>,
+}
+
+impl Map {
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        // Look up the value stored under a key
+        self.position(key).map(|idx| &self.entries[idx].1)
+    }
+
+    pub fn insert(&mut self, key: Value, value: Value) {
+        // Store a value under a key, replacing any value already there
+        match self.position(&key) {
+            Some(idx) => self.entries[idx].1 = value,
+            None => {
+                if let Some(hashed) = Key::from(&key) {
+                    self.index.insert(hashed, self.entries.len());
+                }
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn entries(&self) -> &[(Value, Value)] {
+        // Give back the entries in the order they were inserted
+        &self.entries
+    }
+
+    fn position(&self, key: &Value) -> Option<usize> {
+        // Find where the entry for a key is, other keys (e.g. lists) have to be searched for
+        match Key::from(key) {
+            Some(hashed) => self.index.get(&hashed).copied(),
+            None => self.entries.iter().position(|(k, _)| k == key),
+        }
+    }
+}
+
+// The values that can be hashed, which are the ones map keys usually are
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum Key {
+    Integer(i64),
+    Boolean(bool),
+    String(String),
+    Tuple(Vec<Key>),
+}
+
+impl Key {
+    fn from(value: &Value) -> Option<Key> {
+        // Turn a value into a key, if it can be hashed
+        Some(match value {
+            Value::Integer(int) => Key::Integer(*int),
+            // Whole floats are equal to the integer they hold, so they have to hash the same
+            Value::Number(float) if float.fract() == 0.0 && 
+                *float >= i64::MIN as f64 && *float < i64::MAX as f64 => Key::Integer(*float as i64),
+            Value::Boolean(boolean) => Key::Boolean(*boolean),
+            Value::String(string) => Key::String(string.clone()),
+            Value::Tuple(items) => Key::Tuple(items.iter().map(Key::from).collect::<Option<_>>()?),
+            _ => return None,
+        })
+    }
+}
+
+impl PartialEq for Map {
+    fn eq(&self, other: &Map) -> bool {
+        // Maps are equal if they have the same entries, in any order
+        self.entries.len() == other.entries.len() && 
+            self.entries.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+// A range of integers, counting from the start towards the end by the step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub start: i64,
+    pub end: i64,
+    pub step: i64,
+    pub inclusive: bool,
+}
+
+impl Range {
+    pub fn len(&self) -> u128 {
+        // Find out how many integers are within this range (which may be more than fit in an i64)
+        let (start, step) = (self.start as i128, self.step as i128);
+        let last = if self.inclusive { self.end as i128 } else { self.end as i128 - step.signum() };
+        if (step > 0 && last < start) || (step < 0 && last > start) { 0 } else {
+            ((last - start) / step + 1) as u128
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        // Determine if there are no integers within this range
+        self.len() == 0
+    }
+
+    pub fn nth(&self, n: u128) -> Option<i64> {
+        // Find the integer at a position within this range, nothing past the end can overflow
+        if n < self.len() {
+            Some((self.start as i128 + n as i128 * self.step as i128) as i64)
+        } else {
+            None
+        }
+    }
+
+    pub fn contains(&self, value: i64) -> bool {
+        // Determine if an integer is one of the ones this range counts through
+        let (value, start, step) = (value as i128, self.start as i128, self.step as i128);
+        let offset = value - start;
+        offset % step == 0 && offset / step >= 0 && offset / step < self.len() as i128
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        // Show a range the way it is written e.g. 0..=10 step 2
+        write!(fmt, "{}{}{}", self.start, if self.inclusive { "..=" } else { ".." }, self.end)?;
+        if self.step != 1 {
+            write!(fmt, " step {}", self.step)?;
+        }
+        Ok(())
+    }
+}
+
+// The state of a for loop as it moves through what it is looping over
+#[derive(Debug)]
+pub struct Iter {
+    pub source: Source,
+    pub position: usize,
+    // Whether each step gives back the index (or key) along with the value
+    pub pairs: bool,
+}
+
+#[derive(Debug)]
+pub enum Source {
+    List(Rc<RefCell<Vec<Value>>>),
+    Tuple(Rc<Vec<Value>>),
+    Map(Rc<RefCell<Map>>),
+    // Strings are walked through a grapheme at a time, keeping track of the byte offset
+    String(String, usize),
+    Range(Range),
+    // An instance with a next method, which gives back nil once it is done
+    Object(Value),
+    Generator(Rc<RefCell<Generator>>),
+}
+
+// What a for loop does next
+pub enum Step {
+    Done,
+    Value(Value),
+    Pair(Value, Value),
+    Call(Value),
+    Resume(Rc<RefCell<Generator>>),
+}
+
+impl Iter {
+    pub fn step(&mut self) -> Step {
+        // Move on to the next value
+        let position = self.position;
+        let (key, value) = match &mut self.source {
+            Source::List(items) => match items.borrow().get(position) {
+                Some(item) => (Value::Integer(position as i64), item.clone()),
+                None => return Step::Done,
+            },
+            Source::Tuple(items) => match items.get(position) {
+                Some(item) => (Value::Integer(position as i64), item.clone()),
+                None => return Step::Done,
+            },
+            Source::Map(map) => match map.borrow().entries().get(position) {
+                Some((key, value)) => (key.clone(), value.clone()),
+                None => return Step::Done,
+            },
+            Source::String(string, offset) => match string[*offset..].graphemes(true).next() {
+                Some(grapheme) => {
+                    *offset += grapheme.len();
+                    (Value::Integer(position as i64), Value::String(grapheme.to_string()))
+                }
+                None => return Step::Done,
+            },
+            Source::Range(range) => match range.nth(position as u128) {
+                Some(value) => (Value::Nil, Value::Integer(value)),
+                None => return Step::Done,
+            },
+            Source::Object(object) => return Step::Call(object.clone()),
+            Source::Generator(generator) => return Step::Resume(generator.clone()),
+        };
+        self.position += 1;
+        if self.pairs {
+            Step::Pair(key, value)
+        } else if let Source::Map(_) = self.source {
+            // Looping over a map on its own gives back the keys
+            Step::Value(key)
+        } else {
+            Step::Value(value)
+        }
+    }
+}
+
 impl fmt::Display for Value {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         // Define how to print certain values
         match self {
+            Self::Integer(i) => write!(fmt, "{}", i),
             Self::Number(f) => write!(fmt, "{}", round(*f, 5)),
             Self::Boolean(b) => write!(fmt, "{}", b),
             Self::String(s) => write!(fmt, "{}", s),
+            Self::Function(func) => write!(fmt, "{:?}", func),
+            Self::Struct(kind) => write!(fmt, "{:?}", kind),
+            Self::Enum(kind) => write!(fmt, "{:?}", kind),
+            Self::Module(module) => write!(fmt, "<module {}>", module.name),
+            Self::Iterator(_) => write!(fmt, "<iterator>"),
+            Self::Range(range) => write!(fmt, "{}", range),
+            Self::Generator(generator) => write!(fmt, "<generator {}>", generator.borrow().function.name),
+            Self::Userdata(data) => write!(fmt, "<{}>", data.name()),
             Self::Nil => write!(fmt, "nil"),
+            // Values that hold others show each of them in turn
+            _ => write!(fmt, "{}", self.show(&mut |value| Ok::<_, fmt::Error>(value.to_string()))?),
         }
     }
 }
 
+fn show_items<E>(items: &[Value], item: &mut dyn FnMut(&Value) -> Result<String, E>) -> Result<String, E> {
+    // Show a sequence of values separated by commas
+    Ok(items.iter().map(item).collect::<Result<Vec<_>, E>>()?.join(", "))
+}
+
+impl Value {
+    pub fn show<E>(&self, item: &mut dyn FnMut(&Value) -> Result<String, E>) -> Result<String, E> {
+        // Show a value, leaving any values within it to be shown by the function given
+        // (the VM uses this to have instances within collections go through their to_string methods)
+        Ok(match self {
+            Self::Tuple(items) => format!("({})", show_items(items, item)?),
+            // Collections are copied first, in case showing an item changes them
+            Self::List(items) => {
+                let items = items.borrow().clone();
+                format!("[{}]", show_items(&items, item)?)
+            }
+            Self::Map(map) => {
+                let entries = map.borrow().entries().to_vec();
+                let entries = entries
+                    .iter()
+                    .map(|(k, v)| Ok(format!("{}: {}", item(k)?, item(v)?)))
+                    .collect::<Result<Vec<String>, E>>()?;
+                format!("{{{}}}", entries.join(", "))
+            }
+            Self::Instance(instance) => {
+                let (kind, fields) = {
+                    let instance = instance.borrow();
+                    (instance.kind.clone(), instance.fields.clone())
+                };
+                show_fields(&kind, &fields, item)?
+            }
+            Self::Variant(variant) => variant.show(item)?,
+            _ => self.to_string(),
+        })
+    }
+
+    pub fn is_numeric(&self) -> bool {
+        // Determine if this value is an integer or a float
+        matches!(self, Self::Integer(_) | Self::Number(_))
+    }
+
+    pub fn as_float(&self) -> f64 {
+        // Promote a numeric value to a float
+        match self {
+            Self::Integer(i) => *i as f64,
+            Self::Number(f) => *f,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn is(&self, kind: &Type) -> bool {
+        // Determine if this value fits a type, for values the compiler couldn't know the type of
+        match (kind, self) {
+            (Type::Any, _) => true,
+            (Type::Integer, Self::Integer(_)) |
+            (Type::Number, Self::Number(_) | Self::Integer(_)) |
+            (Type::Boolean, Self::Boolean(_)) |
+            (Type::String, Self::String(_)) |
+            (Type::Nil, Self::Nil) |
+            (Type::Module(_), Self::Module(_)) |
+            (Type::Range, Self::Range(_)) |
+            (Type::Generator, Self::Generator(_)) => true,
+            // Functions don't keep the types of their parameters
+            (Type::Function(params, _), Self::Function(function)) =>
+                params.kinds.len() == function.params.len(),
+            (Type::Struct(name), Self::Struct(kind)) => &kind.name == name,
+            (Type::Enum(name), Self::Enum(kind)) => &kind.name == name,
+            (Type::Instance(name), Self::Instance(instance)) => &instance.borrow().kind.name == name,
+            (Type::Instance(name), Self::Variant(variant)) => &variant.kind.name == name,
+            (Type::Instance(name), Self::Userdata(data)) => data.name() == name,
+            (Type::Tuple(kinds), Self::Tuple(items)) =>
+                kinds.len() == items.len() && items.iter().zip(kinds).all(|(item, kind)| item.is(kind)),
+            (Type::List(kind), Self::List(items)) => items.borrow().iter().all(|item| item.is(kind)),
+            (Type::Map(key, value), Self::Map(map)) =>
+                map.borrow().entries().iter().all(|(k, v)| k.is(key) && v.is(value)),
+            _ => false,
+        }
+    }
+
+    pub fn kind(&self) -> Type {
+        // Find the type of this value as it would be annotated, collections go by their first item
+        match self {
+            Self::Integer(_) => Type::Integer,
+            Self::Number(_) => Type::Number,
+            Self::Boolean(_) => Type::Boolean,
+            Self::String(_) => Type::String,
+            Self::Function(function) => Type::Function(
+                Params::positional(vec![Type::Any; function.params.len()]),
+                Box::new(Type::Any),
+            ),
+            Self::Struct(kind) => Type::Struct(kind.name.clone()),
+            Self::Instance(instance) => Type::Instance(instance.borrow().kind.name.clone()),
+            Self::Enum(kind) => Type::Enum(kind.name.clone()),
+            Self::Variant(variant) => Type::Instance(variant.kind.name.clone()),
+            Self::Userdata(data) => Type::Instance(data.name().to_string()),
+            Self::Module(module) => Type::Module(module.path.clone()),
+            Self::Tuple(items) => Type::Tuple(items.iter().map(Value::kind).collect()),
+            Self::List(items) => Type::List(Box::new(items.borrow().first().map_or(Type::Any, Value::kind))),
+            Self::Map(map) => match map.borrow().entries().first() {
+                Some((key, value)) => Type::Map(Box::new(key.kind()), Box::new(value.kind())),
+                None => Type::Map(Box::new(Type::Any), Box::new(Type::Any)),
+            },
+            Self::Iterator(_) | Self::Generator(_) => Type::Generator,
+            Self::Range(_) => Type::Range,
+            Self::Nil => Type::Nil,
+        }
+    }
+
+    pub fn variant(&self) -> Option<(&str, &[Value])> {
+        // Look inside an enum value, for turning it into an enum of the host application
+        match self {
+            Self::Variant(variant) => Some((variant.name(), &variant.values)),
+            _ => None,
+        }
+    }
+
+    pub fn int_div(self, other: Value) -> Option<Value> {
+        // Divide two values, rounding down to a whole number
+        match (self, other) {
+            (Self::Integer(a), Self::Integer(b)) => {
+                let quotient = a.checked_div(b)?;
+                // Round towards negative infinity rather than zero
+                if a % b != 0 && (a < 0) != (b < 0) {
+                    Some(Self::Integer(quotient - 1))
+                } else {
+                    Some(Self::Integer(quotient))
+                }
+            }
+            (a, b) => Some(Self::Number((a.as_float() / b.as_float()).floor())),
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        // Compare two values, integers and floats are compared by their value
+        match (self, other) {
+            (Self::Integer(a), Self::Integer(b)) => a == b,
+            (Self::Boolean(a), Self::Boolean(b)) => a == b,
+            (Self::String(a), Self::String(b)) => a == b,
+            (Self::Function(a), Self::Function(b)) => Rc::ptr_eq(a, b),
+            (Self::Struct(a), Self::Struct(b)) => Rc::ptr_eq(a, b),
+            (Self::Instance(a), Self::Instance(b)) => Rc::ptr_eq(a, b),
+            (Self::Enum(a), Self::Enum(b)) => Rc::ptr_eq(a, b),
+            // Variants are equal when they are the same variant carrying the same values
+            (Self::Variant(a), Self::Variant(b)) => 
+                Rc::ptr_eq(&a.kind, &b.kind) && a.tag == b.tag && a.values == b.values,
+            (Self::Module(a), Self::Module(b)) => a.path == b.path,
+            (Self::Tuple(a), Self::Tuple(b)) => a == b,
+            (Self::List(a), Self::List(b)) => a == b,
+            (Self::Map(a), Self::Map(b)) => a == b,
+            (Self::Iterator(a), Self::Iterator(b)) => Rc::ptr_eq(a, b),
+            (Self::Range(a), Self::Range(b)) => a == b,
+            (Self::Generator(a), Self::Generator(b)) => Rc::ptr_eq(a, b),
+            (Self::Userdata(a), Self::Userdata(b)) => Rc::ptr_eq(a, b),
+            (Self::Nil, Self::Nil) => true,
+            (Self::Integer(a), Self::Number(b)) | (Self::Number(b), Self::Integer(a)) =>
+                compare(*a, *b) == Some(Ordering::Equal),
+            (a, b) if a.is_numeric() && b.is_numeric() => a.as_float() == b.as_float(),
+            _ => false,
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        // Order two values, integers and floats are ordered by their value
+        match (self, other) {
+            (Self::Integer(a), Self::Integer(b)) => a.partial_cmp(b),
+            (Self::Boolean(a), Self::Boolean(b)) => a.partial_cmp(b),
+            (Self::String(a), Self::String(b)) => a.partial_cmp(b),
+            (Self::Nil, Self::Nil) => Some(Ordering::Equal),
+            (Self::Integer(a), Self::Number(b)) => compare(*a, *b),
+            (Self::Number(a), Self::Integer(b)) => compare(*b, *a).map(Ordering::reverse),
+            (a, b) if a.is_numeric() && b.is_numeric() => a.as_float().partial_cmp(&b.as_float()),
+            _ => None,
+        }
+    }
+}
+
+fn compare(int: i64, float: f64) -> Option<Ordering> {
+    // Compare an integer with a float exactly, turning a large integer into a float would round it
+    if float.is_nan() {
+        return None;
+    } else if float >= i64::MAX as f64 {
+        // The largest integer rounds up to 2^63 as a float, so everything from there is larger
+        return Some(Ordering::Less);
+    } else if float < i64::MIN as f64 {
+        return Some(Ordering::Greater);
+    }
+    let whole = float.trunc();
+    match int.cmp(&(whole as i64)) {
+        Ordering::Equal => 0.0.partial_cmp(&(float - whole)),
+        ordering => Some(ordering),
+    }
+}
+
+// Operations on integers are checked, these give back None if they overflow
 impl Add for Value {
-    type Output = Value;
+    type Output = Option<Value>;
     fn add(self, other: Value) -> Self::Output {
         // Add two values
         match (self, other) {
-            (Self::Number(a), Self::Number(b)) => Self::Number(a + b),
-            (Self::String(a), Self::String(b)) => Self::String(a + &b),
-            _ => unreachable!(),
+            (Self::Integer(a), Self::Integer(b)) => Some(Self::Integer(a.checked_add(b)?)),
+            (Self::String(a), Self::String(b)) => Some(Self::String(a + &b)),
+            (a, b) => Some(Self::Number(a.as_float() + b.as_float())),
         }
     }
 }
 
 impl Sub for Value {
-    type Output = Value;
+    type Output = Option<Value>;
     fn sub(self, other: Value) -> Self::Output {
         // Subtract two values
         match (self, other) {
-            (Self::Number(a), Self::Number(b)) => Self::Number(a - b),
-            _ => unreachable!(),
+            (Self::Integer(a), Self::Integer(b)) => Some(Self::Integer(a.checked_sub(b)?)),
+            (a, b) => Some(Self::Number(a.as_float() - b.as_float())),
         }
     }
 }
 
 impl Mul for Value {
-    type Output = Value;
+    type Output = Option<Value>;
     fn mul(self, other: Value) -> Self::Output {
         // Multiply two values
         match (self, other) {
-            (Self::Number(a), Self::Number(b)) => Self::Number(a * b),
-            _ => unreachable!(),
+            (Self::Integer(a), Self::Integer(b)) => Some(Self::Integer(a.checked_mul(b)?)),
+            (a, b) => Some(Self::Number(a.as_float() * b.as_float())),
         }
     }
 }
 
 impl Div for Value {
-    type Output = Value;
+    type Output = Option<Value>;
     fn div(self, other: Value) -> Self::Output {
-        // Divide two values
-        match (self, other) {
-            (Self::Number(a), Self::Number(b)) => Self::Number(a / b),
-            _ => unreachable!(),
-        }
+        // Divide two values, this always results in a float
+        Some(Self::Number(self.as_float() / other.as_float()))
     }
 }
 
 impl Rem for Value {
-    type Output = Value;
+    type Output = Option<Value>;
     fn rem(self, other: Value) -> Self::Output {
-        // Find the remainder of two values
+        // Find the remainder of two values, rounding down like div does so the remainder has
+        // the sign of the divisor e.g. -7 % 2 is 1, as -7 div 2 is -4
         match (self, other) {
-            (Self::Number(a), Self::Number(b)) => Self::Number(a % b),
-            _ => unreachable!(),
+            (Self::Integer(a), Self::Integer(b)) => {
+                let rem = a.checked_rem(b)?;
+                Some(Self::Integer(if rem != 0 && (rem < 0) != (b < 0) { rem + b } else { rem }))
+            }
+            (a, b) => {
+                let (a, b) = (a.as_float(), b.as_float());
+                let rem = a % b;
+                Some(Self::Number(if rem != 0.0 && (rem < 0.0) != (b < 0.0) { rem + b } else { rem }))
+            }
         }
     }
 }
@@ -82,22 +698,24 @@ impl Rem for Value {
 // Actually acts as a power operator, not a bitxor
 // We just want to trick rust into using it like this, for code clarity
 impl BitXor for Value {
-    type Output = Value;
+    type Output = Option<Value>;
     fn bitxor(self, other: Value) -> Self::Output {
         // Exponentiate two values
         match (self, other) {
-            (Self::Number(a), Self::Number(b)) => Self::Number(a.powf(b)),
-            _ => unreachable!(),
+            (Self::Integer(a), Self::Integer(b)) if b >= 0 => 
+                Some(Self::Integer(a.checked_pow(u32::try_from(b).ok()?)?)),
+            (a, b) => Some(Self::Number(a.as_float().powf(b.as_float()))),
         }
     }
 }
 
 impl Neg for Value {
-    type Output = Value;
+    type Output = Option<Value>;
     fn neg(self) -> Self::Output {
         // Negate a value
         match self {
-            Self::Number(num) => Self::Number(-num),
+            Self::Integer(num) => Some(Self::Integer(num.checked_neg()?)),
+            Self::Number(num) => Some(Self::Number(-num)),
             _ => unreachable!(),
         }
     }
@@ -1,9 +1,58 @@
 // vm.rs - Stack-based Bytecode Virtual Machine
-use crate::{Chunk, Error, OpCode, Value};
-use std::collections::HashMap;
+use crate::methods;
+use crate::chunk::{PENDING_THROW, PENDING_RETURN};
+use crate::{
+    Chunk, Error, Function, Generator, Instance, Iter, Map, Module, OpCode, Range, Source, Step, Struct,
+    Userdata, Value
+};
+use unicode_segmentation::UnicodeSegmentation;
+use std::collections::{HashMap, HashSet, hash_map::Entry};
+use std::convert::TryFrom;
+use std::cell::RefCell;
+use std::rc::Rc;
 use round::round;
 
 const STACK_SIZE: usize = 256;
+const FRAMES_MAX: usize = 256;
+
+// The state of a caller, restored when a function returns
+struct Frame {
+    chunk: Rc<Chunk>,
+    ip: usize,
+    base: usize,
+    height: usize,
+    span: (usize, usize),
+    // The generator that was resumed from this frame, if any
+    generator: Option<Rc<RefCell<Generator>>>,
+}
+
+// A special method of a value e.g. add for +, from a struct in the script or from the host
+enum Special {
+    Method(Rc<Function>),
+    Host(Rc<dyn Userdata>),
+}
+
+impl Special {
+    fn find(value: &Value, name: &str) -> Option<Self> {
+        // Look for a special method of a value
+        match value {
+            Value::Instance(instance) => instance.borrow().kind.methods.get(name).cloned().map(Self::Method),
+            Value::Userdata(data) if data.has(name) => Some(Self::Host(data.clone())),
+            _ => None,
+        }
+    }
+}
+
+// Where to resume when an error is thrown inside a try block
+struct Handler {
+    chunk: Rc<Chunk>,
+    ip: usize,
+    finally: usize,
+    base: usize,
+    frames: usize,
+    height: usize,
+    locals: usize,
+}
 
 #[allow(clippy::upper_case_acronyms)]
 pub struct VM {
@@ -11,7 +60,23 @@ pub struct VM {
     pub positions: Vec<(usize, usize)>,
     pub result: Option<Value>,
     pub globals: HashMap<String, Value>,
-    chunk: Chunk,
+    pub modules: HashMap<String, HashMap<String, Value>>,
+    pub locals: Vec<Value>,
+    // Constants of the script and of each module, keyed by the module's path
+    consts: HashMap<Option<String>, HashMap<String, (usize, usize)>>,
+    // Globals the host application has set
+    hosted: HashSet<String>,
+    frames: Vec<Frame>,
+    handlers: Vec<Handler>,
+    thrown: Option<Value>,
+    caught: Option<(Value, Error)>,
+    // Where the error last handed to a try block was raised
+    raised: (usize, usize, usize),
+    error: Rc<Struct>,
+    chunk: Rc<Chunk>,
+    ip: usize,
+    base: usize,
+    line: usize,
     verbose: bool,
 }
 
@@ -21,17 +86,81 @@ impl VM {
         Self {
             stack: Vec::with_capacity(STACK_SIZE),
             positions: Vec::with_capacity(STACK_SIZE),
-            chunk: Chunk::new(0),
+            chunk: Rc::new(Chunk::new(0)),
+            frames: vec![],
+            handlers: vec![],
+            thrown: None,
+            caught: None,
+            raised: (0, 0, 0),
+            error: Rc::new(Struct {
+                name: "Error".to_string(),
+                fields: ["message", "line", "column", "length"].map(String::from).to_vec(),
+                methods: HashMap::new(),
+            }),
+            ip: 0,
+            base: 0,
+            line: 0,
             result: None,
             globals: HashMap::default(),
+            modules: HashMap::default(),
+            locals: vec![],
+            consts: HashMap::default(),
+            hosted: HashSet::default(),
             verbose,
         }
     }
 
     pub fn run(&mut self, chunk: Chunk) -> Result<(), Error> {
         // Execute a bytecode chunk
-        self.chunk = chunk;
-        for (col, len, instruction) in self.chunk.code.clone() {
+        self.declare(&chunk)?;
+        self.chunk = Rc::new(chunk);
+        (self.ip, self.base) = (0, 0);
+        self.proceed()
+    }
+
+    pub fn resume(&mut self, generator: &Rc<RefCell<Generator>>, sent: Value) -> Result<Option<Value>, Error> {
+        // Allows the host application to run a generator up until its next yield,
+        // giving back the value it yielded (or None once it has finished)
+        let depth = self.frames.len();
+        let (chunk, ip, base) = (self.chunk.clone(), self.ip, self.base);
+        // Running out of instructions once the generator yields hands control back here
+        self.chunk = Rc::new(Chunk::new(0));
+        self.ip = 0;
+        let result = self.wake(generator, sent, (1, 0)).and_then(|()| self.proceed());
+        if result.is_err() {
+            self.unwind(depth);
+        }
+        (self.chunk, self.ip, self.base) = (chunk, ip, base);
+        result?;
+        self.positions.pop();
+        let value = self.stack.pop().unwrap();
+        Ok(if generator.borrow().done { None } else { Some(value) })
+    }
+
+    fn proceed(&mut self) -> Result<(), Error> {
+        // Run until the end of the chunk, carrying on from any catch clauses along the way
+        loop {
+            match self.execute() {
+                Ok(()) => return Ok(()),
+                // Carry on from the nearest catch clause
+                Err(error) if !self.handlers.is_empty() => self.catch(error),
+                Err(error) => {
+                    self.thrown = None;
+                    return Err(match &self.chunk.module {
+                        // Point out errors that happened within an imported module
+                        Some(path) => Error::InModule(path.clone(), Box::new(error)),
+                        None => error,
+                    })
+                }
+            }
+        }
+    }
+
+    fn execute(&mut self) -> Result<(), Error> {
+        // Run instructions until the end of the chunk
+        while let Some((col, len, instruction)) = self.chunk.code.get(self.ip).cloned() {
+            self.line = self.chunk.lines[self.ip];
+            self.ip += 1;
             // Display stack if verbose option specified
             if self.verbose {
                 for slot in &self.stack {
@@ -56,33 +185,62 @@ impl VM {
                         // Item wasn't a boolean
                         self.stack.push(pop);
                         return Err(Error::MismatchedTypes(
-                            self.chunk.line,
+                            self.line,
                             self.get_col(0).0,
                             self.get_col(0).1,
                             "Operand must be a boolean".to_string()
                         ))
                     });
                 }
-                OpCode::OpNegate => if let Some(Value::Number(_)) = self.peek(0) {
+                OpCode::OpNegate => if self.peek(0).is_some_and(Value::is_numeric) {
                     // Negate a number
                     let operand = -self.stack.pop().unwrap();
-                    self.positions.pop();
-                    self.stack.push(operand);
-                    self.positions.push((col, len));
+                    let (c, l) = self.positions.pop().unwrap();
+                    if let Some(operand) = operand {
+                        self.stack.push(operand);
+                        self.positions.push(join((col, len), (c, l)));
+                    } else {
+                        // Negating the smallest integer overflows
+                        return Err(Error::IntegerOverflow(
+                            self.line, col, len, "'-' overflowed".to_string()
+                        ));
+                    }
                 } else {
                     // Target of negation wasn't a number
                     return Err(Error::MismatchedTypes(
-                        self.chunk.line,
+                        self.line,
                         self.get_col(0).0,
                         self.get_col(0).1,
                         "Operand must be a number".to_string()
                     ));
                 }
+                // Flip the bits of an integer
+                OpCode::OpBitNot => if let Some(Value::Integer(int)) = self.peek(0) {
+                    let flipped = !int;
+                    let (c, l) = self.positions.pop().unwrap();
+                    self.stack.pop();
+                    self.stack.push(Value::Integer(flipped));
+                    self.positions.push(join((col, len), (c, l)));
+                } else {
+                    return Err(Error::MismatchedTypes(
+                        self.line,
+                        self.get_col(0).0,
+                        self.get_col(0).1,
+                        "Operand must be an integer".to_string()
+                    ));
+                }
+                // Carry out bitwise operations on integers
+                OpCode::OpBitAnd => self.bit_op("&")?,
+                OpCode::OpBitOr => self.bit_op("|")?,
+                OpCode::OpBitXor => self.bit_op("^^")?,
+                OpCode::OpShiftLeft => self.bit_op("<<")?,
+                OpCode::OpShiftRight => self.bit_op(">>")?,
                 // Carry out various binary operations
                 OpCode::OpAdd => self.bin_op("+", col)?,
                 OpCode::OpSub => self.bin_op("-", col)?,
                 OpCode::OpMul => self.bin_op("*", col)?,
                 OpCode::OpDiv => self.bin_op("/", col)?,
+                OpCode::OpIntDiv => self.bin_op("div", col)?,
                 OpCode::OpMod => self.bin_op("%", col)?,
                 OpCode::OpPow => self.bin_op("^", col)?,
                 // Push a nil literal onto the stack
@@ -100,43 +258,51 @@ impl VM {
                     self.stack.push(Value::Boolean(false));
                     self.positions.push((col, len));
                 }
-                // Compare two values to see if they are equal
-                OpCode::OpEqual => {
+                // Compare two values to see if they are equal, instances may have an eq method for this
+                // (though comparing against nil never uses it)
+                OpCode::OpEqual => if matches!(self.peek(0), Some(Value::Nil)) || !self.overload("eq", false)? {
                     let a = self.stack.pop().unwrap();
                     let c = self.positions.pop().unwrap();
                     let b = self.stack.pop().unwrap();
                     let d = self.positions.pop().unwrap();
                     self.stack.push(Value::Boolean(a == b));
-                    self.positions.push((d.0, c.0 - d.0 + c.1));
+                    self.positions.push(join(d, c));
                 }
                 // Carry out comparison operations
                 OpCode::OpGreater => self.bin_op(">", col)?,
                 OpCode::OpLess => self.bin_op("<", col)?,
                 // Print a value
                 OpCode::OpPrint => {
-                    self.positions.pop(); 
-                    println!("{}", self.stack.pop().unwrap())
+                    let value = self.stack.pop().unwrap();
+                    let span = self.positions.pop().unwrap();
+                    println!("{}", self.stringify(&value, span)?)
                 }
                 // Defining a global variable
-                OpCode::OpDefineGlobal(idx) => {
+                OpCode::OpDefineGlobal(idx) | OpCode::OpDefineConst(idx) => {
                     let value = self.stack.pop().unwrap();
                     self.positions.pop();
                     let id = self.chunk.constants[idx as usize].clone();
                     if let Value::String(name) = id {
-                        self.globals.insert(name, value);
+                        // Constants were recorded before the chunk started running
+                        if let OpCode::OpDefineGlobal(_) = instruction {
+                            self.check_const(&name, col, len)?;
+                        }
+                        self.namespace().insert(name, value);
                     }
                 }
                 // Updating a global variable
                 OpCode::OpSetGlobal(idx) => {
                     let id = self.chunk.constants[idx as usize].clone();
                     if let Value::String(name) = id {
-                        if let Some(_) = self.globals.get(&name) {
+                        self.check_const(&name, col, len)?;
+                        let value = self.peek(0).unwrap().to_owned();
+                        if let Some(global) = self.namespace().get_mut(&name) {
                             // Variable was found
-                            self.globals.insert(name, self.peek(0).unwrap().to_owned());
+                            *global = value;
                         } else {
                             // Variable not found
                             return Err(Error::UndefinedVariable(
-                                self.chunk.line, 
+                                self.line, 
                                 col, len, name
                             ));
                         }
@@ -146,24 +312,684 @@ impl VM {
                 OpCode::OpGetGlobal(idx) => {
                     let id = self.chunk.constants[idx as usize].clone();
                     if let Value::String(name) = id {
-                        if let Some(value) = self.globals.get(&name) {
+                        if let Some(value) = self.namespace().get(&name) {
                             // Variable was found
-                            self.stack.push(value.to_owned());
+                            let value = value.to_owned();
+                            self.stack.push(value);
                             self.positions.push((col, len));
                         } else {
                             // Variable not found
                             return Err(Error::UndefinedVariable(
-                                self.chunk.line, 
+                                self.line, 
                                 col, len, name
                             ));
                         }
                     }
                 }
+                // Move a value from the stack into a new local variable
+                OpCode::OpDefineLocal => {
+                    self.positions.pop();
+                    let value = self.stack.pop().unwrap();
+                    self.locals.push(value);
+                }
+                // Retrieving a local variable
+                OpCode::OpGetLocal(slot) => {
+                    self.stack.push(self.locals[self.base + slot as usize].clone());
+                    self.positions.push((col, len));
+                }
+                // Updating a local variable
+                OpCode::OpSetLocal(slot) => {
+                    self.locals[self.base + slot as usize] = self.peek(0).unwrap().to_owned();
+                }
+                // Discard local variables that have gone out of scope
+                OpCode::OpPopLocals(count) => {
+                    self.locals.truncate(self.locals.len() - count as usize);
+                }
+                // Check if a value is within a range
+                OpCode::OpInRange(inclusive) => {
+                    let hi = self.stack.pop().unwrap();
+                    let lo = self.stack.pop().unwrap();
+                    let value = self.stack.pop().unwrap();
+                    self.positions.truncate(self.positions.len() - 2);
+                    let numeric = value.is_numeric() && lo.is_numeric() && hi.is_numeric();
+                    let below = if inclusive { value <= hi } else { value < hi };
+                    self.stack.push(Value::Boolean(numeric && value >= lo && below));
+                }
+                // Create a range from its start, end and (optionally) step
+                OpCode::OpRange(inclusive, stepped) => {
+                    let count = 2 + stepped as usize;
+                    let bounds = self.stack.split_off(self.stack.len() - count);
+                    let spans = self.positions.split_off(self.positions.len() - count);
+                    let mut ints = vec![];
+                    for (bound, span) in bounds.iter().zip(&spans) {
+                        if let Value::Integer(int) = bound { ints.push(*int) } else {
+                            return Err(Error::MismatchedTypes(
+                                self.line, span.0, span.1, "Ranges can only be made of integers".to_string()
+                            ));
+                        }
+                    }
+                    let step = if stepped { ints[2] } else { 1 };
+                    if step == 0 {
+                        return Err(Error::ZeroStep(self.line, spans[2].0, spans[2].1));
+                    }
+                    self.stack.push(Value::Range(Range { start: ints[0], end: ints[1], step, inclusive }));
+                    self.positions.push(join(spans[0], spans[count - 1]));
+                }
+                // Check if a collection contains a value
+                OpCode::OpContains => {
+                    let collection = self.stack.pop().unwrap();
+                    let (c, l) = self.positions.pop().unwrap();
+                    let value = self.stack.pop().unwrap();
+                    let position = self.positions.pop().unwrap();
+                    let found = match (&collection, &value) {
+                        (Value::Range(range), Value::Integer(int)) => range.contains(*int),
+                        (Value::Range(_), _) => false,
+                        (Value::List(items), _) => items.borrow().contains(&value),
+                        (Value::Tuple(items), _) => items.contains(&value),
+                        (Value::Map(map), _) => map.borrow().get(&value).is_some(),
+                        (Value::String(string), Value::String(part)) => string.contains(part.as_str()),
+                        (Value::String(_), _) => return Err(Error::MismatchedTypes(
+                            self.line, position.0, position.1, 
+                            "Can only look for strings within a string".to_string()
+                        )),
+                        _ => return Err(Error::MismatchedTypes(
+                            self.line, c, l, 
+                            "Can only look within ranges, lists, tuples, maps and strings".to_string()
+                        )),
+                    };
+                    self.stack.push(Value::Boolean(found));
+                    self.positions.push(join(position, (c, l)));
+                }
+                // No arm of a match expression matched
+                OpCode::OpNoMatch => {
+                    let value = self.locals.last().unwrap().to_string();
+                    return Err(Error::NoMatchingArm(self.line, col, len, value));
+                }
+                // Jump forward through the chunk
+                OpCode::OpJump(offset) => self.ip += offset as usize,
+                // Jump forward through the chunk if the value on top of the stack is nil
+                OpCode::OpJumpIfNil(offset) => if let Some(Value::Nil) = self.peek(0) {
+                    self.ip += offset as usize;
+                }
+                // Jump forward through the chunk if the condition is false
+                OpCode::OpJumpIfFalse(offset) => match self.peek(0) {
+                    Some(Value::Boolean(false)) => self.ip += offset as usize,
+                    Some(Value::Boolean(true)) => (),
+                    _ => return Err(Error::MismatchedTypes(
+                        self.line,
+                        self.get_col(0).0,
+                        self.get_col(0).1,
+                        "Condition must be a boolean".to_string()
+                    )),
+                }
+                // Call a function with the arguments on top of the stack
+                OpCode::OpCall(argc, keywords) => {
+                    let argc = argc as usize;
+                    let names = self.keywords(keywords);
+                    let callee = self.peek(argc).unwrap().clone();
+                    let callee_pos = self.get_col(argc);
+                    let span = join(callee_pos, (col, len));
+                    let args = self.stack.split_off(self.stack.len() - argc);
+                    self.stack.pop();
+                    self.positions.truncate(self.stack.len());
+                    match callee {
+                        Value::Function(function) => {
+                            self.call(&function, args, &names, 0, span)?;
+                        }
+                        // Calling a struct creates an instance, taking the fields in order (or by name)
+                        Value::Struct(kind) => {
+                            let fields = self.bind(&kind.fields, kind.fields.len(), false, args, &names, 0, span)?;
+                            let instance = Instance { kind, fields };
+                            self.stack.push(Value::Instance(Rc::new(RefCell::new(instance))));
+                            self.positions.push(span);
+                        }
+                        _ => return Err(Error::MismatchedTypes(
+                            self.line, callee_pos.0, callee_pos.1, 
+                            "Can only call functions".to_string()
+                        )),
+                    }
+                }
+                // Call a method on the object below the arguments on top of the stack
+                OpCode::OpInvoke(idx, argc, keywords) => {
+                    let argc = argc as usize;
+                    let names = self.keywords(keywords);
+                    let name = self.chunk.constants[idx as usize].to_string();
+                    let object = self.peek(argc).unwrap().clone();
+                    let span = join(self.get_col(argc), (col, len));
+                    if let Value::Generator(generator) = &object {
+                        // Generators are resumed through their own methods
+                        let mut args = self.stack.split_off(self.stack.len() - argc - 1);
+                        self.positions.truncate(self.stack.len());
+                        let expected = match name.as_str() {
+                            "next" => 0,
+                            "send" => 1,
+                            _ => return Err(Error::UndefinedProperty(self.line, span.0, span.1, name)),
+                        };
+                        if argc != expected || !names.is_empty() {
+                            return Err(Error::WrongArgumentCount(
+                                self.line, span.0, span.1, expected.to_string(), argc
+                            ));
+                        }
+                        let sent = if argc == 1 { args.pop().unwrap() } else { Value::Nil };
+                        self.wake(generator, sent, span)?;
+                        continue;
+                    }
+                    if methods::kind(&object).is_some() {
+                        // Values that aren't instances have built-in methods
+                        let args = self.stack.split_off(self.stack.len() - argc);
+                        self.stack.pop();
+                        self.positions.truncate(self.stack.len());
+                        if let Some(keyword) = names.first() {
+                            return Err(Error::UnknownKeyword(self.line, span.0, span.1, keyword.clone()));
+                        }
+                        let line = self.line;
+                        let show = &mut |value: &Value| self.stringify(value, span);
+                        let result = methods::call(object, &name, args, line, span, show)?;
+                        self.stack.push(result);
+                        self.positions.push(span);
+                        continue;
+                    }
+                    if let Value::Enum(kind) = &object {
+                        // Calling a variant of an enum creates a value carrying the arguments
+                        let args = self.stack.split_off(self.stack.len() - argc);
+                        self.stack.pop();
+                        self.positions.truncate(self.stack.len());
+                        if let Some(keyword) = names.first() {
+                            return Err(Error::UnknownKeyword(self.line, span.0, span.1, keyword.clone()));
+                        }
+                        let count = match kind.variant(&name) {
+                            Some(tag) => kind.variants[tag].1,
+                            None => return Err(Error::UndefinedProperty(self.line, span.0, span.1, name)),
+                        };
+                        let value = kind.create(&name, args).ok_or_else(||
+                            Error::WrongArgumentCount(self.line, span.0, span.1, count.to_string(), argc)
+                        )?;
+                        self.stack.push(value);
+                        self.positions.push(span);
+                        continue;
+                    }
+                    let (function, receiver) = match &object {
+                        Value::Instance(instance) => {
+                            let instance = instance.borrow();
+                            match instance.field(&name) {
+                                // A function stored in a field is called without self
+                                Some(field) => (instance.fields[field].clone(), false),
+                                None => match instance.kind.methods.get(&name) {
+                                    Some(method) => (Value::Function(method.clone()), true),
+                                    None => return Err(Error::UndefinedProperty(
+                                        self.line, span.0, span.1, name
+                                    )),
+                                },
+                            }
+                        }
+                        // Functions can be called from within a module
+                        Value::Module(module) => match self.modules[&module.path].get(&name) {
+                            Some(value) => (value.clone(), false),
+                            None => return Err(Error::UndefinedProperty(
+                                self.line, span.0, span.1, name
+                            )),
+                        },
+                        // Methods can be called directly on the struct itself
+                        Value::Struct(kind) => match kind.methods.get(&name) {
+                            Some(method) => (Value::Function(method.clone()), false),
+                            None => return Err(Error::UndefinedProperty(
+                                self.line, span.0, span.1, name
+                            )),
+                        },
+                        _ => return Err(Error::MismatchedTypes(
+                            self.line, span.0, span.1, "Only instances have properties".to_string()
+                        )),
+                    };
+                    // Either the object is passed in as self, or it is discarded
+                    let count = if receiver { argc + 1 } else { argc };
+                    let args = self.stack.split_off(self.stack.len() - count);
+                    if !receiver { self.stack.pop(); }
+                    self.positions.truncate(self.stack.len());
+                    if let Value::Function(function) = function {
+                        let hidden = if receiver { 1 } else { 0 };
+                        self.call(&function, args, &names, hidden, span)?;
+                    } else {
+                        return Err(Error::MismatchedTypes(
+                            self.line, span.0, span.1, "Can only call functions".to_string()
+                        ));
+                    }
+                }
+                // Retrieve a field (or method) from an object
+                OpCode::OpGetProperty(idx) => {
+                    let name = self.chunk.constants[idx as usize].to_string();
+                    let object = self.stack.pop().unwrap();
+                    let span = join(self.positions.pop().unwrap(), (col, len));
+                    let value = match &object {
+                        Value::Instance(instance) => {
+                            let instance = instance.borrow();
+                            match instance.field(&name) {
+                                Some(field) => Some(instance.fields[field].clone()),
+                                None => instance.kind.methods.get(&name).cloned().map(Value::Function),
+                            }
+                        }
+                        Value::Struct(kind) => kind.methods.get(&name).cloned().map(Value::Function),
+                        Value::Module(module) => self.modules[&module.path].get(&name).cloned(),
+                        // Variants that don't carry anything are values by themselves
+                        Value::Enum(kind) => match kind.variant(&name).map(|tag| kind.variants[tag].1) {
+                            Some(count) if count != 0 => return Err(Error::WrongArgumentCount(
+                                self.line, span.0, span.1, count.to_string(), 0
+                            )),
+                            _ => kind.create(&name, vec![]),
+                        },
+                        Value::Generator(generator) if name == "done" => 
+                            Some(Value::Boolean(generator.borrow().done)),
+                        Value::Generator(_) => None,
+                        _ => return Err(Error::MismatchedTypes(
+                            self.line, span.0, span.1, "Only instances have properties".to_string()
+                        )),
+                    };
+                    if let Some(value) = value {
+                        self.stack.push(value);
+                        self.positions.push(span);
+                    } else {
+                        return Err(Error::UndefinedProperty(self.line, col, len, name));
+                    }
+                }
+                // Update a field of an object
+                OpCode::OpSetProperty(idx) => {
+                    let name = self.chunk.constants[idx as usize].to_string();
+                    let value = self.stack.pop().unwrap();
+                    let value_pos = self.positions.pop().unwrap();
+                    let object = self.stack.pop().unwrap();
+                    let object_pos = self.positions.pop().unwrap();
+                    if let Value::Instance(instance) = object {
+                        let mut instance = instance.borrow_mut();
+                        if let Some(field) = instance.field(&name) {
+                            instance.fields[field] = value.clone();
+                        } else {
+                            return Err(Error::UndefinedProperty(self.line, col, len, name));
+                        }
+                    } else {
+                        return Err(Error::MismatchedTypes(
+                            self.line, object_pos.0, object_pos.1, 
+                            "Only instances have fields".to_string()
+                        ));
+                    }
+                    self.stack.push(value);
+                    self.positions.push(join(object_pos, value_pos));
+                }
+                // Run a module the first time it is imported, giving back the module
+                OpCode::OpImport(idx) => {
+                    let body = if let Value::Function(body) = &self.chunk.constants[idx as usize] {
+                        body.clone()
+                    } else {
+                        unreachable!()
+                    };
+                    let path = body.chunk.module.clone().unwrap();
+                    if let Entry::Vacant(entry) = self.modules.entry(path.clone()) {
+                        entry.insert(HashMap::new());
+                        self.declare(&body.chunk)?;
+                        self.call(&body, vec![], &[], 0, (col, len))?;
+                    } else {
+                        // The module has already been run
+                        let module = Module { name: body.name.clone(), path };
+                        self.stack.push(Value::Module(Rc::new(module)));
+                        self.positions.push((col, len));
+                    }
+                }
+                // Collect values from the top of the stack into a tuple or list
+                OpCode::OpTuple(count) | OpCode::OpList(count) => {
+                    let items = self.stack.split_off(self.stack.len() - count as usize);
+                    self.positions.truncate(self.stack.len());
+                    self.stack.push(if let OpCode::OpTuple(_) = instruction {
+                        Value::Tuple(Rc::new(items))
+                    } else {
+                        Value::List(Rc::new(RefCell::new(items)))
+                    });
+                    self.positions.push((col, len));
+                }
+                // Collect keys and values from the top of the stack into a map
+                OpCode::OpMap(count) => {
+                    let items = self.stack.split_off(self.stack.len() - 2 * count as usize);
+                    self.positions.truncate(self.stack.len());
+                    let mut map = Map::default();
+                    let mut items = items.into_iter();
+                    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                        map.insert(key, value);
+                    }
+                    self.stack.push(Value::Map(Rc::new(RefCell::new(map))));
+                    self.positions.push((col, len));
+                }
+                // Swap a piped value with what it is passed to, making it the first argument
+                OpCode::OpPipe(argc) => {
+                    let at = self.stack.len() - argc as usize - 2;
+                    self.stack.swap(at, at + 1);
+                    self.positions.swap(at, at + 1);
+                }
+                // Get an item (or slice) of a value
+                OpCode::OpIndex => {
+                    if let Some(special) = self.special(1, "index") {
+                        // Instances can be indexed through their own method
+                        let span = join(self.get_col(1), (col, len));
+                        let args = self.stack.split_off(self.stack.len() - 2);
+                        self.positions.truncate(self.stack.len());
+                        self.dispatch(special, "index", args, span)?;
+                        continue;
+                    }
+                    let index = self.stack.pop().unwrap();
+                    let index_pos = self.positions.pop().unwrap();
+                    let object = self.stack.pop().unwrap();
+                    let object_pos = self.positions.pop().unwrap();
+                    let item = self.index(&object, object_pos, &index, index_pos)?;
+                    self.stack.push(item);
+                    self.positions.push(join(object_pos, (col, len)));
+                }
+                // Change an item of a list or map
+                OpCode::OpSetIndex => {
+                    let value = self.stack.pop().unwrap();
+                    let value_pos = self.positions.pop().unwrap();
+                    let index = self.stack.pop().unwrap();
+                    let index_pos = self.positions.pop().unwrap();
+                    let object = self.stack.pop().unwrap();
+                    let object_pos = self.positions.pop().unwrap();
+                    match &object {
+                        Value::List(items) => {
+                            let mut items = items.borrow_mut();
+                            let length = items.len();
+                            match index {
+                                Value::Integer(int) if int >= 0 && (int as u64) < length as u64 => 
+                                    items[int as usize] = value.clone(),
+                                Value::Integer(_) => return Err(Error::IndexOutOfRange(
+                                    self.line, index_pos.0, index_pos.1, index.to_string(), length
+                                )),
+                                _ => return Err(Error::MismatchedTypes(
+                                    self.line, index_pos.0, index_pos.1, 
+                                    "Can only change one item of a list at a time".to_string()
+                                )),
+                            }
+                        }
+                        Value::Map(map) => map.borrow_mut().insert(index, value.clone()),
+                        _ => return Err(Error::MismatchedTypes(
+                            self.line, object_pos.0, object_pos.1, 
+                            "Can only change the items of lists and maps".to_string()
+                        )),
+                    }
+                    self.stack.push(value);
+                    self.positions.push(join(object_pos, value_pos));
+                }
+                // Start looping over the value on top of the stack
+                OpCode::OpIter(pairs) => {
+                    let value = self.stack.pop().unwrap();
+                    let source = match value {
+                        Value::List(items) => Some(Source::List(items)),
+                        Value::Tuple(items) => Some(Source::Tuple(items)),
+                        Value::Map(map) => Some(Source::Map(map)),
+                        Value::String(string) => Some(Source::String(string, 0)),
+                        Value::Range(range) if !pairs => Some(Source::Range(range)),
+                        Value::Generator(generator) if !pairs => Some(Source::Generator(generator)),
+                        // Instances can be looped over if they have a next method
+                        Value::Instance(ref instance) if !pairs && 
+                            instance.borrow().kind.methods.contains_key("next") => 
+                            Some(Source::Object(value)),
+                        _ => None,
+                    };
+                    if let Some(source) = source {
+                        let iter = Iter { source, position: 0, pairs };
+                        self.stack.push(Value::Iterator(Rc::new(RefCell::new(iter))));
+                    } else {
+                        return Err(Error::MismatchedTypes(
+                            self.line, col, len, if pairs {
+                                "Can only loop over the index and value of lists, tuples, maps and strings"
+                            } else {
+                                "Can only loop over lists, tuples, maps, strings, ranges, generators and iterators"
+                            }.to_string()
+                        ));
+                    }
+                }
+                // Move to the next value of the iterator on top of the stack
+                OpCode::OpNext(offset) => {
+                    let iter = if let Some(Value::Iterator(iter)) = self.peek(0) { iter.clone() } else {
+                        unreachable!()
+                    };
+                    let step = iter.borrow_mut().step();
+                    match step {
+                        Step::Done => self.ip += offset as usize,
+                        Step::Value(value) => {
+                            self.stack.push(value);
+                            self.positions.push((col, len));
+                            // Only values from a next method need checking
+                            self.ip += 1;
+                        }
+                        Step::Pair(key, value) => {
+                            self.stack.push(value);
+                            self.stack.push(key);
+                            self.positions.push((col, len));
+                            self.positions.push((col, len));
+                            self.ip += 1;
+                        }
+                        Step::Call(object) => {
+                            let method = if let Value::Instance(instance) = &object {
+                                instance.borrow().kind.methods["next"].clone()
+                            } else {
+                                unreachable!()
+                            };
+                            self.call(&method, vec![object], &[], 1, (col, len))?;
+                        }
+                        Step::Resume(generator) => self.wake(&generator, Value::Nil, (col, len))?,
+                    }
+                }
+                // Finish the loop if a next method gave back nil, or a generator has finished
+                OpCode::OpCheckNext(offset) => {
+                    let finished = match self.peek(1) {
+                        Some(Value::Iterator(iter)) => match &iter.borrow().source {
+                            Source::Generator(generator) => generator.borrow().done,
+                            _ => matches!(self.peek(0), Some(Value::Nil)),
+                        },
+                        _ => unreachable!(),
+                    };
+                    if finished {
+                        self.stack.pop();
+                        self.positions.pop();
+                        self.ip += offset as usize;
+                    }
+                }
+                // Jump back through the chunk
+                OpCode::OpLoop(offset) => self.ip -= offset as usize,
+                // Spread a tuple or list out onto the stack, with the first value on top
+                OpCode::OpUnpack(count, rest) => {
+                    let count = count as usize;
+                    let value = self.stack.pop().unwrap();
+                    let position = self.positions.pop().unwrap();
+                    let mut items = match &value {
+                        Value::Tuple(items) => items.to_vec(),
+                        Value::List(items) => items.borrow().clone(),
+                        _ => return Err(Error::MismatchedTypes(
+                            self.line, position.0, position.1, 
+                            "Can only unpack tuples and lists".to_string()
+                        )),
+                    };
+                    let fits = if rest { items.len() + 1 >= count } else { items.len() == count };
+                    if !fits {
+                        let expected = if rest { format!("at least {}", count - 1) } else { count.to_string() };
+                        return Err(Error::UnpackMismatch(self.line, col, len, expected, items.len()));
+                    }
+                    if rest {
+                        // The remaining values are kept together, as the same kind of sequence
+                        let remaining = items.split_off(count - 1);
+                        self.stack.push(match value {
+                            Value::Tuple(_) => Value::Tuple(Rc::new(remaining)),
+                            _ => Value::List(Rc::new(RefCell::new(remaining))),
+                        });
+                        self.positions.push((col, len));
+                    }
+                    for item in items.into_iter().rev() {
+                        self.stack.push(item);
+                        self.positions.push((col, len));
+                    }
+                }
+                // Test whether a value is a variant of an enum, giving back what it carries
+                OpCode::OpMatchVariant(idx, count) => {
+                    let name = self.chunk.constants[idx as usize].to_string();
+                    let count = count as usize;
+                    let kind = self.stack.pop().unwrap();
+                    let position = self.positions.pop().unwrap();
+                    let value = self.stack.pop().unwrap();
+                    self.positions.pop();
+                    let kind = if let Value::Enum(kind) = kind { kind } else {
+                        return Err(Error::MismatchedTypes(
+                            self.line, position.0, position.1, 
+                            "Can only match variants of enums".to_string()
+                        ));
+                    };
+                    let tag = match kind.variant(&name) {
+                        Some(tag) => tag,
+                        None => return Err(Error::UndefinedProperty(self.line, col, len, name)),
+                    };
+                    let values = match &value {
+                        Value::Variant(variant) if Rc::ptr_eq(&variant.kind, &kind) && variant.tag == tag => 
+                            Some(variant.values.clone()),
+                        _ => None,
+                    };
+                    if let Some(values) = values.as_ref().filter(|v| count != 0 && v.len() != count) {
+                        return Err(Error::UnpackMismatch(self.line, col, len, count.to_string(), values.len()));
+                    }
+                    self.stack.push(Value::Boolean(values.is_some()));
+                    self.positions.push((col, len));
+                    // The values are left for the arm even without a match, to keep its locals in line
+                    let values = values.unwrap_or_else(|| vec![Value::Nil; count]);
+                    for value in values.into_iter().take(count).rev() {
+                        self.stack.push(value);
+                        self.positions.push((col, len));
+                    }
+                }
+                // Start a try block, remembering where its catch and finally clauses are
+                OpCode::OpTry(catch, finally) => self.handlers.push(Handler {
+                    chunk: self.chunk.clone(),
+                    ip: self.ip + catch as usize,
+                    finally: self.ip + finally as usize,
+                    base: self.base,
+                    frames: self.frames.len(),
+                    height: self.stack.len(),
+                    locals: self.locals.len(),
+                }),
+                // Leave a try block without any errors
+                OpCode::OpEndTry => {
+                    self.handlers.pop();
+                }
+                // Throw the value on top of the stack
+                OpCode::OpThrow => return Err(self.throw(None)),
+                // Keep the error that was just caught pending, along with where it was raised
+                OpCode::OpPending => {
+                    let (line, col, len) = self.raised;
+                    let pending = [PENDING_THROW, line as i64, col as i64, len as i64];
+                    self.stack.push(Value::Tuple(Rc::new(pending.map(Value::Integer).to_vec())));
+                    self.positions.push((col, len));
+                }
+                // Carry on with whatever was pending when a finally clause was entered
+                OpCode::OpFinally => {
+                    self.positions.pop();
+                    match self.stack.pop().unwrap() {
+                        // Errors are raised again where they first happened, not at the finally clause
+                        Value::Tuple(pending) => {
+                            let at = match pending[..] {
+                                [_, Value::Integer(line), Value::Integer(col), Value::Integer(len)] =>
+                                    (line as usize, col as usize, len as usize),
+                                _ => unreachable!(),
+                            };
+                            return Err(self.throw(Some(at)));
+                        }
+                        Value::Integer(PENDING_RETURN) => self.leave(),
+                        _ => {
+                            self.stack.pop();
+                            self.positions.pop();
+                        }
+                    }
+                }
+                // Make sure a value the compiler couldn't know the type of fits where it is going
+                OpCode::OpCheckType(idx) => {
+                    let kind = &self.chunk.types[idx as usize];
+                    let value = self.peek(0).unwrap();
+                    if !value.is(kind) {
+                        let (c, l) = self.get_col(0);
+                        return Err(Error::MismatchedTypes(
+                            self.line, c, l,
+                            format!("Expected {} but found {}", kind, value.kind())
+                        ));
+                    }
+                }
+                // Fail an assertion, showing the condition along with the values of its operands
+                OpCode::OpAssert(idx, count, message) => {
+                    let message = if message {
+                        self.positions.pop();
+                        Some(self.stack.pop().unwrap())
+                    } else {
+                        None
+                    };
+                    let operands = self.stack.split_off(self.stack.len() - count as usize);
+                    let positions = self.positions.split_off(self.positions.len() - count as usize);
+                    let source = self.chunk.constants[idx as usize].to_string();
+                    let values: Vec<String> = operands.iter().zip(positions).filter_map(|(value, (c, l))| {
+                        // Operands are found within the condition by their position on the line
+                        let text: String = source.chars().skip(c.checked_sub(col)?).take(l).collect();
+                        let shown = match value {
+                            Value::String(s) => format!("\"{}\"", s),
+                            value => value.to_string(),
+                        };
+                        // Literals already show their own value
+                        if text.is_empty() || text == shown { return None }
+                        Some(format!("{} was {}", text, shown))
+                    }).collect();
+                    let mut description = source.clone();
+                    if !values.is_empty() {
+                        description = format!("{} ({})", description, values.join(", "));
+                    }
+                    if let Some(message) = message {
+                        description = format!("{}: {}", message, description);
+                    }
+                    return Err(Error::AssertionFailed(self.line, col, len, description));
+                }
+                // Pause the running generator, handing a value back to whatever resumed it
+                OpCode::OpYield => {
+                    let value = self.stack.pop().unwrap();
+                    self.positions.pop();
+                    let depth = self.frames.len();
+                    let frame = self.frames.pop().unwrap();
+                    let generator = frame.generator.clone().unwrap();
+                    let mut state = generator.borrow_mut();
+                    state.locals = self.locals.split_off(self.base);
+                    let stack = self.stack.split_off(frame.height);
+                    let positions = self.positions.split_off(frame.height);
+                    state.stack = stack.into_iter().zip(positions).collect();
+                    // Try blocks within the generator are put aside until it is resumed
+                    let inner = self.handlers.iter().position(|h| h.frames >= depth).unwrap_or(self.handlers.len());
+                    state.handlers = self.handlers.split_off(inner)
+                        .into_iter()
+                        .map(|h| (h.ip, h.finally, h.height - frame.height, h.locals - self.base))
+                        .collect();
+                    state.ip = self.ip;
+                    state.running = false;
+                    self.chunk = frame.chunk;
+                    self.ip = frame.ip;
+                    self.base = frame.base;
+                    self.stack.push(value);
+                    self.positions.push(frame.span);
+                }
+                // Copy the value on top of the stack
+                OpCode::OpDup => {
+                    let position = self.get_col(0);
+                    self.stack.push(self.peek(0).unwrap().clone());
+                    self.positions.push(position);
+                }
+                // Copy the two values on top of the stack, keeping their order
+                OpCode::OpDupPair => {
+                    let (a, b) = (self.get_col(1), self.get_col(0));
+                    self.stack.push(self.peek(1).unwrap().clone());
+                    self.stack.push(self.peek(1).unwrap().clone());
+                    self.positions.push(a);
+                    self.positions.push(b);
+                }
                 // Discard a value from the stack
                 OpCode::OpPop => {
                     self.stack.pop();
                     self.positions.pop();
                 }
+                // Return a value from a function back to its caller
+                OpCode::OpReturn if !self.frames.is_empty() => self.leave(),
                 // Return a value from the stack
                 OpCode::OpReturn => {
                     self.positions.pop();
@@ -188,25 +1014,39 @@ impl VM {
         // Execute a binary operation
         let (a, b) = (self.peek(0), self.peek(1));
         let (c, d) = (self.get_col(0), self.get_col(1));
-        if let (Some(&Value::Number(_)), Some(&Value::Number(_))) = (a, b) {
+        if a.is_some_and(Value::is_numeric) && b.is_some_and(Value::is_numeric) {
             // Operate on numbers
             self.positions.pop();
             let b = self.stack.pop().unwrap();
             self.positions.pop();
             let a = self.stack.pop().unwrap();
-            match op {
-                "+" => self.stack.push(a + b),
-                "-" => self.stack.push(a - b),
-                "*" => self.stack.push(a * b),
-                "/" => self.stack.push(a / b),
-                "%" => self.stack.push(a % b),
-                "^" => self.stack.push(a ^ b),
-                ">" => self.stack.push(Value::Boolean(a > b)),
-                "<" => self.stack.push(Value::Boolean(a < b)),
+            let span = join(d, c);
+            // Integers can't be divided by zero
+            if let (Value::Integer(_), Value::Integer(0), "%" | "div") = (&a, &b, op) {
+                return Err(Error::DivisionByZero(self.line, span.0, span.1));
+            }
+            let result = match op {
+                "+" => a + b,
+                "-" => a - b,
+                "*" => a * b,
+                "/" => a / b,
+                "div" => a.int_div(b),
+                "%" => a % b,
+                "^" => a ^ b,
+                ">" => Some(Value::Boolean(a > b)),
+                "<" => Some(Value::Boolean(a < b)),
                 _ => unreachable!(),
+            };
+            if let Some(result) = result {
+                self.stack.push(result);
+                self.positions.push(span);
+                Ok(())
+            } else {
+                // The result was too big to fit in an integer
+                Err(Error::IntegerOverflow(
+                    self.line, span.0, span.1, format!("'{}' overflowed", op)
+                ))
             }
-            self.positions.push((d.0, c.0 - d.0 + c.1));
-            Ok(())
         } else if let (Some(&Value::String(_)), Some(&Value::String(_))) = (a, b) {
             if op == "+" {
                 // String concatenation
@@ -214,29 +1054,506 @@ impl VM {
                 let b = self.stack.pop().unwrap();
                 self.positions.pop();
                 let a = self.stack.pop().unwrap();
-                self.stack.push(a + b);
-                self.positions.push((d.0, c.0 - d.0 + c.1));
+                self.stack.push((a + b).unwrap());
+                self.positions.push(join(d, c));
                 Ok(())
             } else {
                 // Provided an impossible operation on two strings
                 Err(Error::ImpossibleOperation(
-                    self.chunk.line, 
+                    self.line, 
                     loc,
                     1,
                     op.to_string(),
                 ))
             }
         } else {
+            let numeric = b.is_some_and(Value::is_numeric);
+            // Instances can carry out operations through their special methods
+            let method = match op {
+                "+" => "add",
+                "-" => "sub",
+                "*" => "mul",
+                "<" | ">" => "lt",
+                _ => "",
+            };
+            let swapped = op == ">";
+            if self.overload(method, swapped)? { return Ok(()) }
+            // Name the special method an instance (or a value from the host) is missing
+            let owner = match self.peek(!swapped as usize) {
+                Some(Value::Instance(instance)) => Some(instance.borrow().kind.name.clone()),
+                Some(Value::Userdata(data)) => Some(data.name().to_string()),
+                _ => None,
+            };
+            if let (Some(owner), false) = (owner, method.is_empty()) {
+                let (col, len) = if swapped { c } else { d };
+                return Err(Error::MismatchedTypes(
+                    self.line, col, len,
+                    format!("{} has no '{}' method for '{}'", owner, method, op)
+                ));
+            }
             // Incorrect types provided
             Err(Error::MismatchedTypes(
-                self.chunk.line, 
-                if let Some(&Value::Number(_)) = b { c.0 } else { d.0 },
-                if let Some(&Value::Number(_)) = b { c.1 } else { d.1 },
+                self.line, 
+                if numeric { c.0 } else { d.0 },
+                if numeric { c.1 } else { d.1 },
                 "Operands must be either numbers or strings".to_string()
             ))
         }
     }
 
+    fn declare(&mut self, chunk: &Chunk) -> Result<(), Error> {
+        // Record the constants of a chunk before it runs, so nothing can override them
+        let consts = self.consts.entry(chunk.module.clone()).or_default();
+        for (name, (line, col)) in &chunk.consts {
+            // Values set by the host would be replaced without it knowing
+            if chunk.module.is_none() && self.hosted.contains(name) {
+                return Err(Error::ConstantOverride(name.to_string()));
+            }
+            // Constants can't be declared again (e.g. on a later line in the repl)
+            if let Some((decl_line, decl)) = consts.get(name) {
+                return Err(Error::ConstantAssignment(
+                    *line, *col, name.chars().count(), name.to_string(), *decl_line, *decl
+                ));
+            }
+        }
+        consts.extend(chunk.consts.clone());
+        Ok(())
+    }
+
+    fn special(&self, distance: usize, name: &str) -> Option<Special> {
+        // Find a special method of a value on the stack e.g. add, which is used for +
+        self.peek(distance).and_then(|value| Special::find(value, name))
+    }
+
+    fn overload(&mut self, name: &str, swapped: bool) -> Result<bool, Error> {
+        // Hand an operation to a special method of the value on the left, 
+        // or on the right if the operands are swapped e.g. a > b is b.lt(a)
+        let special = if let Some(special) = self.special(!swapped as usize, name) { special } else {
+            return Ok(false)
+        };
+        let span = join(self.get_col(1), self.get_col(0));
+        let mut args = self.stack.split_off(self.stack.len() - 2);
+        self.positions.truncate(self.stack.len());
+        if swapped { args.reverse(); }
+        self.dispatch(special, name, args, span)?;
+        Ok(true)
+    }
+
+    fn dispatch(&mut self, special: Special, name: &str, mut args: Vec<Value>, span: (usize, usize)) -> Result<(), Error> {
+        // Start a special method, with the value it belongs to first among the arguments
+        match special {
+            Special::Method(method) => self.call(&method, args, &[], 1, span),
+            Special::Host(data) => {
+                args.remove(0);
+                let result = data.call(name, args)
+                    .map_err(|message| Error::Thrown(self.line, span.0, span.1, message))?;
+                self.stack.push(result);
+                self.positions.push(span);
+                Ok(())
+            }
+        }
+    }
+
+    fn invoke(&mut self, special: Special, name: &str, args: Vec<Value>, span: (usize, usize)) -> Result<Value, Error> {
+        // Carry out a special method straight away, for when its result is needed part way through
+        // an instruction (try blocks around it are set aside, so errors come back out through here)
+        let depth = self.frames.len();
+        let handlers = std::mem::take(&mut self.handlers);
+        let (chunk, ip, base) = (self.chunk.clone(), self.ip, self.base);
+        // Running out of instructions once the method returns hands control back here
+        self.chunk = Rc::new(Chunk::new(0));
+        self.ip = 0;
+        let result = self.dispatch(special, name, args, span).and_then(|()| loop {
+            match self.execute() {
+                Ok(()) => break Ok(()),
+                Err(error) if !self.handlers.is_empty() => self.catch(error),
+                // Point out errors that happened within an imported module
+                Err(error) => break Err(match &self.chunk.module {
+                    Some(path) if self.chunk.module != chunk.module => Error::InModule(path.clone(), Box::new(error)),
+                    _ => error,
+                }),
+            }
+        });
+        if result.is_err() {
+            self.unwind(depth);
+        }
+        self.handlers = handlers;
+        (self.chunk, self.ip, self.base) = (chunk, ip, base);
+        result?;
+        self.positions.pop();
+        Ok(self.stack.pop().unwrap())
+    }
+
+    fn stringify(&mut self, value: &Value, span: (usize, usize)) -> Result<String, Error> {
+        // Turn a value into text, going through the to_string methods of any instances within it
+        match Special::find(value, "to_string") {
+            Some(special) => match self.invoke(special, "to_string", vec![value.clone()], span)? {
+                Value::String(text) => Ok(text),
+                other => self.stringify(&other, span),
+            },
+            None => value.show(&mut |item| self.stringify(item, span)),
+        }
+    }
+
+    fn index(
+        &self, object: &Value, object_pos: (usize, usize), index: &Value, index_pos: (usize, usize)
+    ) -> Result<Value, Error> {
+        // Look up an item of a value, or a slice of it if the index is a range
+        let slice = matches!(index, Value::Range(_));
+        match object {
+            Value::Map(map) => map.borrow().get(index).cloned().ok_or_else(|| 
+                Error::MissingKey(self.line, index_pos.0, index_pos.1, index.to_string())
+            ),
+            Value::List(items) => {
+                let mut items = self.pick(&items.borrow(), index, index_pos)?;
+                Ok(if slice { Value::List(Rc::new(RefCell::new(items))) } else { items.pop().unwrap() })
+            }
+            Value::Tuple(items) => {
+                let mut items = self.pick(items, index, index_pos)?;
+                Ok(if slice { Value::Tuple(Rc::new(items)) } else { items.pop().unwrap() })
+            }
+            // Strings are indexed by grapheme, so characters made of several parts stay whole
+            Value::String(string) => {
+                let graphemes: Vec<&str> = string.graphemes(true).collect();
+                Ok(Value::String(self.pick(&graphemes, index, index_pos)?.concat()))
+            }
+            _ => Err(Error::MismatchedTypes(
+                self.line, object_pos.0, object_pos.1, 
+                "Can only index into lists, tuples, maps and strings".to_string()
+            )),
+        }
+    }
+
+    fn pick<T: Clone>(&self, items: &[T], index: &Value, (col, len): (usize, usize)) -> Result<Vec<T>, Error> {
+        // Take the items at an index, or within a range, making sure they exist
+        let range = match index {
+            Value::Integer(int) => Range { start: *int, end: *int, step: 1, inclusive: true },
+            Value::Range(range) => *range,
+            _ => return Err(Error::MismatchedTypes(
+                self.line, col, len, "Index must be an integer or a range".to_string()
+            )),
+        };
+        // Ranges only move one way, so checking the ends checks everything in between
+        let within = |n: Option<i64>| n.is_none_or(|n| n >= 0 && (n as u64) < items.len() as u64);
+        if !within(range.nth(0)) || !within(range.len().checked_sub(1).and_then(|n| range.nth(n))) {
+            return Err(Error::IndexOutOfRange(self.line, col, len, index.to_string(), items.len()));
+        }
+        Ok((0..range.len()).filter_map(|n| range.nth(n)).map(|n| items[n as usize].clone()).collect())
+    }
+
+    fn bit_op(&mut self, op: &str) -> Result<(), Error> {
+        // Execute a bitwise operation, these only work on integers
+        let (c, d) = (self.get_col(0), self.get_col(1));
+        let (a, b) = match (self.peek(1), self.peek(0)) {
+            (Some(&Value::Integer(a)), Some(&Value::Integer(b))) => (a, b),
+            (Some(&Value::Integer(_)), _) => return Err(Error::MismatchedTypes(
+                self.line, c.0, c.1, "Operands must be integers".to_string()
+            )),
+            _ => return Err(Error::MismatchedTypes(
+                self.line, d.0, d.1, "Operands must be integers".to_string()
+            )),
+        };
+        let span = join(d, c);
+        // Shifting by a negative amount, or by the whole width of an integer, overflows
+        let shift = u32::try_from(b).ok().filter(|b| *b < 64);
+        let result = match op {
+            "&" => Some(a & b),
+            "|" => Some(a | b),
+            "^^" => Some(a ^ b),
+            "<<" => shift.map(|b| a << b),
+            ">>" => shift.map(|b| a >> b),
+            _ => unreachable!(),
+        };
+        if let Some(result) = result {
+            self.stack.truncate(self.stack.len() - 2);
+            self.positions.truncate(self.positions.len() - 2);
+            self.stack.push(Value::Integer(result));
+            self.positions.push(span);
+            Ok(())
+        } else {
+            Err(Error::IntegerOverflow(self.line, span.0, span.1, format!("'{}' overflowed", op)))
+        }
+    }
+
+    fn call(
+        &mut self, 
+        function: &Rc<Function>, 
+        args: Vec<Value>, 
+        names: &[String],
+        hidden: usize,
+        span: (usize, usize), 
+    ) -> Result<(), Error> {
+        // Enter a function, saving where to come back to
+        let values = self.bind(
+            &function.params, function.required, function.variadic, args, names, hidden, span
+        )?;
+        if function.generator {
+            // The body only starts running once the generator is resumed
+            let generator = Generator::new(function.clone(), values);
+            self.stack.push(Value::Generator(Rc::new(RefCell::new(generator))));
+            self.positions.push(span);
+            return Ok(());
+        }
+        if self.frames.len() >= FRAMES_MAX {
+            return Err(Error::StackOverflow(self.line, span.0, span.1));
+        }
+        self.frames.push(Frame {
+            chunk: std::mem::replace(&mut self.chunk, function.chunk.clone()),
+            ip: self.ip,
+            base: self.base,
+            height: self.stack.len(),
+            span,
+            generator: None,
+        });
+        self.base = self.locals.len();
+        self.locals.extend(values);
+        self.ip = 0;
+        Ok(())
+    }
+
+    fn throw(&mut self, at: Option<(usize, usize, usize)>) -> Error {
+        // Throw the value on top of the stack, giving back the error it raises
+        // (values thrown again by a finally clause keep where they were first raised)
+        let value = self.stack.pop().unwrap();
+        let (c, l) = self.positions.pop().unwrap();
+        let (line, c, l) = at.unwrap_or((self.line, c, l));
+        // Errors that are thrown again keep their original location
+        let error = match (&self.caught, &value) {
+            (Some((Value::Instance(a), error)), Value::Instance(b)) if Rc::ptr_eq(a, b) => 
+                error.clone(),
+            _ => Error::Thrown(line, c, l, value.to_string()),
+        };
+        self.thrown = Some(value);
+        error
+    }
+
+    fn leave(&mut self) {
+        // Return the value on top of the stack from the running function
+        let result = self.stack.pop().unwrap();
+        let position = self.positions.pop().unwrap();
+        let depth = self.frames.len();
+        if self.handlers.last().is_some_and(|handler| handler.frames == depth) {
+            // Finally clauses within the function run first, then return the value themselves
+            let handler = self.handlers.pop().unwrap();
+            self.locals.truncate(handler.locals);
+            self.stack.truncate(handler.height);
+            self.positions.truncate(handler.height);
+            self.stack.push(result);
+            self.positions.push(position);
+            self.stack.push(Value::Integer(PENDING_RETURN));
+            self.positions.push(position);
+            self.ip = handler.finally;
+            return;
+        }
+        let frame = self.frames.pop().unwrap();
+        if let Some(generator) = &frame.generator {
+            generator.borrow_mut().finish();
+        }
+        self.locals.truncate(self.base);
+        self.stack.truncate(frame.height);
+        // Try blocks left by returning are no longer active
+        self.handlers.retain(|handler| handler.frames < depth);
+        self.positions.truncate(frame.height);
+        self.stack.push(result);
+        self.positions.push(frame.span);
+        self.chunk = frame.chunk;
+        self.ip = frame.ip;
+        self.base = frame.base;
+    }
+
+    fn wake(&mut self, generator: &Rc<RefCell<Generator>>, sent: Value, span: (usize, usize)) -> Result<(), Error> {
+        // Carry on running a generator from where it last yielded
+        let mut state = generator.borrow_mut();
+        if state.running {
+            return Err(Error::GeneratorRunning(self.line, span.0, span.1));
+        }
+        if state.done {
+            // Finished generators have nothing more to give
+            self.stack.push(Value::Nil);
+            self.positions.push(span);
+            return Ok(());
+        }
+        if self.frames.len() >= FRAMES_MAX {
+            return Err(Error::StackOverflow(self.line, span.0, span.1));
+        }
+        self.frames.push(Frame {
+            chunk: std::mem::replace(&mut self.chunk, state.function.chunk.clone()),
+            ip: self.ip,
+            base: self.base,
+            height: self.stack.len(),
+            span,
+            generator: Some(generator.clone()),
+        });
+        self.base = self.locals.len();
+        let height = self.stack.len();
+        self.locals.append(&mut state.locals);
+        for (value, position) in state.stack.drain(..) {
+            self.stack.push(value);
+            self.positions.push(position);
+        }
+        for (ip, finally, stack, locals) in state.handlers.drain(..) {
+            self.handlers.push(Handler {
+                chunk: self.chunk.clone(),
+                ip,
+                finally,
+                base: self.base,
+                frames: self.frames.len(),
+                height: height + stack,
+                locals: self.base + locals,
+            });
+        }
+        // The value it is resumed with becomes the result of the yield it stopped at
+        if state.started {
+            self.stack.push(sent);
+            self.positions.push(span);
+        }
+        state.started = true;
+        state.running = true;
+        self.ip = state.ip;
+        Ok(())
+    }
+
+    fn unwind(&mut self, depth: usize) {
+        // Drop the frames above a depth, finishing any generators they were running
+        for frame in self.frames.drain(depth..) {
+            if let Some(generator) = frame.generator {
+                generator.borrow_mut().finish();
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn bind(
+        &self,
+        params: &[String],
+        required: usize,
+        variadic: bool,
+        mut args: Vec<Value>,
+        names: &[String],
+        hidden: usize,
+        (col, len): (usize, usize),
+    ) -> Result<Vec<Value>, Error> {
+        // Match arguments up with parameters, giving back the value of each parameter
+        // followed by whether each parameter with a default was left out
+        let fixed = params.len() - variadic as usize;
+        let named = args.split_off(args.len() - names.len());
+        // Arguments passed in implicitly (e.g. self) aren't counted
+        let got = args.len() + named.len() - hidden;
+        let expected = crate::error::arity(required.saturating_sub(hidden), fixed - hidden, variadic);
+        if args.len() > fixed && !variadic {
+            return Err(Error::WrongArgumentCount(self.line, col, len, expected, got));
+        }
+        let extra = if args.len() > fixed { args.split_off(fixed) } else { vec![] };
+        let mut slots: Vec<Option<Value>> = args.into_iter().map(Some).collect();
+        slots.resize(fixed, None);
+        for (name, value) in names.iter().zip(named) {
+            match params[..fixed].iter().position(|p| p == name) {
+                Some(i) if slots[i].is_some() => {
+                    return Err(Error::RepeatedArgument(self.line, col, len, name.clone()));
+                }
+                Some(i) => slots[i] = Some(value),
+                None => return Err(Error::UnknownKeyword(self.line, col, len, name.clone())),
+            }
+        }
+        if let Some(i) = slots[..required].iter().position(Option::is_none) {
+            return Err(if names.is_empty() {
+                Error::WrongArgumentCount(self.line, col, len, expected, got)
+            } else {
+                Error::MissingArgument(self.line, col, len, params[i].clone())
+            });
+        }
+        let flags: Vec<Value> = slots[required..].iter().map(|s| Value::Boolean(s.is_none())).collect();
+        let mut values: Vec<Value> = slots.into_iter().map(|s| s.unwrap_or(Value::Nil)).collect();
+        if variadic {
+            values.push(Value::List(Rc::new(RefCell::new(extra))));
+        }
+        values.extend(flags);
+        Ok(values)
+    }
+
+    fn keywords(&self, names: Option<u16>) -> Vec<String> {
+        // Look up the names of the keyword arguments of a call
+        match names.map(|idx| &self.chunk.constants[idx as usize]) {
+            Some(Value::Tuple(names)) => names.iter().map(Value::to_string).collect(),
+            _ => vec![],
+        }
+    }
+
+    fn catch(&mut self, error: Error) {
+        // Unwind to the innermost try block, handing the error to its catch clause
+        let handler = self.handlers.pop().unwrap();
+        let (col, len) = error.span();
+        self.raised = (error.line(), col, len);
+        let value = match self.thrown.take() {
+            Some(value) => value,
+            None => {
+                // Errors from the VM itself become instances of Error
+                let fields = vec![
+                    Value::String(error.message()),
+                    Value::Integer(error.line() as i64),
+                    Value::Integer(col as i64),
+                    Value::Integer(len as i64),
+                ];
+                let instance = Instance { kind: self.error.clone(), fields };
+                let value = Value::Instance(Rc::new(RefCell::new(instance)));
+                self.caught = Some((value.clone(), error.clone()));
+                value
+            }
+        };
+        self.unwind(handler.frames);
+        self.locals.truncate(handler.locals);
+        self.stack.truncate(handler.height);
+        self.positions.truncate(handler.height);
+        self.chunk = handler.chunk;
+        self.ip = handler.ip;
+        self.base = handler.base;
+        self.stack.push(value);
+        self.positions.push((col, len));
+    }
+
+    fn namespace(&mut self) -> &mut HashMap<String, Value> {
+        // Find the globals of the module that is currently running
+        match &self.chunk.module {
+            Some(path) => self.modules.entry(path.clone()).or_default(),
+            None => &mut self.globals,
+        }
+    }
+
+    fn check_const(&self, name: &str, col: usize, len: usize) -> Result<(), Error> {
+        // Refuse to assign to a constant (e.g. from a previous line in the repl)
+        let consts = self.consts.get(&self.chunk.module);
+        if let Some((line, decl)) = consts.and_then(|consts| consts.get(name)) {
+            Err(Error::ConstantAssignment(self.line, col, len, name.to_string(), *line, *decl))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) -> Result<(), Error> {
+        // Allows the host application to set a global variable before running
+        if self.is_const(name) {
+            Err(Error::ConstantOverride(name.to_string()))
+        } else {
+            self.hosted.insert(name.to_string());
+            self.globals.insert(name.to_string(), value);
+            Ok(())
+        }
+    }
+
+    pub fn variant(&self, kind: &str, name: &str, values: Vec<Value>) -> Option<Value> {
+        // Allows the host application to create a value of an enum declared as a global,
+        // e.g. when converting from one of its own enums
+        match self.globals.get(kind) {
+            Some(Value::Enum(kind)) => kind.create(name, values),
+            _ => None,
+        }
+    }
+
+    pub fn is_const(&self, name: &str) -> bool {
+        // Determine if a global was declared as a constant
+        self.consts.get(&None).is_some_and(|consts| consts.contains_key(name))
+    }
+
     fn peek(&self, distance: usize) -> Option<&Value> {
         // Look at the stack without popping
         self.stack.get(self.stack.len() - 1 - distance)
@@ -251,6 +1568,20 @@ impl VM {
         // Clear the VM after execution
         self.positions.clear();
         self.stack.clear();
+        self.locals.clear();
+        self.unwind(0);
+        self.handlers.clear();
+        self.caught = None;
     }
 }
 
+
+fn join(start: (usize, usize), end: (usize, usize)) -> (usize, usize) {
+    // Create a position spanning from the start of one operand to the end of another
+    if end.0 >= start.0 {
+        (start.0, end.0 - start.0 + end.1)
+    } else {
+        // The operands are on different lines
+        start
+    }
+}
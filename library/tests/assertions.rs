@@ -0,0 +1,43 @@
+// assertions.rs - Assert statements and the messages they fail with
+use psi_lang::{Compiler, Error, Lexer, VM};
+
+fn run(src: &str) -> Result<VM, Error> {
+    // Compile and run a program, giving back the VM it ran in
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile()?;
+    let mut vm = VM::new(false);
+    vm.run(compiler.chunk)?;
+    Ok(vm)
+}
+
+fn failure(src: &str) -> (usize, usize, usize, String) {
+    // Run a program that should fail an assertion, giving back where and why
+    match run(src) {
+        Err(Error::AssertionFailed(line, col, len, message)) => (line, col, len, message),
+        other => panic!("expected a failed assertion, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn passing_assertions_do_nothing() {
+    assert!(run("assert 1 < 2\nassert true, \"never shown\"\n").is_ok());
+}
+
+#[test]
+fn operands_are_shown() {
+    // Each side of a comparison that isn't a literal is shown with its value
+    let (line, col, len, message) = failure("var a = 3\nvar b = 2\nassert a < b");
+    assert_eq!((line, col, len), (3, 8, 5));
+    assert_eq!(message, "a < b (a was 3, b was 2)");
+    let (_, _, _, message) = failure("var a = 3\nassert a == 4, \"a must be 4\"");
+    assert_eq!(message, "a must be 4: a == 4 (a was 3)");
+}
+
+#[test]
+fn other_conditions() {
+    // Conditions that aren't comparisons are shown as written, membership tests like comparisons
+    let (_, _, _, message) = failure("var ready = false\nassert ready");
+    assert_eq!(message, "ready");
+    let (_, _, _, message) = failure("var hosts = [\"a\"]\nassert \"x\" in hosts");
+    assert_eq!(message, "\"x\" in hosts (hosts was [a])");
+}
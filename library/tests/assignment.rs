@@ -0,0 +1,26 @@
+// assignment.rs - Plain and compound assignment to variables
+use psi_lang::{Compiler, Error, Lexer, Value, VM};
+
+fn run(src: &str) -> Result<VM, Error> {
+    // Compile and run a program, giving back the VM it ran in
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile()?;
+    let mut vm = VM::new(false);
+    vm.run(compiler.chunk)?;
+    Ok(vm)
+}
+
+#[test]
+fn compound_operators() {
+    // Every compound operator reads the variable, operates and writes it back
+    let vm = run("var a = 10\na += 1\na -= 3\na *= 2\na %= 5\na ^= 3\nvar b = 9\nb /= 2\n").unwrap();
+    assert_eq!(vm.globals["a"], Value::Integer(1));
+    assert_eq!(vm.globals["b"], Value::Number(4.5));
+}
+
+#[test]
+fn invalid_targets() {
+    // Only a variable can be assigned to, and the error covers the whole target
+    assert!(matches!(run("var a = 1\n(a + 1) += 2"), Err(Error::InvalidAssignmentTarget(2, 1, 7))));
+    assert!(matches!(run("var a = 1\n1 = a"), Err(Error::InvalidAssignmentTarget(2, 1, 1))));
+}
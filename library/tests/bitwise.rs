@@ -0,0 +1,30 @@
+// bitwise.rs - Bitwise operators on integers and where they sit in the precedence order
+use psi_lang::{Compiler, Error, Lexer, Value, VM};
+
+fn run(src: &str) -> Result<VM, Error> {
+    // Compile and run a program, giving back the VM it ran in
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile()?;
+    let mut vm = VM::new(false);
+    vm.run(compiler.chunk)?;
+    Ok(vm)
+}
+
+#[test]
+fn precedence() {
+    // Shifts bind tighter than &, which binds tighter than ^^, then |, then comparisons
+    let vm = run("var a = 1 | 6 ^^ 3 & 1 << 1\nvar b = 6 & 2 == 2\nvar c = ~0\nvar d = -8 >> 1\n").unwrap();
+    assert_eq!(vm.globals["a"], Value::Integer(5));
+    assert_eq!(vm.globals["b"], Value::Boolean(true));
+    assert_eq!(vm.globals["c"], Value::Integer(-1));
+    assert_eq!(vm.globals["d"], Value::Integer(-4));
+}
+
+#[test]
+fn errors() {
+    // Only integers have bits to work with, and shifts can't go past the width of one
+    assert!(matches!(run("var x = 1.5 & 1"), Err(Error::MismatchedTypes(1, ..))));
+    assert!(matches!(run("var x = 1 << 64"), Err(Error::IntegerOverflow(1, ..))));
+    assert!(matches!(run("var x = 1 << -1"), Err(Error::IntegerOverflow(1, ..))));
+    assert!(run("var x = 1 << 63").is_ok());
+}
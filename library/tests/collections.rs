@@ -0,0 +1,59 @@
+// collections.rs - Tuples, lists and unpacking them into variables
+use psi_lang::{Compiler, Error, Lexer, Value, VM};
+
+fn run(src: &str) -> Result<VM, Error> {
+    // Compile and run a program, giving back the VM it ran in
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile()?;
+    let mut vm = VM::new(false);
+    vm.run(compiler.chunk)?;
+    Ok(vm)
+}
+
+#[test]
+fn multiple_returns() {
+    // A function giving back several values is unpacked by the caller
+    let src = "fn pair(n) { return n, n * 2 }\nvar (a, b) = pair(4)\nfn f() { var (x, y) = pair(1)\n x + y }\nvar c = f()\n";
+    let vm = run(src).unwrap();
+    assert_eq!(vm.globals["a"], Value::Integer(4));
+    assert_eq!(vm.globals["b"], Value::Integer(8));
+    assert_eq!(vm.globals["c"], Value::Integer(3));
+}
+
+#[test]
+fn rest_keeps_the_sequence_kind() {
+    // Left over values stay a tuple when unpacking a tuple and a list when unpacking a list
+    let vm = run("var (a, ...t) = (1, 2, 3)\nvar [b, ...l] = [1]\n").unwrap();
+    assert_eq!(vm.globals["t"].to_string(), "(2, 3)");
+    assert_eq!(vm.globals["l"].to_string(), "[]");
+    assert_eq!(vm.globals["b"], Value::Integer(1));
+}
+
+#[test]
+fn lists_are_shared() {
+    // Assigning a list doesn't copy it
+    let vm = run("var a = [1, 2]\nvar b = a\nvar (x, y) = (b, b)\n").unwrap();
+    assert_eq!(vm.globals["x"], vm.globals["a"]);
+}
+
+#[test]
+fn unpack_mismatch() {
+    // The wrong number of values is an error, whether or not there is a rest name
+    assert!(matches!(run("var (a, b) = (1, 2, 3)"), Err(Error::UnpackMismatch(1, _, _, ref n, 3)) if n == "2"));
+    assert!(matches!(run("var (a, b, ...c) = (1,)"), Err(Error::UnpackMismatch(1, _, _, ref n, 1)) if n == "at least 2"));
+    assert!(matches!(run("var (a, b) = 5"), Err(Error::MismatchedTypes(..))));
+}
+
+#[test]
+fn constants_keep_unpacked_types() {
+    // Destructured constants keep the type of each value while variables can still change type
+    let compile = |src| Compiler::new(Lexer::new(src)).compile();
+    assert!(matches!(compile("const (a, b) = (1, \"x\")\nvar c = a + b\n"), Err(Error::MismatchedTypes(2, ..))));
+    assert!(matches!(compile("const [a, ...b] = [1, 2]\nvar c = b + 1\n"), Err(Error::MismatchedTypes(2, ..))));
+    assert!(matches!(compile("fn f() { const (a, b) = (1, 2)\n a = 3 }"), Err(Error::ConstantAssignment(2, 2, 1, ..))));
+    assert!(matches!(compile("const (a, b) = (1, 2)\nb = 3\n"), Err(Error::ConstantAssignment(2, 1, 1, ..))));
+    let vm = run("const (a, b) = (1, \"x\")\nvar (c, d) = (a, b)\nc = c + 0.5\nd = nil\n").unwrap();
+    assert_eq!(vm.globals["c"], Value::Number(1.5));
+    assert_eq!(vm.globals["d"], Value::Nil);
+    assert!(vm.is_const("a"));
+}
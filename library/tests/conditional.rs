@@ -0,0 +1,33 @@
+// conditional.rs - If expressions and the ternary operator
+use psi_lang::{Compiler, Error, Lexer, Value, VM};
+
+fn run(src: &str) -> Result<VM, Error> {
+    // Compile and run a program, giving back the VM it ran in
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile()?;
+    let mut vm = VM::new(false);
+    vm.run(compiler.chunk)?;
+    Ok(vm)
+}
+
+#[test]
+fn only_chosen_branch_runs() {
+    // The branch not taken would divide by zero if it were evaluated
+    let vm = run("var a = if true then 1 else 1 div 0\nvar b = false ? 1 div 0 : 2\n").unwrap();
+    assert_eq!(vm.globals["a"], Value::Integer(1));
+    assert_eq!(vm.globals["b"], Value::Integer(2));
+}
+
+#[test]
+fn ternaries_chain_to_the_right() {
+    // a ? b : c ? d : e groups as a ? b : (c ? d : e)
+    let vm = run("var n = 2\nvar s = n == 1 ? \"one\" : n == 2 ? \"two\" : \"many\"\n").unwrap();
+    assert_eq!(vm.globals["s"], Value::String("two".to_string()));
+}
+
+#[test]
+fn else_is_required() {
+    // An if expression always has to produce a value
+    assert!(matches!(run("var x = if true then 1"), Err(Error::ExpectedToken(..))));
+    assert!(matches!(run("var x = true ? 1"), Err(Error::ExpectedToken(..))));
+}
@@ -0,0 +1,43 @@
+// consts.rs - Constants, checked by the compiler and guarded from the host
+use psi_lang::{Compiler, Error, Lexer, Value, VM};
+
+fn compile(src: &str) -> Result<Compiler<'_>, Error> {
+    // Compile a program without running it
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile()?;
+    Ok(compiler)
+}
+
+#[test]
+fn assignment_is_a_compile_error() {
+    // Both plain and compound assignment point back at the declaration
+    let error = compile("const port = 8080\n\nport = 9090").err();
+    assert!(matches!(error, Some(Error::ConstantAssignment(3, 1, 4, ref n, 1, 7)) if n == "port"));
+    let error = compile("const port = 8080\nport += 1").err();
+    assert!(matches!(error, Some(Error::ConstantAssignment(2, 1, 4, ..))));
+}
+
+#[test]
+fn host_cannot_override() {
+    // Constants are known before the script runs, either way round
+    let mut vm = VM::new(false);
+    vm.run(compile("const port = 8080").unwrap().chunk).unwrap();
+    assert!(vm.is_const("port"));
+    assert!(matches!(vm.set_global("port", Value::Integer(1)), Err(Error::ConstantOverride(_))));
+    let mut vm = VM::new(false);
+    vm.set_global("port", Value::Integer(1)).unwrap();
+    let result = vm.run(compile("const port = 8080").unwrap().chunk);
+    assert!(matches!(result, Err(Error::ConstantOverride(_))));
+    assert_eq!(vm.globals["port"], Value::Integer(1));
+}
+
+#[test]
+fn repl_lines_share_constants() {
+    // A later chunk run in the same VM can't redeclare or assign a constant
+    let mut vm = VM::new(false);
+    vm.run(compile("const port = 8080").unwrap().chunk).unwrap();
+    assert!(matches!(vm.run(compile("const port = 1").unwrap().chunk), Err(Error::ConstantAssignment(..))));
+    assert!(matches!(vm.run(compile("var port = 1").unwrap().chunk), Err(Error::ConstantAssignment(..))));
+    assert!(matches!(vm.run(compile("port = 1").unwrap().chunk), Err(Error::ConstantAssignment(..))));
+    assert_eq!(vm.globals["port"], Value::Integer(8080));
+}
@@ -0,0 +1,63 @@
+// enums.rs - Enums, matching on their variants and handing them to the host
+use psi_lang::{Compiler, Error, Lexer, Value, VM};
+
+fn run(src: &str) -> Result<VM, Error> {
+    // Compile and run a program, giving back the VM it ran in
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile()?;
+    let mut vm = VM::new(false);
+    vm.run(compiler.chunk)?;
+    Ok(vm)
+}
+
+// An enum the host application might convert script values into
+#[derive(Debug, PartialEq)]
+enum Level {
+    Info,
+    Warn(String),
+}
+
+fn convert(value: &Value) -> Option<Level> {
+    match value.variant()? {
+        ("Info", []) => Some(Level::Info),
+        ("Warn", [message]) => Some(Level::Warn(message.to_string())),
+        _ => None,
+    }
+}
+
+const LEVEL: &str = "enum Level { Info, Warn(string) }\n";
+
+#[test]
+fn host_conversion() {
+    // Variants can be read by the host and created by it to pass back in
+    let mut vm = run(&format!("{}var a = Level.Info\nvar b = Level.Warn(\"disk\")\nvar c = 3\n", LEVEL)).unwrap();
+    assert_eq!(convert(&vm.globals["a"]), Some(Level::Info));
+    assert_eq!(convert(&vm.globals["b"]), Some(Level::Warn("disk".to_string())));
+    assert_eq!(convert(&vm.globals["c"]), None);
+    let kind = match &vm.globals["Level"] {
+        Value::Enum(kind) => kind.clone(),
+        _ => panic!("expected an enum"),
+    };
+    assert!(kind.create("Warn", vec![]).is_none());
+    let made = kind.create("Warn", vec![Value::String("disk".to_string())]).unwrap();
+    assert_eq!(made, vm.globals["b"]);
+    vm.set_global("made", made).unwrap();
+}
+
+#[test]
+fn matching_binds_values() {
+    let src = format!("{}fn f(l) {{\n    match l {{ Level.Warn(m) => m, Level.Info => \"info\" }}\n}}\nvar a = f(Level.Warn(\"x\"))\nvar b = f(Level.Info)\n", LEVEL);
+    let vm = run(&src).unwrap();
+    assert_eq!(vm.globals["a"], Value::String("x".to_string()));
+    assert_eq!(vm.globals["b"], Value::String("info".to_string()));
+}
+
+#[test]
+fn errors() {
+    // Variants are checked when declared, created and matched
+    assert!(matches!(run("enum Mode { Fast, Fast }"), Err(Error::RepeatedVariant(1, 19, 4, _))));
+    assert!(matches!(run(&format!("{}Level.Warn()", LEVEL)), Err(Error::WrongArgumentCount(2, ..))));
+    assert!(matches!(run(&format!("{}Level.Error", LEVEL)), Err(Error::UndefinedProperty(2, ..))));
+    let src = format!("{}fn f(l) {{ match l {{ Level.Warn(a, b) => 1, _ => 2 }} }}\nf(Level.Warn(\"x\"))", LEVEL);
+    assert!(matches!(run(&src), Err(Error::UnpackMismatch(..))));
+}
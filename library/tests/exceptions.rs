@@ -0,0 +1,103 @@
+// exceptions.rs - Throwing, catching and the finally clauses that run on the way out
+use psi_lang::{Compiler, Error, Lexer, Value, VM};
+
+fn run(src: &str) -> Result<VM, Error> {
+    // Compile and run a program, giving back the VM it ran in
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile()?;
+    let mut vm = VM::new(false);
+    vm.run(compiler.chunk)?;
+    Ok(vm)
+}
+
+#[test]
+fn uncaught_throw() {
+    // The error points at the value that was thrown
+    let error = run("var a = 1\nthrow \"boom\"").err();
+    assert!(matches!(error, Some(Error::Thrown(2, 7, 6, ref v)) if v == "boom"));
+}
+
+#[test]
+fn runtime_errors_are_caught() {
+    // Errors from the language arrive as an Error with a message and location
+    let vm = run("var m = nil\nvar l = nil\ntry {\n    print 1 div 0\n} catch e {\n    m = e.message\n    l = e.line\n}\n").unwrap();
+    assert_eq!(vm.globals["m"], Value::String("Division by zero".to_string()));
+    assert_eq!(vm.globals["l"], Value::Integer(4));
+}
+
+#[test]
+fn finally_runs_on_return_from_try() {
+    let vm = run("
+var cleaned = false
+fn f() {
+    try {
+        return 1
+    } finally {
+        cleaned = true
+    }
+}
+var result = f()
+").unwrap();
+    assert_eq!(vm.globals["result"], Value::Integer(1));
+    assert_eq!(vm.globals["cleaned"], Value::Boolean(true));
+}
+
+#[test]
+fn finally_runs_on_return_from_catch() {
+    let vm = run("
+var steps = \"\"
+fn f() {
+    try {
+        try {
+            throw \"failed\"
+        } catch e {
+            return e
+        } finally {
+            steps += \"inner \"
+        }
+    } finally {
+        steps += \"outer\"
+    }
+}
+var result = f()
+").unwrap();
+    assert_eq!(vm.globals["result"], Value::String("failed".to_string()));
+    assert_eq!(vm.globals["steps"], Value::String("inner outer".to_string()));
+}
+
+#[test]
+fn return_from_finally_replaces_throw() {
+    // Whatever was pending is dropped when the finally clause returns itself
+    let vm = run("fn f() {\n    try { throw 1 } finally { return 2 }\n}\nvar result = f()\n").unwrap();
+    assert_eq!(vm.globals["result"], Value::Integer(2));
+}
+
+#[test]
+fn finally_runs_on_return_from_loop() {
+    let vm = run("
+var cleaned = false
+fn first(items) {
+    try {
+        for item in items {
+            return item
+        }
+    } finally {
+        cleaned = true
+    }
+}
+var result = first([3, 4])
+").unwrap();
+    assert_eq!(vm.globals["result"], Value::Integer(3));
+    assert_eq!(vm.globals["cleaned"], Value::Boolean(true));
+}
+
+#[test]
+fn finally_keeps_where_errors_were_raised() {
+    // Errors passing through a finally clause are raised again where they first happened
+    let error = run("try {\n    throw \"boom\"\n} finally {\n    print 1\n}\n").err();
+    assert!(matches!(error, Some(Error::Thrown(2, 11, 6, ref v)) if v == "boom"));
+    let error = run("fn f() {\n    try { throw 1 } finally { print 2 }\n}\ntry {\n    f()\n} finally {\n    print 3\n}\n").err();
+    assert!(matches!(error, Some(Error::Thrown(2, 17, 1, ref v)) if v == "1"));
+    let error = run("try {\n    print 1 div 0\n} finally {\n    print 2\n}\n").err();
+    assert!(matches!(error, Some(Error::DivisionByZero(2, ..))));
+}
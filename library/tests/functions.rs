@@ -0,0 +1,69 @@
+// functions.rs - Functions, calls and the types checked before anything runs
+use psi_lang::{Compiler, Error, Lexer, Value, VM};
+
+fn run(src: &str) -> Result<VM, Error> {
+    // Compile and run a program, giving back the VM it ran in
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile()?;
+    let mut vm = VM::new(false);
+    vm.run(compiler.chunk)?;
+    Ok(vm)
+}
+
+#[test]
+fn calls_and_recursion() {
+    // Locals, implicit returns and a function calling itself
+    let src = "fn fact(n: int) -> int {\n    if n < 2 then 1 else n * fact(n - 1)\n}\nvar x = fact(10)\n";
+    assert_eq!(run(src).unwrap().globals["x"], Value::Integer(3628800));
+}
+
+#[test]
+fn annotations_are_checked() {
+    // Mismatches are found at compile time, so nothing runs
+    assert!(matches!(run("var a: int = \"30s\""), Err(Error::MismatchedTypes(1, 14, 5, _))));
+    assert!(matches!(run("fn f(x: int) -> int { x }\nf(\"1\")"), Err(Error::MismatchedTypes(2, 3, 3, _))));
+    assert!(matches!(run("fn f() -> string { 1 }"), Err(Error::MismatchedTypes(..))));
+    assert!(matches!(run("var a: integer = 1"), Err(Error::UnknownType(1, 8, 7, _))));
+}
+
+#[test]
+fn unannotated_values_can_change_type() {
+    // Without an annotation a variable is free to hold anything
+    let vm = run("var total = 0\ntotal = total + 0.5\nvar a = 5\na /= 2\nvar x = 1\nx = nil\n").unwrap();
+    assert_eq!(vm.globals["total"], Value::Number(0.5));
+    assert_eq!(vm.globals["a"], Value::Number(2.5));
+    assert_eq!(vm.globals["x"], Value::Nil);
+}
+
+#[test]
+fn annotated_variables_keep_their_type() {
+    // Plain and compound assignment are checked the same way, at compile time when possible
+    assert!(matches!(run("var a: int = 5\na = 2.5"), Err(Error::MismatchedTypes(2, 5, 3, _))));
+    assert!(matches!(run("var a: int = 5\na /= 2"), Err(Error::MismatchedTypes(2, 1, 1, _))));
+    // Otherwise once the value is known, before it is stored
+    let src = "fn id(v) { v }\nvar a: int = 5\na = id(\"s\")";
+    assert!(matches!(run(src), Err(Error::MismatchedTypes(3, 5, 7, _))));
+    let src = "fn id(v) { v }\nvar a: int = 5\na += id(0.5)\n";
+    assert!(matches!(run(src), Err(Error::MismatchedTypes(3, 1, 12, _))));
+    let vm = run("fn id(v) { v }\nvar a: int = 5\na += id(1)\n").unwrap();
+    assert_eq!(vm.globals["a"], Value::Integer(6));
+}
+
+#[test]
+fn declarations_further_down_are_checked() {
+    // The whole program is checked before any code is generated
+    let src = "fn a() { b(1) }\nfn b(x: string) { x }\n";
+    assert!(matches!(run(src), Err(Error::MismatchedTypes(1, 12, 1, _))));
+    let src = "fn a() -> int { limit + 1 }\nvar limit: string = \"10\"\n";
+    assert!(matches!(run(src), Err(Error::MismatchedTypes(1, 25, 1, _))));
+    let vm = run("fn a() -> int { b(1) }\nfn b(x: int) -> int { x + 1 }\nvar r = a()\n").unwrap();
+    assert_eq!(vm.globals["r"], Value::Integer(2));
+}
+
+#[test]
+fn call_errors() {
+    // Argument counts, returning at the top level and runaway recursion
+    assert!(matches!(run("fn f(a, b) { a }\nf(1)"), Err(Error::WrongArgumentCount(2, _, _, ref n, 1)) if n == "2"));
+    assert!(matches!(run("return 3"), Err(Error::ReturnOutsideFunction(1, 1, 6))));
+    assert!(matches!(run("fn f(n) { f(n + 1) }\nf(0)"), Err(Error::StackOverflow(..))));
+}
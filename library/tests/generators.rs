@@ -0,0 +1,66 @@
+// generators.rs - Generators run from scripts and resumed by the host
+use psi_lang::{Compiler, Error, Lexer, Value, VM};
+
+fn run(src: &str) -> Result<VM, Error> {
+    // Compile and run a program, giving back the VM it ran in
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile()?;
+    let mut vm = VM::new(false);
+    vm.run(compiler.chunk)?;
+    Ok(vm)
+}
+
+#[test]
+fn host_resumes_generators() {
+    // The host gets each yielded value, sends values back in and sees when it is done
+    let mut vm = run("fn echo() {\n    var got = yield 1\n    yield got * 10\n}\nvar g = echo()\n").unwrap();
+    let generator = match &vm.globals["g"] {
+        Value::Generator(generator) => generator.clone(),
+        _ => panic!("expected a generator"),
+    };
+    assert_eq!(vm.resume(&generator, Value::Nil).unwrap(), Some(Value::Integer(1)));
+    assert_eq!(vm.resume(&generator, Value::Integer(4)).unwrap(), Some(Value::Integer(40)));
+    assert_eq!(vm.resume(&generator, Value::Nil).unwrap(), None);
+    assert_eq!(vm.resume(&generator, Value::Nil).unwrap(), None);
+}
+
+#[test]
+fn errors_finish_the_generator() {
+    // An error escaping a generator reaches the host and leaves the VM usable
+    let mut vm = run("fn bad() {\n    yield 1\n    throw \"broken\"\n}\nvar g = bad()\n").unwrap();
+    let generator = match &vm.globals["g"] {
+        Value::Generator(generator) => generator.clone(),
+        _ => panic!("expected a generator"),
+    };
+    vm.resume(&generator, Value::Nil).unwrap();
+    assert!(matches!(vm.resume(&generator, Value::Nil), Err(Error::Thrown(3, ..))));
+    assert_eq!(vm.resume(&generator, Value::Nil).unwrap(), None);
+    assert!(vm.stack.is_empty());
+}
+
+#[test]
+fn finally_runs_on_return_after_resuming() {
+    // Try blocks put aside while paused still know where their finally clause is
+    let vm = run("
+var cleaned = false
+fn g() {
+    try {
+        yield 1
+        return 2
+    } finally {
+        cleaned = true
+    }
+}
+var gen = g()
+var a = gen.next()
+var b = gen.next()
+").unwrap();
+    assert_eq!(vm.globals["a"], Value::Integer(1));
+    assert_eq!(vm.globals["cleaned"], Value::Boolean(true));
+}
+
+#[test]
+fn yield_errors() {
+    assert!(matches!(run("yield 1"), Err(Error::YieldOutsideFunction(1, 1, 5))));
+    assert!(matches!(run("var g = nil\nfn f() {\n    g.next()\n    yield 1\n}\ng = f()\ng.next()"), Err(Error::GeneratorRunning(3, ..))));
+}
@@ -0,0 +1,50 @@
+// integers.rs - Exact whole number arithmetic and where it runs out
+use psi_lang::{Compiler, Error, Lexer, Value, VM};
+
+fn run(src: &str) -> Result<VM, Error> {
+    // Compile and run a program, giving back the VM it ran in
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile()?;
+    let mut vm = VM::new(false);
+    vm.run(compiler.chunk)?;
+    Ok(vm)
+}
+
+#[test]
+fn integers_stay_exact() {
+    // Values past 2^53 would lose their last digit as floats
+    let vm = run("var a = 9007199254740993 + 2\nvar b = -7 div 2\nvar c = 7 / 2\n").unwrap();
+    assert_eq!(vm.globals["a"], Value::Integer(9007199254740995));
+    assert_eq!(vm.globals["b"], Value::Integer(-4));
+    assert_eq!(vm.globals["c"], Value::Number(3.5));
+}
+
+#[test]
+fn overflow_is_an_error() {
+    // Arithmetic past the i64 range fails instead of wrapping
+    assert!(matches!(run("var x = 9223372036854775807 + 1"), Err(Error::IntegerOverflow(1, ..))));
+    assert!(matches!(run("var x = 2 ^ 63"), Err(Error::IntegerOverflow(1, ..))));
+    assert!(matches!(run("var x = 99999999999999999999"), Err(Error::IntegerOverflow(1, 9, 20, _))));
+}
+
+#[test]
+fn remainders_round_down_like_div() {
+    // The remainder takes the sign of the divisor, so a == b * (a div b) + a % b
+    let vm = run("var a = -7 % 2\nvar b = 7 % -2\nvar c = -7 % -2\nvar d = -7.5 % 2\nvar e = (-7 div 2) * 2 + -7 % 2\n").unwrap();
+    assert_eq!(vm.globals["a"], Value::Integer(1));
+    assert_eq!(vm.globals["b"], Value::Integer(-1));
+    assert_eq!(vm.globals["c"], Value::Integer(-1));
+    assert_eq!(vm.globals["d"], Value::Number(0.5));
+    assert_eq!(vm.globals["e"], Value::Integer(-7));
+}
+
+#[test]
+fn integers_and_floats_compare_exactly() {
+    // 9007199254740993 would round to 9007199254740992 as a float
+    let vm = run("var a = 9007199254740993 == 9007199254740992.0\nvar b = 9007199254740993 > 9007199254740992.0\nvar c = 3 == 3.0\nvar d = 9223372036854775807 < 9223372036854775807.0\nvar e = 2.5 > 2\n").unwrap();
+    assert_eq!(vm.globals["a"], Value::Boolean(false));
+    assert_eq!(vm.globals["b"], Value::Boolean(true));
+    assert_eq!(vm.globals["c"], Value::Boolean(true));
+    assert_eq!(vm.globals["d"], Value::Boolean(true));
+    assert_eq!(vm.globals["e"], Value::Boolean(true));
+}
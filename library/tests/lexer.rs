@@ -0,0 +1,67 @@
+// lexer.rs - Source text the lexer has to get right, checked through the globals programs leave behind
+use psi_lang::{Compiler, Lexer, TokenKind, Value, VM};
+
+fn run(src: &str) -> VM {
+    // Lex, compile and run a program, giving back the VM it ran in
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile().unwrap();
+    let mut vm = VM::new(false);
+    vm.run(compiler.chunk).unwrap();
+    vm
+}
+
+fn text(s: &str) -> Value {
+    Value::String(s.to_string())
+}
+
+#[test]
+fn dedent_multibyte_whitespace() {
+    // Indentation of no-break and ideographic spaces used to be sliced mid-character
+    let vm = run("var a = \"\"\"\n\u{a0}\u{a0}one\n\u{a0} two\n\"\"\"\nvar b = \"\"\"\n\u{3000}x\n\u{3000}y\n\"\"\"\n");
+    assert_eq!(vm.globals["a"], text("\u{a0}one\n two"));
+    assert_eq!(vm.globals["b"], text("x\ny"));
+}
+
+#[test]
+fn dedent_mixed_tabs_and_spaces() {
+    // A tab and four spaces share no indentation, so neither line loses any
+    let vm = run("var s = \"\"\"\n\tx\n    y\n\"\"\"\n");
+    assert_eq!(vm.globals["s"], text("\tx\n    y"));
+}
+
+#[test]
+fn continued_lines() {
+    // Lines starting with an operator, and line breaks inside brackets, don't end a statement
+    let vm = run("var total = 1\n    + 2\n    * 3\nvar grouped = (1 +\n    2)\n");
+    assert_eq!(vm.globals["total"].to_string(), "7");
+    assert_eq!(vm.globals["grouped"].to_string(), "3");
+}
+
+#[test]
+fn nested_comments_and_docs() {
+    // Block comments nest, and doc comments attach to the declaration after them
+    let src = "/* outer /* inner */ still outer */\n/// First line\n/// Second line\nvar x = 1 // trailing\n";
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile().unwrap();
+    assert_eq!(compiler.chunk.docs["x"], "First line\nSecond line");
+}
+
+#[test]
+fn tokens_borrow_source() {
+    // Tokens are pulled one at a time and point straight into the source text
+    let src = "var name = \"plain\"";
+    let spans: Vec<&str> = Lexer::new(src).map(|t| &src[t.unwrap().span]).collect();
+    assert_eq!(spans[..4], ["var", "name", "=", "\"plain\""]);
+}
+
+#[test]
+fn blocks_inside_brackets() {
+    // Line breaks end statements in the body of a lambda, even when it is passed as an argument
+    let src = "fn apply(f, x) { f(x) }\nvar r = apply(fn(h) {\n    var y = h + 1\n    y * 2\n}, 3)\n";
+    let delims = Lexer::new(src).filter(|t| t.as_ref().unwrap().kind == TokenKind::Delim).count();
+    assert_eq!(delims, 4);
+    assert_eq!(run(src).globals["r"], Value::Integer(8));
+    // Line breaks in the brackets around the block still don't
+    let vm = run("var l = [fn(a) {\n    var b = a\n    b\n}\n, 2]\nvar r = l[0](\n    l[1]\n)\n");
+    assert_eq!(vm.globals["r"], Value::Integer(2));
+}
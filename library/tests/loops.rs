@@ -0,0 +1,68 @@
+// loops.rs - Maps and for loops over everything that can be iterated
+use psi_lang::{Compiler, Error, Lexer, Value, VM};
+
+fn run(src: &str) -> Result<VM, Error> {
+    // Compile and run a program, giving back the VM it ran in
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile()?;
+    let mut vm = VM::new(false);
+    vm.run(compiler.chunk)?;
+    Ok(vm)
+}
+
+#[test]
+fn maps_keep_insertion_order() {
+    // Keys come back in the order they were first given, and later duplicates replace the value
+    let vm = run("var m = {\"b\": 1, \"a\": 2, \"b\": 3}\nvar keys = \"\"\nvar total = 0\nfor k, v in m {\n    keys += k\n    total += v\n}\n").unwrap();
+    assert_eq!(vm.globals["keys"], Value::String("ba".to_string()));
+    assert_eq!(vm.globals["total"], Value::Integer(5));
+    assert_eq!(vm.globals["m"].to_string(), "{b: 3, a: 2}");
+}
+
+#[test]
+fn loops_over_each_kind() {
+    // Lists, tuples, strings and ranges can all be looped over
+    let src = "
+var n = 0
+for x in [1, 2] { n += x }
+for x in (3, 4) { n += x }
+for i, x in [10, 20] { n += i }
+var s = \"\"
+for c in \"héllo\" { s = c + s }
+for i in 0..3 { n += i }
+";
+    let vm = run(src).unwrap();
+    assert_eq!(vm.globals["n"], Value::Integer(14));
+    assert_eq!(vm.globals["s"], Value::String("olléh".to_string()));
+}
+
+#[test]
+fn loop_variables_are_scoped() {
+    // Each loop variable is a new local that goes away once the loop is done
+    let vm = run("var total = 0\nfn sum(items) {\n    var t = 0\n    for x in items { t += x }\n    t\n}\ntotal = sum([1, 2, 3])\n").unwrap();
+    assert_eq!(vm.globals["total"], Value::Integer(6));
+    assert!(run("for x in [1] { }\nprint x").is_err());
+}
+
+#[test]
+fn not_iterable() {
+    assert!(matches!(run("for x in 5 { }"), Err(Error::MismatchedTypes(1, 10, 1, _))));
+}
+
+#[test]
+fn map_keys_of_each_kind() {
+    // Integers, strings, booleans and tuples of them are hashed, anything else is searched for
+    let src = "var m = {1: \"int\", \"1\": \"string\", true: \"bool\", (1, \"a\"): \"tuple\", [1]: \"list\"}
+m[2.0] = \"float\"
+var found = (m[1.0], m[\"1\"], m[true], m[(1.0, \"a\")], m[[1]], m[2])
+var missing = (m.contains(false), m.contains((1, \"b\")), m.contains([2]), m.contains(1.5))
+var many = {}
+for i in 0..1000 { many[(i, i % 7 == 0)] = i }
+var last = many[(994, true)]
+";
+    let vm = run(src).unwrap();
+    assert_eq!(vm.globals["found"].to_string(), "(int, string, bool, tuple, list, float)");
+    assert_eq!(vm.globals["missing"].to_string(), "(false, false, false, false)");
+    assert_eq!(vm.globals["last"], Value::Integer(994));
+    assert_eq!(vm.globals["m"].to_string(), "{1: int, 1: string, true: bool, (1, a): tuple, [1]: list, 2: float}");
+}
@@ -0,0 +1,40 @@
+// matching.rs - Match expressions, their patterns and what happens when none fit
+use psi_lang::{Compiler, Error, Lexer, Value, VM};
+
+fn run(src: &str) -> Result<VM, Error> {
+    // Compile and run a program, giving back the VM it ran in
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile()?;
+    let mut vm = VM::new(false);
+    vm.run(compiler.chunk)?;
+    Ok(vm)
+}
+
+#[test]
+fn patterns() {
+    // Alternatives, inclusive and exclusive ranges, and bindings with guards
+    let src = "
+var f = match 5 { 1 | 5 => \"alt\"\n _ => \"none\" }
+var g = match 5 { 0..5 => \"excl\"\n 0..=5 => \"incl\"\n _ => \"none\" }
+var h = match 12 { n if n > 10 => n * 2\n _ => 0 }
+";
+    let vm = run(src).unwrap();
+    assert_eq!(vm.globals["f"], Value::String("alt".to_string()));
+    assert_eq!(vm.globals["g"], Value::String("incl".to_string()));
+    assert_eq!(vm.globals["h"], Value::Integer(24));
+}
+
+#[test]
+fn no_arm_matches() {
+    // Without a catch-all the match fails at runtime, pointing at the match
+    let error = run("var x = match 3 {\n 1 => 1\n 2 => 2\n}");
+    assert!(matches!(error, Err(Error::NoMatchingArm(1, 9, 5, v)) if v == "3"));
+}
+
+#[test]
+fn unreachable_arms_warn() {
+    // Arms after a catch-all can never run
+    let mut compiler = Compiler::new(Lexer::new("var x = match 3 { _ => 1\n 2 => 2 }"));
+    compiler.compile().unwrap();
+    assert!(matches!(compiler.warnings[..], [Error::UnreachableArm(2, ..)]));
+}
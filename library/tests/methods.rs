@@ -0,0 +1,76 @@
+// methods.rs - Built-in methods of strings, numbers and collections
+use psi_lang::{Compiler, Error, Lexer, Value, VM};
+
+fn run(src: &str) -> Result<VM, Error> {
+    // Compile and run a program, giving back the VM it ran in
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile()?;
+    let mut vm = VM::new(false);
+    vm.run(compiler.chunk)?;
+    Ok(vm)
+}
+
+fn text(s: &str) -> Value {
+    Value::String(s.to_string())
+}
+
+#[test]
+fn strings_count_characters() {
+    // Lengths and positions are in characters, not bytes
+    let vm = run("var n = \"añ👍🏽\".len()\nvar i = \"añ👍🏽x\".index(\"x\")\nvar parts = \"abc\".split(\"\")\n").unwrap();
+    assert_eq!(vm.globals["n"], Value::Integer(3));
+    assert_eq!(vm.globals["i"], Value::Integer(3));
+    assert_eq!(vm.globals["parts"].to_string(), "[a, b, c]");
+}
+
+#[test]
+fn collections_change_in_place() {
+    // Lists and maps are shared, so methods that change them are seen everywhere
+    let vm = run("var a = [1]\nvar b = a\nb.push(2)\nvar last = a.pop()\nvar m = {\"x\": 1}\nvar k = m.keys()\n").unwrap();
+    assert_eq!(vm.globals["a"].to_string(), "[1]");
+    assert_eq!(vm.globals["last"], Value::Integer(2));
+    assert_eq!(vm.globals["k"].to_string(), "[x]");
+    let vm = run("var e = [].pop()\nvar j = [1, 2].join(\", \")\n").unwrap();
+    assert_eq!(vm.globals["e"], Value::Nil);
+    assert_eq!(vm.globals["j"], text("1, 2"));
+}
+
+#[test]
+fn numbers_round_to_integers() {
+    let vm = run("var a = 2.5.round()\nvar b = (-2.5).floor()\nvar c = (-3).abs()\n").unwrap();
+    assert_eq!(vm.globals["a"], Value::Integer(3));
+    assert_eq!(vm.globals["b"], Value::Integer(-3));
+    assert_eq!(vm.globals["c"], Value::Integer(3));
+    assert!(matches!(run("var x = (2.0 ^ 100).floor()"), Err(Error::IntegerOverflow(..))));
+}
+
+#[test]
+fn calls_are_checked() {
+    // Known types are checked when compiling, unknown ones while running
+    assert!(matches!(run("\"a\".contains(1)"), Err(Error::MismatchedTypes(1, ..))));
+    assert!(matches!(run("var l: [int] = []\nl.push(\"a\")"), Err(Error::MismatchedTypes(2, ..))));
+    assert!(matches!(run("fn f(x) { \"abc\".contains(x) }\nf(1)"), Err(Error::MismatchedTypes(1, ..))));
+    assert!(matches!(run("\"a\".upper(1)"), Err(Error::WrongArgumentCount(1, ..))));
+    assert!(matches!(run("\"a\".shout()"), Err(Error::UndefinedProperty(1, 5, 5, _))));
+}
+
+#[test]
+fn arguments_without_methods_are_checked() {
+    // Structs and lists passed where text is expected are reported rather than crashing
+    let calls = [
+        "\"abc\".split(x)", "\"abc\".replace(x, \"b\")", "\"abc\".replace(\"b\", x)",
+        "\"abc\".starts_with(x)", "\"abc\".ends_with(x)", "\"abc\".contains(x)",
+        "\"abc\".index(x)", "[1, 2].join(x)",
+    ];
+    for call in calls {
+        for arg in ["S(1)", "[1]"] {
+            let src = format!("struct S {{ a }}\nfn f(x) {{ {} }}\nprint f({})\n", call, arg);
+            assert!(matches!(run(&src), Err(Error::MismatchedTypes(2, ..))), "{} with {}", call, arg);
+        }
+    }
+    // Lists and maps hold anything, so these only look for or add the value
+    let src = "struct S { a }\nfn f(x) { var l = [1]\nl.push(x)\n(l.len(), l.contains(x), [2].contains(x), {1: 2}.contains(x)) }\nvar a = f(S(1))\nvar b = f([1])\n";
+    let vm = run(src).unwrap();
+    assert_eq!(vm.globals["a"].to_string(), "(2, true, false, false)");
+    assert_eq!(vm.globals["b"].to_string(), "(2, true, false, false)");
+}
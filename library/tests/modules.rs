@@ -0,0 +1,51 @@
+// modules.rs - Importing modules from files next to the script
+use psi_lang::{Compiler, Error, Lexer, Value, VM};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn project(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    // Write out a directory of modules to import from
+    let dir = std::env::temp_dir().join(format!("psi-modules-{}-{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for (file, src) in files {
+        fs::write(dir.join(file), src).unwrap();
+    }
+    dir
+}
+
+fn run(dir: &Path, src: &str) -> Result<VM, Error> {
+    // Compile and run a program as if it were a file in the directory
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.file = dir.join("main.psi");
+    compiler.compile()?;
+    let mut vm = VM::new(false);
+    vm.run(compiler.chunk)?;
+    Ok(vm)
+}
+
+#[test]
+fn namespaces_and_constants() {
+    // A module's globals and constants don't clash with the script's
+    let dir = project("namespaces", &[("settings.psi", "const port = 8080\nvar hits = 0\nhits += 1\n")]);
+    let src = "import \"settings.psi\" as settings\nfrom \"settings.psi\" import port\nvar a = settings.port\nconst hits = 5\n";
+    let vm = run(&dir, src).unwrap();
+    assert_eq!(vm.globals["a"], Value::Integer(8080));
+    assert_eq!(vm.globals["port"], Value::Integer(8080));
+    assert_eq!(vm.globals["hits"], Value::Integer(5));
+    assert_eq!(vm.modules.len(), 1);
+    assert!(!vm.is_const("port"));
+}
+
+#[test]
+fn import_errors() {
+    // Missing modules, cycles, and errors inside a module name the file they're in
+    let dir = project("errors", &[
+        ("a.psi", "import \"b.psi\" as b\n"),
+        ("b.psi", "import \"a.psi\" as a\n"),
+        ("broken.psi", "var x = 1 +\n"),
+    ]);
+    assert!(matches!(run(&dir, "import \"missing.psi\" as m"), Err(Error::ModuleNotFound(1, 8, 13, _))));
+    assert!(matches!(run(&dir, "import \"a.psi\" as a"), Err(Error::InModule(..))));
+    let error = run(&dir, "import \"broken.psi\" as b").err().unwrap();
+    assert!(error.file().is_some_and(|f| f.ends_with("broken.psi")));
+}
@@ -0,0 +1,38 @@
+// optional.rs - Nil-coalescing and optional chaining
+use psi_lang::{Compiler, Error, Lexer, Value, VM};
+
+fn run(src: &str) -> Result<VM, Error> {
+    // Compile and run a program, giving back the VM it ran in
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile()?;
+    let mut vm = VM::new(false);
+    vm.run(compiler.chunk)?;
+    Ok(vm)
+}
+
+#[test]
+fn coalescing_short_circuits() {
+    // The right side only runs when the left is nil, and false isn't nil
+    let vm = run("var calls = 0\nfn f() {\n    calls += 1\n    2\n}\nvar a = 1 ?? f()\nvar b = nil ?? f()\nvar c = false ?? f()\n").unwrap();
+    assert_eq!(vm.globals["calls"], Value::Integer(1));
+    assert_eq!(vm.globals["b"], Value::Integer(2));
+    assert_eq!(vm.globals["c"], Value::Boolean(false));
+}
+
+#[test]
+fn whole_chain_is_skipped() {
+    // a?.b.c acts like a?.b?.c, including indexes and calls further along
+    let vm = run("var a\nvar x = a?.b.c\nvar y = a?[0].len()\nvar z = a?.f(1)[2]\n").unwrap();
+    assert_eq!(vm.globals["x"], Value::Nil);
+    assert_eq!(vm.globals["y"], Value::Nil);
+    assert_eq!(vm.globals["z"], Value::Nil);
+}
+
+#[test]
+fn only_the_checked_value_may_be_nil() {
+    // A nil further along the chain is still an error unless it is checked too
+    let src = "struct S { inner: any }\nvar s = S(nil)\nvar x = s?.inner.len()";
+    assert!(run(src).is_err());
+    let vm = run("struct S { inner: any }\nvar s = S(nil)\nvar x = s.inner?.len()").unwrap();
+    assert_eq!(vm.globals["x"], Value::Nil);
+}
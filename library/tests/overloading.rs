@@ -0,0 +1,128 @@
+// overloading.rs - Structs taking part in operators through special methods
+use psi_lang::{Compiler, Error, Lexer, Userdata, Value, VM};
+use std::any::Any;
+use std::rc::Rc;
+
+fn run(src: &str) -> Result<VM, Error> {
+    // Compile and run a program, giving back the VM it ran in
+    run_with(src, VM::new(false))
+}
+
+fn run_with(src: &str, mut vm: VM) -> Result<VM, Error> {
+    // Compile and run a program in a VM the host has already set up
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile()?;
+    vm.run(compiler.chunk)?;
+    Ok(vm)
+}
+
+// A duration the host hands over, which only has some of the special methods
+#[derive(Debug)]
+struct Duration(i64);
+
+impl Userdata for Duration {
+    fn name(&self) -> &str {
+        "Duration"
+    }
+
+    fn has(&self, method: &str) -> bool {
+        matches!(method, "add" | "lt" | "eq" | "index" | "to_string")
+    }
+
+    fn call(&self, method: &str, args: Vec<Value>) -> Result<Value, String> {
+        let other = match args.first() {
+            Some(Value::Userdata(other)) => other.as_any().downcast_ref::<Duration>().map(|other| other.0),
+            Some(Value::Integer(i)) => Some(*i),
+            _ => None,
+        };
+        Ok(match (method, other) {
+            ("add", Some(other)) => Value::Userdata(Rc::new(Duration(self.0 + other))),
+            ("lt", Some(other)) => Value::Boolean(self.0 < other),
+            ("eq", Some(other)) => Value::Boolean(self.0 == other),
+            ("index", _) => match &args[0] {
+                Value::String(unit) if unit == "minutes" => Value::Integer(self.0 / 60),
+                _ => return Err("Durations only have minutes".to_string()),
+            },
+            ("to_string", _) => Value::String(format!("{}s", self.0)),
+            _ => return Err(format!("Can't {} a duration with that", method)),
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+const MONEY: &str = "struct Money {
+    cents: int
+    fn add(self, other: Money) -> Money { Money(self.cents + other.cents) }
+    fn lt(self, other: Money) -> bool { self.cents < other.cents }
+    fn eq(self, other: Money) -> bool { self.cents == other.cents }
+    fn to_string(self) -> string { \"$\" + (self.cents div 100).to_string() }
+}
+";
+
+#[test]
+fn comparisons_are_derived() {
+    // > and >= swap the operands of lt, and != negates eq
+    let src = format!("{}var a = Money(100)\nvar b = Money(250)\nvar gt = b > a\nvar ge = a >= a\nvar ne = a != b\nvar sum = (a + b).cents\n", MONEY);
+    let vm = run(&src).unwrap();
+    assert_eq!(vm.globals["gt"], Value::Boolean(true));
+    assert_eq!(vm.globals["ge"], Value::Boolean(true));
+    assert_eq!(vm.globals["ne"], Value::Boolean(true));
+    assert_eq!(vm.globals["sum"], Value::Integer(350));
+}
+
+#[test]
+fn to_string_is_used() {
+    // Printing and converting an instance both go through its to_string method
+    let vm = run(&format!("{}var s = Money(250).to_string()\n", MONEY)).unwrap();
+    assert_eq!(vm.globals["s"], Value::String("$2".to_string()));
+}
+
+#[test]
+fn missing_special_method() {
+    // The error names the method that would be needed, whether found when compiling or running
+    let known = format!("{}var x = Money(1) * 2", MONEY);
+    assert!(matches!(run(&known), Err(Error::MismatchedTypes(8, _, _, ref m)) if m.contains("'mul'")));
+    let unknown = format!("{}fn f(a, b) {{ a - b }}\nf(Money(1), Money(2))", MONEY);
+    assert!(matches!(run(&unknown), Err(Error::MismatchedTypes(8, _, _, ref m)) if m.contains("'sub'")));
+}
+
+#[test]
+fn nested_values_use_to_string() {
+    // Instances within collections are shown the same way as on their own
+    let src = format!("{}var m = Money(250)\nvar l = [m].to_string()\nvar t = (m, 1).to_string()\nvar d = {{\"a\": m}}.to_string()\nvar j = [m, m].join(\" \")\n", MONEY);
+    let vm = run(&src).unwrap();
+    assert_eq!(vm.globals["l"], Value::String("[$2]".to_string()));
+    assert_eq!(vm.globals["t"], Value::String("($2, 1)".to_string()));
+    assert_eq!(vm.globals["d"], Value::String("{a: $2}".to_string()));
+    assert_eq!(vm.globals["j"], Value::String("$2 $2".to_string()));
+    // Errors within to_string come back out where it was needed, and can be caught there
+    let src = "struct Bad {\n    fn to_string(self) { throw \"no\" }\n}\nvar r = nil\ntry { r = [Bad()].to_string() } catch e { r = e }\n";
+    assert_eq!(run(src).unwrap().globals["r"], Value::String("no".to_string()));
+}
+
+#[test]
+fn host_values_use_special_methods() {
+    // Values from the host take part in operators the same way instances do
+    let mut vm = VM::new(false);
+    vm.set_global("timeout", Value::Userdata(Rc::new(Duration(90)))).unwrap();
+    let src = "var longer = timeout + 30\nvar both = (timeout + timeout) == 180\nvar shorter = timeout < 120\nvar same = timeout == 90\nvar minutes = timeout[\"minutes\"]\nvar text = [timeout].to_string()\n";
+    let vm = run_with(src, vm).unwrap();
+    assert_eq!(vm.globals["longer"].to_string(), "<Duration>");
+    assert_eq!(vm.globals["both"], Value::Boolean(true));
+    assert_eq!(vm.globals["shorter"], Value::Boolean(true));
+    assert_eq!(vm.globals["same"], Value::Boolean(true));
+    assert_eq!(vm.globals["minutes"], Value::Integer(1));
+    assert_eq!(vm.globals["text"], Value::String("[90s]".to_string()));
+    // Missing methods are named, and failures are thrown where the operator was used
+    let mut vm = VM::new(false);
+    vm.set_global("timeout", Value::Userdata(Rc::new(Duration(90)))).unwrap();
+    assert!(matches!(run_with("var x = timeout * 2", vm), Err(Error::MismatchedTypes(1, 9, 7, ref m)) if m.contains("Duration has no 'mul'")));
+    let mut vm = VM::new(false);
+    vm.set_global("timeout", Value::Userdata(Rc::new(Duration(90)))).unwrap();
+    let src = "var e = nil\ntry { timeout[\"hours\"] } catch error { e = error.message }\n";
+    let vm = run_with(src, vm).unwrap();
+    assert_eq!(vm.globals["e"], Value::String("Uncaught exception: Durations only have minutes".to_string()));
+}
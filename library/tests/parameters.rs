@@ -0,0 +1,39 @@
+// parameters.rs - Default, keyword and rest parameters, and binding arguments to them
+use psi_lang::{Compiler, Error, Lexer, Value, VM};
+
+fn run(src: &str) -> Result<VM, Error> {
+    // Compile and run a program, giving back the VM it ran in
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile()?;
+    let mut vm = VM::new(false);
+    vm.run(compiler.chunk)?;
+    Ok(vm)
+}
+
+const F: &str = "fn f(a, b = a * 2, ...rest) { (a, b, rest) }\n";
+
+#[test]
+fn defaults_are_worked_out_each_call() {
+    // Defaults can refer to earlier parameters, and aren't shared between calls
+    let src = format!("{}var x = f(1)\nvar y = f(2, b: 5)\nvar calls = 0\nfn next() {{\n calls += 1\n calls\n}}\nfn g(n = next()) {{ n }}\nvar p = g()\nvar q = g()\n", F);
+    let vm = run(&src).unwrap();
+    assert_eq!(vm.globals["x"].to_string(), "(1, 2, [])");
+    assert_eq!(vm.globals["y"].to_string(), "(2, 5, [])");
+    assert_eq!(vm.globals["p"], Value::Integer(1));
+    assert_eq!(vm.globals["q"], Value::Integer(2));
+}
+
+#[test]
+fn rest_collects_extra_arguments() {
+    let vm = run(&format!("{}var x = f(1, 2, 3, 4)\n", F)).unwrap();
+    assert_eq!(vm.globals["x"].to_string(), "(1, 2, [3, 4])");
+}
+
+#[test]
+fn binding_errors() {
+    // Keywords have to name a parameter once, and required parameters must be given
+    assert!(matches!(run(&format!("{}f(1, c: 2)", F)), Err(Error::UnknownKeyword(2, _, _, ref n)) if n == "c"));
+    assert!(matches!(run(&format!("{}f(1, a: 2)", F)), Err(Error::RepeatedArgument(2, _, _, ref n)) if n == "a"));
+    assert!(matches!(run(&format!("{}f(b: 2)", F)), Err(Error::MissingArgument(2, _, _, ref n)) if n == "a"));
+    assert!(matches!(run("fn g(a, b = 1) { a }\ng(1, 2, 3)"), Err(Error::WrongArgumentCount(2, ..))));
+}
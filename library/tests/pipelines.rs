@@ -0,0 +1,34 @@
+// pipelines.rs - Passing values along with |>
+use psi_lang::{Compiler, Error, Lexer, Value, VM};
+
+fn run(src: &str) -> Result<VM, Error> {
+    // Compile and run a program, giving back the VM it ran in
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile()?;
+    let mut vm = VM::new(false);
+    vm.run(compiler.chunk)?;
+    Ok(vm)
+}
+
+#[test]
+fn value_goes_first() {
+    // The piped value comes before any arguments given in brackets, including keywords
+    let src = "fn sub(a, b = 0) { a - b }\nvar a = 10 |> sub(3)\nvar b = 10 |> sub\nvar c = 10 |> sub(b: 4)\n";
+    let vm = run(src).unwrap();
+    assert_eq!(vm.globals["a"], Value::Integer(7));
+    assert_eq!(vm.globals["b"], Value::Integer(10));
+    assert_eq!(vm.globals["c"], Value::Integer(6));
+}
+
+#[test]
+fn chains_left_to_right() {
+    // Methods and lambdas can be piped into too
+    let vm = run("var s = \" a \" |> fn(x) { x.trim() } |> fn(x) { x + \"!\" }\n").unwrap();
+    assert_eq!(vm.globals["s"], Value::String("a!".to_string()));
+}
+
+#[test]
+fn must_pipe_into_a_call() {
+    // Anything other than something callable is an error
+    assert!(run("var x = 1 |> 2").is_err());
+}
@@ -0,0 +1,58 @@
+// ranges.rs - Range values, membership tests, indexing and slicing
+use psi_lang::{Compiler, Error, Lexer, Value, VM};
+
+fn run(src: &str) -> Result<VM, Error> {
+    // Compile and run a program, giving back the VM it ran in
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile()?;
+    let mut vm = VM::new(false);
+    vm.run(compiler.chunk)?;
+    Ok(vm)
+}
+
+#[test]
+fn steps_and_membership() {
+    // Only values the range actually lands on are within it
+    let vm = run("var a = 30 in 0..60 step 15\nvar b = 31 in 0..60 step 15\nvar c = 60 in 0..60\nvar d = 1 in 3..=1 step -1\n").unwrap();
+    assert_eq!(vm.globals["a"], Value::Boolean(true));
+    assert_eq!(vm.globals["b"], Value::Boolean(false));
+    assert_eq!(vm.globals["c"], Value::Boolean(false));
+    assert_eq!(vm.globals["d"], Value::Boolean(true));
+}
+
+#[test]
+fn slices() {
+    // Slicing keeps the kind of sequence, and a reversed range gives items in reverse
+    let vm = run("var t = (1, 2, 3, 4)[1..3]\nvar l = [1, 2, 3, 4][3..=0 step -1]\nvar e = [1, 2][1..1]\n").unwrap();
+    assert_eq!(vm.globals["t"].to_string(), "(2, 3)");
+    assert_eq!(vm.globals["l"].to_string(), "[4, 3, 2, 1]");
+    assert_eq!(vm.globals["e"].to_string(), "[]");
+}
+
+#[test]
+fn index_errors() {
+    // Errors point at the index rather than the thing being indexed
+    assert!(matches!(run("var l = [1, 2]\nprint l[2]"), Err(Error::IndexOutOfRange(2, 9, 1, _, 2))));
+    assert!(matches!(run("var l = [1, 2]\nprint l[-1]"), Err(Error::IndexOutOfRange(2, 9, _, _, 2))));
+    assert!(matches!(run("var m = {\"a\": 1}\nprint m[\"b\"]"), Err(Error::MissingKey(2, 9, 3, _))));
+    assert!(matches!(run("for i in 0..3 step 0 { }"), Err(Error::ZeroStep(..))));
+}
+
+#[test]
+fn ranges_at_the_limits() {
+    // Stepping past the largest integer ends the loop rather than overflowing
+    let vm = run("var seen = []\nfor i in 0..9223372036854775807 step 9223372036854775807 { seen.push(i) }\n").unwrap();
+    assert_eq!(vm.globals["seen"].to_string(), "[0]");
+    let src = "var seen = []\nfor i in -9223372036854775808..9223372036854775807 step 9223372036854775807 { seen.push(i) }\n";
+    let vm = run(src).unwrap();
+    assert_eq!(vm.globals["seen"].to_string(), "[-9223372036854775808, -1, 9223372036854775806]");
+    let vm = run("var a = 9223372036854775807 in -9223372036854775808..=9223372036854775807\n").unwrap();
+    assert_eq!(vm.globals["a"], Value::Boolean(true));
+    // Counting every integer gives more than an integer can hold
+    let src = "var n = (-9223372036854775808..9223372036854775807).len()";
+    assert!(matches!(run(src), Err(Error::IntegerOverflow(1, 10, 48, _))));
+    let vm = run("var n = (-9223372036854775808..-9223372036854775807).len()").unwrap();
+    assert_eq!(vm.globals["n"], Value::Integer(1));
+    // The smallest integer can only be written negated
+    assert!(matches!(run("var n = 1 -9223372036854775808"), Err(Error::IntegerOverflow(1, 12, 19, _))));
+}
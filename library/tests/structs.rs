@@ -0,0 +1,40 @@
+// structs.rs - Struct declarations, instances and their methods
+use psi_lang::{Compiler, Error, Lexer, Value, VM};
+
+fn run(src: &str) -> Result<VM, Error> {
+    // Compile and run a program, giving back the VM it ran in
+    let mut compiler = Compiler::new(Lexer::new(src));
+    compiler.compile()?;
+    let mut vm = VM::new(false);
+    vm.run(compiler.chunk)?;
+    Ok(vm)
+}
+
+const POINT: &str = "struct Point {
+    x: int
+    y: int
+    fn origin() -> Point { Point(0, 0) }
+    fn sum(self) -> int { self.x + self.y }
+}
+";
+
+#[test]
+fn fields_and_methods() {
+    // Instances are shared, so updating one through another name is seen by both
+    let src = format!("{}var p = Point(1, 2)\nvar q = p\nq.x += 10\nvar s = p.sum()\nvar o = Point.origin().sum()\n", POINT);
+    let vm = run(&src).unwrap();
+    assert_eq!(vm.globals["s"], Value::Integer(13));
+    assert_eq!(vm.globals["o"], Value::Integer(0));
+    assert_eq!(vm.globals["p"].to_string(), "Point { x: 11, y: 2 }");
+}
+
+#[test]
+fn property_errors() {
+    // Unknown fields are caught while compiling when the type is known, otherwise while running
+    let known = format!("{}var p: Point = Point(1, 2)\nprint p.z", POINT);
+    assert!(matches!(run(&known), Err(Error::UndefinedProperty(8, 9, 1, ref n)) if n == "z"));
+    let unknown = format!("{}fn f(p) {{ p.z }}\nf(Point(1, 2))", POINT);
+    assert!(matches!(run(&unknown), Err(Error::UndefinedProperty(7, 13, 1, _))));
+    let field = format!("{}var p = Point(1, \"2\")", POINT);
+    assert!(matches!(run(&field), Err(Error::MismatchedTypes(..))));
+}